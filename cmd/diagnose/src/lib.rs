@@ -57,6 +57,16 @@ struct DiagnoseArgs {
     /// suppresses automatic coredump generation
     #[clap(long, short)]
     no_dump: bool,
+
+    /// capture a window of device memory in the coredump, by name or as
+    /// address:size
+    #[clap(
+        long,
+        short,
+        multiple_occurrences = true,
+        conflicts_with = "no_dump"
+    )]
+    mmio: Vec<String>,
 }
 
 /// Error conditions that we can report, giving the user a code they can cite
@@ -90,6 +100,7 @@ fn diagnose(
     subargs: &[String],
 ) -> Result<()> {
     let subargs = DiagnoseArgs::try_parse_from(subargs)?;
+    let mmio = humility::arch::dump_mmio(hubris.chip(), &subargs.mmio)?;
 
     section("Initial Inspection");
 
@@ -347,7 +358,7 @@ fn diagnose(
 
     if !subargs.no_dump {
         section("Generating Coredump");
-        let rval = hubris.dump(core, None);
+        let rval = hubris.dump(core, None, &mmio);
         if let Err(e) = rval {
             println!("Coredump failed: {}", e);
         }
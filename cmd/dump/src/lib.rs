@@ -47,6 +47,28 @@
 //!  9 20000558 idle                 0 Healthy(Runnable)          <-
 //! ```
 //!
+//! By default, device memory is not included in a dump.  To capture small
+//! windows of device memory (e.g., to be able to see fault status or
+//! interrupt state from the dump), use `-m` (`--mmio`) to specify either a
+//! named window or a window as an address and size.  The named windows are
+//! `scb`, `nvic` and `dwt` -- and, for archives built for STM32F4 and STM32H7
+//! parts, `rcc` and `gpio`:
+//!
+//! ```console
+//! % humility dump -m scb -m nvic -m 0x40004400:0x400
+//! humility: attached via ST-Link
+//! humility: core halted
+//! humility: dumping to hubris.core.2
+//! humility: dumped 1.12MB in 24 seconds
+//! humility: core resumed
+//! % humility -d hubris.core.2 exceptions
+//! humility: attached to dump
+//! active exceptions: none
+//! pending exceptions: none
+//! fault status: CFSR = 0x00000000, HFSR = 0x00000000
+//! ...
+//! ```
+//!
 
 use anyhow::Result;
 use clap::Command as ClapCommand;
@@ -58,6 +80,10 @@ use humility_cmd::{Archive, Args, Attach, Command, Validate};
 #[derive(Parser, Debug)]
 #[clap(name = "dump", about = env!("CARGO_PKG_DESCRIPTION"))]
 struct DumpArgs {
+    /// capture a window of device memory, by name or as address:size
+    #[clap(long, short, multiple_occurrences = true)]
+    mmio: Vec<String>,
+
     dumpfile: Option<String>,
 }
 
@@ -68,11 +94,12 @@ fn dumpcmd(
     subargs: &[String],
 ) -> Result<()> {
     let subargs = DumpArgs::try_parse_from(subargs)?;
+    let mmio = humility::arch::dump_mmio(hubris.chip(), &subargs.mmio)?;
 
     let _info = core.halt()?;
     humility::msg!("core halted");
 
-    let rval = hubris.dump(core, subargs.dumpfile.as_deref());
+    let rval = hubris.dump(core, subargs.dumpfile.as_deref(), &mmio);
 
     core.run()?;
     humility::msg!("core resumed");
@@ -0,0 +1,426 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility dumpdiff`
+//!
+//! `humility dumpdiff` compares two dumps taken from the same Hubris archive,
+//! reporting any differences in task generations, task states (including
+//! fault information) and ring buffer contents.  The first dump is specified
+//! in the usual way (that is, via `-d` or `HUMILITY_DUMP`); the second dump
+//! is specified as the argument:
+//!
+//! ```console
+//! % humility -d hubris.core.0 dumpdiff hubris.core.1
+//! humility: attached to dump
+//! --- hubris.core.0
+//! +++ hubris.core.1
+//! task ping: generation 14190 -> 14193
+//! task pong: state Healthy(InRecv(None)) -> Faulted { fault: Injected(TaskId(0)), original_state: InRecv(None) }
+//! ring buffer task_pong::__RINGBUF in pong:
+//! -   57        1        1 Pong(0x1)
+//! +   57        1        2 Pong(0x1)
+//! ```
+//!
+//! Ring buffer entries are shown as `LINE GEN COUNT PAYLOAD`.  Entries are
+//! compared in order (oldest first), as with `diff`:  entries in the first
+//! dump that are not in the second are denoted with `-`, and entries in the
+//! second dump that are not in the first are denoted with `+`.
+//!
+//! To additionally compare global variables, specify them with `-V`:
+//!
+//! ```console
+//! % humility -d hubris.core.0 dumpdiff hubris.core.1 -V TICKS -V IRQ_TABLE_SIZE
+//! humility: attached to dump
+//! --- hubris.core.0
+//! +++ hubris.core.1
+//! variable TICKS:
+//! - 0x1b5ef4
+//! + 0x1b6a75
+//! ...
+//! ```
+//!
+//! If the dumps are not from the same archive, `humility dumpdiff` will
+//! fail.
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::Command as ClapCommand;
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cmd::doppel::{Ringbuf, Task, TaskDesc};
+use humility_cmd::reflect::{self, Format, Load, Value};
+use humility_cmd::{Archive, Args, Attach, Command, Validate};
+
+#[derive(Parser, Debug)]
+#[clap(name = "dumpdiff", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct DumpdiffArgs {
+    /// do not compare ring buffers
+    #[clap(long)]
+    no_ringbufs: bool,
+
+    /// global variable to additionally compare
+    #[clap(long = "variable", short = 'V', multiple_occurrences = true)]
+    variables: Vec<String>,
+
+    /// dump to compare against
+    dump: String,
+}
+
+fn read_tasks(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+) -> Result<Vec<(String, Task)>> {
+    let base = core.read_word_32(hubris.lookup_symword("TASK_TABLE_BASE")?)?;
+    let task_count =
+        core.read_word_32(hubris.lookup_symword("TASK_TABLE_SIZE")?)?;
+    let task_t = hubris.lookup_struct_byname("Task")?;
+
+    let mut taskblock = vec![0; task_t.size * task_count as usize];
+    core.read_8(base, &mut taskblock)?;
+
+    let mut tasks = vec![];
+
+    for i in 0..task_count as usize {
+        let task_value: Value =
+            reflect::load(hubris, &taskblock, task_t, i * task_t.size)?;
+        let task = Task::from_value(&task_value)?;
+        let desc: TaskDesc = task.descriptor.load_from(hubris, core)?;
+        let module = hubris.instr_mod(desc.entry_point).unwrap_or("<unknown>");
+
+        tasks.push((module.to_string(), task));
+    }
+
+    Ok(tasks)
+}
+
+fn read_variable(
+    core: &mut dyn Core,
+    variable: &HubrisVariable,
+) -> Result<Vec<u8>> {
+    let mut buf = vec![0; variable.size];
+    core.read_8(variable.addr, &mut buf)?;
+    Ok(buf)
+}
+
+///
+/// Returns the entries of a ring buffer, oldest first, formatted as they
+/// would be by `humility ringbuf` (less the slot index).
+///
+fn read_ringbuf(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    variable: &HubrisVariable,
+) -> Result<Vec<String>> {
+    let definition = hubris.lookup_struct(variable.goff)?;
+    let ringbuf = Ringbuf::read(hubris, core, definition, variable)?;
+    let fmt = HubrisPrintFormat { hex: true, ..HubrisPrintFormat::default() };
+
+    ringbuf
+        .entries()
+        .map(|(_, entry)| {
+            let mut dumped = vec![];
+            entry.payload.format(hubris, fmt, &mut dumped)?;
+
+            Ok(format!(
+                "{:4} {:8} {:8} {}",
+                entry.line,
+                entry.generation,
+                entry.count,
+                String::from_utf8(dumped)?
+            ))
+        })
+        .collect()
+}
+
+fn diff_tasks(
+    hubris: &HubrisArchive,
+    lhs: &mut dyn Core,
+    rhs: &mut dyn Core,
+) -> Result<bool> {
+    let ltasks = read_tasks(hubris, lhs)?;
+    let rtasks = read_tasks(hubris, rhs)?;
+    let mut differ = false;
+
+    if ltasks.len() != rtasks.len() {
+        bail!(
+            "dumps have differing numbers of tasks ({} vs. {})",
+            ltasks.len(),
+            rtasks.len()
+        );
+    }
+
+    for ((name, l), (_, r)) in ltasks.iter().zip(rtasks.iter()) {
+        let (lgen, rgen) = (u32::from(l.generation), u32::from(r.generation));
+
+        if lgen != rgen {
+            println!("task {}: generation {} -> {}", name, lgen, rgen);
+            differ = true;
+        }
+
+        if l.state != r.state {
+            println!("task {}: state {:?} -> {:?}", name, l.state, r.state);
+            differ = true;
+        }
+
+        if l.priority != r.priority {
+            println!(
+                "task {}: priority {} -> {}",
+                name, l.priority.0, r.priority.0
+            );
+            differ = true;
+        }
+    }
+
+    Ok(differ)
+}
+
+//
+// Returns the differences between two sequences of lines, in order:  lines
+// only in `lhs` are prefixed with `-`, and lines only in `rhs` with `+`.
+// This is a diff over the longest common subsequence; a line that moves, or
+// that appears a different number of times, is a difference.  (Our inputs
+// are ring buffers and formatted variables, so the quadratic table is not
+// a concern.)
+//
+fn diff(lhs: &[String], rhs: &[String]) -> Vec<String> {
+    let (n, m) = (lhs.len(), rhs.len());
+
+    //
+    // lcs[i][j] is the length of the longest common subsequence of lhs[i..]
+    // and rhs[j..].
+    //
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if lhs[i] == rhs[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut rval = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < n || j < m {
+        if i < n && j < m && lhs[i] == rhs[j] {
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            rval.push(format!("- {}", lhs[i]));
+            i += 1;
+        } else {
+            rval.push(format!("+ {}", rhs[j]));
+            j += 1;
+        }
+    }
+
+    rval
+}
+
+fn diff_lines(header: &str, lhs: &[String], rhs: &[String]) -> bool {
+    let lines = diff(lhs, rhs);
+
+    if lines.is_empty() {
+        return false;
+    }
+
+    println!("{}:", header);
+
+    for line in lines {
+        println!("{}", line);
+    }
+
+    true
+}
+
+fn diff_ringbufs(
+    hubris: &HubrisArchive,
+    lhs: &mut dyn Core,
+    rhs: &mut dyn Core,
+) -> Result<bool> {
+    let mut ringbufs = hubris
+        .qualified_variables()
+        .filter(|(name, _)| name.ends_with("RINGBUF"))
+        .collect::<Vec<_>>();
+
+    ringbufs.sort();
+
+    let mut differ = false;
+
+    for (name, v) in ringbufs {
+        let task = hubris
+            .lookup_module(HubrisTask::from(v.goff))
+            .map(|m| m.name.as_str())
+            .unwrap_or("???");
+
+        //
+        // As with `humility ringbuf`, we don't want one bad ring buffer to
+        // prevent us from comparing the others.
+        //
+        let (l, r) = match (
+            read_ringbuf(hubris, lhs, v),
+            read_ringbuf(hubris, rhs, v),
+        ) {
+            (Ok(l), Ok(r)) => (l, r),
+            (Err(e), _) | (_, Err(e)) => {
+                humility::msg!("could not read ring buffer {}: {}", name, e);
+                continue;
+            }
+        };
+
+        let header = format!("ring buffer {} in {}", name, task);
+        differ |= diff_lines(&header, &l, &r);
+    }
+
+    Ok(differ)
+}
+
+fn diff_variables(
+    hubris: &HubrisArchive,
+    lhs: &mut dyn Core,
+    rhs: &mut dyn Core,
+    variables: &[String],
+) -> Result<bool> {
+    let fmt = HubrisPrintFormat {
+        newline: true,
+        hex: true,
+        ..HubrisPrintFormat::default()
+    };
+
+    let mut differ = false;
+
+    for name in variables {
+        let v = hubris.lookup_variable(name)?;
+        let l = read_variable(lhs, v)?;
+        let r = read_variable(rhs, v)?;
+
+        if l == r {
+            continue;
+        }
+
+        let l = hubris.printfmt(&l, v.goff, &fmt)?;
+        let r = hubris.printfmt(&r, v.goff, &fmt)?;
+
+        let l = l.lines().map(String::from).collect::<Vec<_>>();
+        let r = r.lines().map(String::from).collect::<Vec<_>>();
+
+        if !diff_lines(&format!("variable {}", name), &l, &r) {
+            //
+            // The variable differs, but not in a way that our formatting
+            // reveals (e.g., padding); call it out anyway.
+            //
+            println!("variable {}: contents differ", name);
+        }
+
+        differ = true;
+    }
+
+    Ok(differ)
+}
+
+fn dumpdiff(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    args: &Args,
+    subargs: &[String],
+) -> Result<()> {
+    let subargs = DumpdiffArgs::try_parse_from(subargs)?;
+
+    let mut other = humility::core::attach_dump(&subargs.dump, hubris)
+        .with_context(|| format!("failed to attach to {}", subargs.dump))?;
+
+    hubris
+        .validate(other.as_mut(), HubrisValidate::ArchiveMatch)
+        .with_context(|| {
+            format!("{} does not match the archive", subargs.dump)
+        })?;
+
+    let rhs = other.as_mut();
+
+    println!("--- {}", args.dump.as_ref().unwrap());
+    println!("+++ {}", subargs.dump);
+
+    let mut differ = diff_tasks(hubris, core, rhs)?;
+
+    if !subargs.no_ringbufs {
+        differ |= diff_ringbufs(hubris, core, rhs)?;
+    }
+
+    differ |= diff_variables(hubris, core, rhs, &subargs.variables)?;
+
+    if !differ {
+        humility::msg!("no differences found");
+    }
+
+    Ok(())
+}
+
+pub fn init() -> (Command, ClapCommand<'static>) {
+    (
+        Command::Attached {
+            name: "dumpdiff",
+            archive: Archive::Required,
+            attach: Attach::DumpOnly,
+            validate: Validate::Match,
+            run: dumpdiff,
+        },
+        DumpdiffArgs::command(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(s: &[&str]) -> Vec<String> {
+        s.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_identical() {
+        let l = lines(&["a", "b", "c"]);
+        assert!(diff(&l, &l).is_empty());
+        assert!(diff(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn diff_in_order() {
+        let l = lines(&["a", "b", "c", "d"]);
+        let r = lines(&["a", "x", "c", "d", "e"]);
+        assert_eq!(diff(&l, &r), lines(&["- b", "+ x", "+ e"]));
+    }
+
+    #[test]
+    fn diff_disjoint() {
+        let l = lines(&["a", "b"]);
+        let r = lines(&["c"]);
+        assert_eq!(diff(&l, &r), lines(&["- a", "- b", "+ c"]));
+        assert_eq!(diff(&l, &[]), lines(&["- a", "- b"]));
+        assert_eq!(diff(&[], &r), lines(&["+ c"]));
+    }
+
+    #[test]
+    fn diff_reordered() {
+        //
+        // The same lines in a different order are a difference.
+        //
+        let l = lines(&["a", "b"]);
+        let r = lines(&["b", "a"]);
+        assert_eq!(diff(&l, &r), lines(&["- a", "+ a"]));
+    }
+
+    #[test]
+    fn diff_repeated() {
+        //
+        // As is a line that appears a different number of times.
+        //
+        let l = lines(&["a", "a", "b"]);
+        let r = lines(&["a", "b", "b"]);
+        assert_eq!(diff(&l, &r), lines(&["- a", "+ b"]));
+    }
+}
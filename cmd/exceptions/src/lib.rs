@@ -0,0 +1,148 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility exceptions`
+//!
+//! `humility exceptions` displays the exceptions that are active and
+//! pending, explains any fault status bits that are set in the System
+//! Control Block, and lists the interrupts that are enabled, pending or
+//! active in the NVIC:
+//!
+//! ```console
+//! % humility exceptions
+//! humility: attached via ST-Link V3
+//! active exceptions: none
+//! pending exceptions: IRQ 39
+//! fault status: CFSR = 0x00000000, HFSR = 0x00000000
+//!
+//!  IRQ ENABLED PENDING ACTIVE PRIORITY TASK
+//!   31 yes     no      no         0x10 i2c_driver
+//!   32 yes     no      no         0x10 i2c_driver
+//!   39 yes     yes     no         0x10 usart_driver
+//! ```
+//!
+//! If any fault status bits are set, each is explained, along with the
+//! faulting address if the processor recorded one:
+//!
+//! ```console
+//! % humility exceptions
+//! humility: attached via ST-Link V3
+//! active exceptions: HardFault
+//! pending exceptions: none
+//! fault status: CFSR = 0x00008200, HFSR = 0x40000000
+//!     BusFault: precise data bus error (PRECISERR)
+//!     HardFault: escalated from a fault that could not be handled (FORCED)
+//!     BusFault address is 0x00000000 (BFAR)
+//! ...
+//! ```
+//!
+//! If an archive is present, each interrupt is shown with the task to which
+//! it is bound.  To list every interrupt that the NVIC supports (rather than
+//! just those that are enabled, pending or active), use `-a` (`--all`).
+//!
+
+use anyhow::Result;
+use clap::Command as ClapCommand;
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cmd::{Archive, Args, Attach, Command, Validate};
+use humility_cortex::fault::FaultStatus;
+use humility_cortex::nvic::{nvic_interrupts, NvicInterrupt};
+
+#[derive(Parser, Debug)]
+#[clap(name = "exceptions", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ExceptionsArgs {
+    /// show all interrupts, including those that are disabled
+    #[clap(long, short)]
+    all: bool,
+}
+
+fn exceptions_list(list: &[String]) -> String {
+    if list.is_empty() {
+        "none".to_string()
+    } else {
+        list.join(", ")
+    }
+}
+
+fn exceptions_bit(bit: bool) -> &'static str {
+    if bit {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn exceptions_halted(
+    core: &mut dyn Core,
+) -> Result<(FaultStatus, Vec<NvicInterrupt>)> {
+    Ok((FaultStatus::read(core)?, nvic_interrupts(core)?))
+}
+
+#[rustfmt::skip::macros(println)]
+fn exceptions(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    _args: &Args,
+    subargs: &[String],
+) -> Result<()> {
+    let subargs = ExceptionsArgs::try_parse_from(subargs)?;
+
+    core.halt()?;
+    let rval = exceptions_halted(core);
+    core.run()?;
+
+    let (status, interrupts) = rval?;
+
+    println!("active exceptions: {}", exceptions_list(&status.active()));
+    println!("pending exceptions: {}", exceptions_list(&status.pending()));
+    println!("fault status: CFSR = 0x{:08x}, HFSR = 0x{:08x}",
+        u32::from(status.cfsr), u32::from(status.hfsr));
+
+    for explanation in status.explain() {
+        println!("    {}", explanation);
+    }
+
+    println!("\n{:>4} {:7} {:7} {:6} {:>8} {}",
+        "IRQ", "ENABLED", "PENDING", "ACTIVE", "PRIORITY", "TASK");
+
+    for interrupt in &interrupts {
+        if !subargs.all && !interrupt.is_interesting() {
+            continue;
+        }
+
+        let tasks = hubris
+            .manifest
+            .task_irqs
+            .iter()
+            .filter(|(_, irqs)| irqs.iter().any(|&(i, _)| i == interrupt.irq))
+            .map(|(task, _)| task.as_str())
+            .collect::<Vec<_>>();
+
+        println!("{:4} {:7} {:7} {:6} {:>8} {}",
+            interrupt.irq,
+            exceptions_bit(interrupt.enabled),
+            exceptions_bit(interrupt.pending),
+            exceptions_bit(interrupt.active),
+            format!("0x{:02x}", interrupt.priority),
+            tasks.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+pub fn init() -> (Command, ClapCommand<'static>) {
+    (
+        Command::Attached {
+            name: "exceptions",
+            archive: Archive::Optional,
+            attach: Attach::Any,
+            validate: Validate::None,
+            run: exceptions,
+        },
+        ExceptionsArgs::command(),
+    )
+}
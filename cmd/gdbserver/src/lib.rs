@@ -0,0 +1,870 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility gdbserver`
+//!
+//! `humility gdbserver` serves the GDB remote serial protocol on a TCP port,
+//! delegating all target operations to however Humility is attached.  This
+//! allows a stock GDB (e.g., `arm-none-eabi-gdb`) to be used with a probe
+//! without running OpenOCD -- or to be used postmortem on a dump:
+//!
+//! ```console
+//! % humility -d hubris.core.0 gdbserver
+//! humility: attached to dump
+//! humility: listening for GDB on 127.0.0.1:2345
+//! ```
+//!
+//! And then, from GDB (using the `final.elf` found in the archive):
+//!
+//! ```console
+//! (gdb) target extended-remote localhost:2345
+//! Remote debugging using localhost:2345
+//! 0x08026e42 in userlib::sys_recv_stub ()
+//! (gdb) bt
+//! ...
+//! ```
+//!
+//! The port and listening address can be changed with `--port` and
+//! `--listen`, respectively.  Memory and register reads and writes are
+//! supported, as are continuing (`c`), single-stepping (`s`) and, on a live
+//! target, hardware breakpoints (`Z0`/`Z1`) via the Flash Patch and
+//! Breakpoint unit.  When attached to a dump, the target can be examined but
+//! not run.
+//!
+//! Each Hubris task is presented to GDB as a thread, allowing each task's
+//! stack to be examined with the usual GDB commands:
+//!
+//! ```console
+//! (gdb) info threads
+//!   Id   Target Id                Frame
+//! * 1    Thread 1 (jefe)          0x08010a2e in userlib::sys_recv_stub ()
+//!   2    Thread 2 (rcc_driver)    0x0800e3f2 in userlib::sys_recv_stub ()
+//! ...
+//! (gdb) thread 2
+//! (gdb) bt
+//! ...
+//! ```
+//!
+//! The kernel is presented as an additional thread, following the tasks.
+//! If the target halted in a task, the registers of that task are those of
+//! the target itself (and may be modified); if it halted in the kernel or in
+//! an ISR, it is the kernel's thread whose registers are those of the target.
+//! The registers of any other task are those saved when it last entered the
+//! kernel, and cannot be modified.
+//!
+//! Connections are served one at a time; when GDB disconnects (or detaches),
+//! any breakpoints are removed, the target is resumed, and `humility
+//! gdbserver` waits for another connection.
+//!
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Command as ClapCommand;
+use clap::{CommandFactory, Parser};
+use humility::arch::ARMRegister;
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cmd::{Archive, Args, Attach, Command, Validate};
+use std::collections::HashMap;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[clap(name = "gdbserver", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct GdbserverArgs {
+    /// address on which to listen
+    #[clap(long, short, default_value = "127.0.0.1")]
+    listen: String,
+
+    /// port on which to listen
+    #[clap(
+        long, short, default_value = "2345",
+        parse(try_from_str = parse_int::parse)
+    )]
+    port: u16,
+}
+
+const GDB_PACKET_START: u8 = b'$';
+const GDB_PACKET_END: u8 = b'#';
+const GDB_PACKET_ACK: u8 = b'+';
+const GDB_PACKET_NACK: u8 = b'-';
+const GDB_PACKET_HALT: u8 = 3;
+
+//
+// The maximum packet size that we advertise, in bytes of packet data; as
+// memory is sent hex-encoded, this bounds a memory read at half as many bytes.
+//
+const GDB_PACKET_SIZE: usize = 0x1000;
+
+//
+// The signals that we report in our stop replies.
+//
+const GDB_SIGINT: u8 = 2;
+const GDB_SIGTRAP: u8 = 5;
+
+//
+// Debug Halting Control and Status Register, and its S_HALT bit.
+//
+const DHCSR: u32 = 0xe000_edf0;
+const DHCSR_S_HALT: u32 = 1 << 17;
+
+//
+// Flash Patch and Breakpoint unit control register and comparators.
+//
+const FP_CTRL: u32 = 0xe000_2000;
+const FP_COMP0: u32 = 0xe000_2008;
+
+//
+// The registers that we describe to GDB, in the order in which we
+// describe them.
+//
+const GDB_REGISTERS: [ARMRegister; 17] = [
+    ARMRegister::R0,
+    ARMRegister::R1,
+    ARMRegister::R2,
+    ARMRegister::R3,
+    ARMRegister::R4,
+    ARMRegister::R5,
+    ARMRegister::R6,
+    ARMRegister::R7,
+    ARMRegister::R8,
+    ARMRegister::R9,
+    ARMRegister::R10,
+    ARMRegister::R11,
+    ARMRegister::R12,
+    ARMRegister::SP,
+    ARMRegister::LR,
+    ARMRegister::PC,
+    ARMRegister::PSR,
+];
+
+const GDB_TARGET_XML: &str = r##"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>arm</architecture>
+  <feature name="org.gnu.gdb.arm.m-profile">
+    <reg name="r0" bitsize="32"/>
+    <reg name="r1" bitsize="32"/>
+    <reg name="r2" bitsize="32"/>
+    <reg name="r3" bitsize="32"/>
+    <reg name="r4" bitsize="32"/>
+    <reg name="r5" bitsize="32"/>
+    <reg name="r6" bitsize="32"/>
+    <reg name="r7" bitsize="32"/>
+    <reg name="r8" bitsize="32"/>
+    <reg name="r9" bitsize="32"/>
+    <reg name="r10" bitsize="32"/>
+    <reg name="r11" bitsize="32"/>
+    <reg name="r12" bitsize="32"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="lr" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="xpsr" bitsize="32"/>
+  </feature>
+</target>
+"##;
+
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+struct Breakpoints {
+    revision: u32,
+    comparators: Vec<Option<u32>>,
+}
+
+impl Breakpoints {
+    fn new(core: &mut dyn Core) -> Result<Self> {
+        let ctrl = core.read_word_32(FP_CTRL)?;
+        let ncomp = ((ctrl >> 8) & 0x70) | ((ctrl >> 4) & 0xf);
+
+        Ok(Self {
+            revision: ctrl >> 28,
+            comparators: vec![None; ncomp as usize],
+        })
+    }
+
+    fn comparator(&self, addr: u32) -> u32 {
+        if self.revision == 0 {
+            //
+            // In the original FPB, the comparator matches a word address,
+            // with the REPLACE field denoting which halfword to break on.
+            //
+            let replace = if addr & 0b10 != 0 { 0b10 } else { 0b01 };
+            (replace << 30) | (addr & 0x1fff_fffc) | 1
+        } else {
+            (addr & !1) | 1
+        }
+    }
+
+    fn insert(&mut self, core: &mut dyn Core, addr: u32) -> Result<()> {
+        if self.comparators.iter().any(|c| *c == Some(addr)) {
+            return Ok(());
+        }
+
+        let ndx =
+            self.comparators.iter().position(|c| c.is_none()).ok_or_else(
+                || anyhow!("no breakpoint comparators available"),
+            )?;
+
+        core.write_word_32(FP_COMP0 + ndx as u32 * 4, self.comparator(addr))?;
+        core.write_word_32(FP_CTRL, 0b11)?;
+        self.comparators[ndx] = Some(addr);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, core: &mut dyn Core, addr: u32) -> Result<()> {
+        if let Some(ndx) =
+            self.comparators.iter().position(|c| *c == Some(addr))
+        {
+            core.write_word_32(FP_COMP0 + ndx as u32 * 4, 0)?;
+            self.comparators[ndx] = None;
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self, core: &mut dyn Core) -> Result<()> {
+        for addr in self.comparators.clone().into_iter().flatten() {
+            self.remove(core, addr)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>> {
+    if !s.is_ascii() {
+        bail!("invalid hex string \"{}\"", s);
+    }
+
+    if s.len() % 2 != 0 {
+        bail!("odd-length hex string \"{}\"", s);
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&s[i..i + 2], 16)?))
+        .collect()
+}
+
+//
+// We present each task as a GDB thread; because GDB reserves thread IDs 0
+// and -1, a task's thread ID is its index plus one.  The kernel (which is
+// what is running when the target is halted in handler mode) is presented
+// as a thread of its own, following the tasks.
+//
+fn thread_id(hubris: &HubrisArchive, task: HubrisTask) -> String {
+    match task {
+        HubrisTask::Task(ndx) => format!("{:x}", ndx + 1),
+        HubrisTask::Kernel => format!("{:x}", hubris.ntasks() + 1),
+    }
+}
+
+fn addr_len(s: &str) -> Result<(u32, usize)> {
+    let (addr, len) =
+        s.split_once(',').ok_or_else(|| anyhow!("malformed \"{}\"", s))?;
+
+    Ok((u32::from_str_radix(addr, 16)?, usize::from_str_radix(len, 16)?))
+}
+
+struct GdbServer<'a> {
+    hubris: &'a HubrisArchive,
+    core: &'a mut dyn Core,
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+    noack: bool,
+    breakpoints: Option<Breakpoints>,
+    thread: Option<HubrisTask>,
+}
+
+impl<'a> GdbServer<'a> {
+    fn new(
+        hubris: &'a HubrisArchive,
+        core: &'a mut dyn Core,
+        stream: TcpStream,
+    ) -> Result<Self> {
+        Ok(Self {
+            hubris,
+            core,
+            reader: BufReader::new(stream.try_clone()?),
+            stream,
+            noack: false,
+            breakpoints: None,
+            thread: None,
+        })
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let mut buf = [0u8; 1];
+
+        match self.reader.read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Option<Packet>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(GDB_PACKET_HALT) => return Ok(Some(Packet::Interrupt)),
+                Some(GDB_PACKET_START) => break,
+                Some(_) => continue,
+            }
+        }
+
+        let mut payload = vec![];
+
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(GDB_PACKET_END) => break,
+                Some(b) => payload.push(b),
+            }
+        }
+
+        let mut cksum = [0u8; 2];
+        self.reader.read_exact(&mut cksum)?;
+
+        let expected = u8::from_str_radix(std::str::from_utf8(&cksum)?, 16)?;
+        let actual = payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+        if !self.noack {
+            if expected != actual {
+                log::warn!("bad checksum on {:?}", payload);
+                self.stream.write_all(&[GDB_PACKET_NACK])?;
+                return self.read_packet();
+            }
+
+            self.stream.write_all(&[GDB_PACKET_ACK])?;
+        }
+
+        let payload = String::from_utf8(payload)?;
+        log::trace!("received {}", payload);
+
+        Ok(Some(Packet::Command(payload)))
+    }
+
+    fn send(&mut self, payload: &str) -> Result<()> {
+        let cksum = payload.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+
+        let packet = format!("${}#{:02x}", payload, cksum);
+        log::trace!("sending {}", packet);
+        self.stream.write_all(packet.as_bytes())?;
+
+        //
+        // If we are still acknowledging, we expect an ACK back.  (We don't
+        // bother retransmitting on a NACK; we are on TCP.)
+        //
+        if !self.noack {
+            match self.read_byte()? {
+                Some(GDB_PACKET_ACK) | Some(GDB_PACKET_NACK) | None => {}
+                Some(b) => log::warn!("expected ack, found 0x{:x}", b),
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Returns the thread that is running:  the current task if the target
+    /// is running in a task, or the kernel otherwise.
+    ///
+    fn current_thread(&mut self) -> Result<HubrisTask> {
+        Ok(self.hubris.current_task(self.core)?.unwrap_or(HubrisTask::Kernel))
+    }
+
+    fn select_thread(&mut self, args: &str) -> Result<()> {
+        //
+        // We only honor the thread selected for register operations (`Hg`);
+        // as we run and step the target as a whole, the thread selected for
+        // execution (`Hc`) is immaterial.
+        //
+        let tid = match args.strip_prefix('g') {
+            Some(tid) => tid,
+            None => return Ok(()),
+        };
+
+        self.thread = match tid {
+            "0" | "-1" => None,
+            _ => Some(self.lookup_thread(tid)?),
+        };
+
+        Ok(())
+    }
+
+    fn lookup_thread(&self, tid: &str) -> Result<HubrisTask> {
+        let tid = u32::from_str_radix(tid, 16)?;
+        let ntasks = self.hubris.ntasks();
+
+        if tid == 0 || tid as usize > ntasks + 1 {
+            bail!("invalid thread {}", tid);
+        }
+
+        if tid as usize == ntasks + 1 {
+            Ok(HubrisTask::Kernel)
+        } else {
+            Ok(HubrisTask::Task(tid - 1))
+        }
+    }
+
+    ///
+    /// Returns the saved registers of the selected thread -- or `None` if
+    /// the selected thread is the one that is running, in which case its
+    /// registers are those of the target.  The kernel saves no registers of
+    /// its own, so when it isn't running, none of its registers are known.
+    ///
+    fn saved_registers(&mut self) -> Result<Option<HashMap<ARMRegister, u32>>> {
+        let task = match self.thread {
+            Some(task) if task != self.current_thread()? => task,
+            _ => return Ok(None),
+        };
+
+        match task {
+            HubrisTask::Task(_) => {
+                Ok(Some(self.hubris.thread(self.core, task)?.registers))
+            }
+            HubrisTask::Kernel => Ok(Some(HashMap::new())),
+        }
+    }
+
+    fn read_register(
+        &mut self,
+        saved: &Option<HashMap<ARMRegister, u32>>,
+        reg: ARMRegister,
+    ) -> Result<u32> {
+        match saved {
+            Some(regs) => regs
+                .get(&reg)
+                .copied()
+                .ok_or_else(|| anyhow!("{} was not saved", reg)),
+            None => self.core.read_reg(reg),
+        }
+    }
+
+    fn read_registers(&mut self) -> Result<String> {
+        let saved = self.saved_registers()?;
+        let mut rval = String::new();
+
+        for reg in GDB_REGISTERS {
+            match self.read_register(&saved, reg) {
+                Ok(val) => rval.push_str(&hex(&val.to_le_bytes())),
+                Err(_) => rval.push_str("xxxxxxxx"),
+            }
+        }
+
+        Ok(rval)
+    }
+
+    fn check_writable(&mut self) -> Result<()> {
+        if self.saved_registers()?.is_some() {
+            bail!("cannot modify the registers of a task that isn't running");
+        }
+
+        Ok(())
+    }
+
+    fn write_registers(&mut self, data: &str) -> Result<()> {
+        self.check_writable()?;
+        let bytes = unhex(data)?;
+
+        for (reg, val) in GDB_REGISTERS.iter().zip(bytes.chunks_exact(4)) {
+            let val = u32::from_le_bytes(val.try_into().unwrap());
+            self.core.write_reg(*reg, val)?;
+        }
+
+        Ok(())
+    }
+
+    fn register(n: &str) -> Result<ARMRegister> {
+        let n = usize::from_str_radix(n, 16)?;
+
+        GDB_REGISTERS
+            .get(n)
+            .copied()
+            .ok_or_else(|| anyhow!("invalid register {}", n))
+    }
+
+    fn read_memory(&mut self, args: &str) -> Result<String> {
+        let (addr, len) = addr_len(args)?;
+
+        if len > GDB_PACKET_SIZE / 2 {
+            bail!("read of {} bytes exceeds packet size", len);
+        }
+
+        let mut buf = vec![0u8; len];
+        self.core.read_8(addr, &mut buf)?;
+        Ok(hex(&buf))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Result<()> {
+        let (region, data) = args
+            .split_once(':')
+            .ok_or_else(|| anyhow!("malformed write \"{}\"", args))?;
+
+        let (addr, len) = addr_len(region)?;
+        let data = unhex(data)?;
+
+        if data.len() != len {
+            bail!("expected {} bytes, found {}", len, data.len());
+        }
+
+        self.core.write_8(addr, &data)
+    }
+
+    ///
+    /// Returns true if we support the breakpoint type of the specified `Z` or
+    /// `z` request:  software (which we implement as hardware) or hardware
+    /// breakpoints on a live target.  In particular, we don't support
+    /// watchpoints.
+    ///
+    fn breakpoint_supported(&self, args: &str) -> bool {
+        matches!(args.split(',').next(), Some("0") | Some("1"))
+            && !self.core.is_dump()
+    }
+
+    fn breakpoint(&mut self, args: &str, insert: bool) -> Result<()> {
+        let addr =
+            args.split(',').nth(1).ok_or_else(|| anyhow!("missing address"))?;
+        let addr = u32::from_str_radix(addr, 16)?;
+
+        let mut breakpoints = match self.breakpoints.take() {
+            Some(breakpoints) => breakpoints,
+            None => Breakpoints::new(self.core)?,
+        };
+
+        let rval = if insert {
+            breakpoints.insert(self.core, addr)
+        } else {
+            breakpoints.remove(self.core, addr)
+        };
+
+        self.breakpoints = Some(breakpoints);
+
+        rval
+    }
+
+    fn halted(&mut self) -> Result<bool> {
+        Ok(self.core.read_word_32(DHCSR)? & DHCSR_S_HALT != 0)
+    }
+
+    ///
+    /// Resumes the target, returning the signal to report when it stops --
+    /// either because it halted on its own (e.g., due to a breakpoint) or
+    /// because GDB interrupted it.
+    ///
+    fn resume(&mut self) -> Result<u8> {
+        if self.core.is_dump() {
+            return Ok(GDB_SIGTRAP);
+        }
+
+        self.core.run()?;
+
+        self.stream.set_read_timeout(Some(Duration::from_millis(10)))?;
+
+        let rval = loop {
+            let mut buf = [0u8; 1];
+
+            match self.reader.read(&mut buf) {
+                Ok(0) => break Err(anyhow!("connection closed")),
+                Ok(_) if buf[0] == GDB_PACKET_HALT => {
+                    self.core.halt()?;
+                    break Ok(GDB_SIGINT);
+                }
+                Ok(_) => {}
+                Err(e)
+                    if e.kind() == ErrorKind::WouldBlock
+                        || e.kind() == ErrorKind::TimedOut =>
+                {
+                    if self.halted()? {
+                        self.core.halt()?;
+                        break Ok(GDB_SIGTRAP);
+                    }
+
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => break Err(e.into()),
+            }
+        };
+
+        self.stream.set_read_timeout(None)?;
+
+        rval
+    }
+
+    ///
+    /// Returns a stop reply indicating the specified signal and the thread
+    /// that was running.  Once the target has stopped, the running thread is
+    /// again the one selected.
+    ///
+    fn stopped(&mut self, signal: u8) -> Result<String> {
+        self.thread = None;
+
+        let current = self.current_thread()?;
+
+        Ok(format!(
+            "T{:02x}thread:{};",
+            signal,
+            thread_id(self.hubris, current)
+        ))
+    }
+
+    fn reply<T>(
+        &mut self,
+        rval: Result<T>,
+        ok: impl Fn(T) -> String,
+    ) -> Result<()> {
+        match rval {
+            Ok(val) => {
+                let payload = ok(val);
+                self.send(&payload)
+            }
+            Err(e) => {
+                log::warn!("request failed: {:?}", e);
+                self.send("E01")
+            }
+        }
+    }
+
+    fn query(&mut self, cmd: &str) -> Result<()> {
+        if cmd.starts_with("qSupported") {
+            self.send(&format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+                GDB_PACKET_SIZE
+            ))
+        } else if let Some(args) =
+            cmd.strip_prefix("qXfer:features:read:target.xml:")
+        {
+            let (offset, len) = addr_len(args)?;
+            let xml = GDB_TARGET_XML.as_bytes();
+            let offset = std::cmp::min(offset as usize, xml.len());
+            let end = std::cmp::min(offset.saturating_add(len), xml.len());
+            let prefix = if end == xml.len() { "l" } else { "m" };
+            let chunk = std::str::from_utf8(&xml[offset..end])?;
+
+            self.send(&format!("{}{}", prefix, chunk))
+        } else if cmd == "QStartNoAckMode" {
+            self.send("OK")?;
+            self.noack = true;
+            Ok(())
+        } else if cmd == "qAttached" {
+            self.send("1")
+        } else if cmd == "qC" {
+            let current = self.current_thread()?;
+            self.send(&format!("QC{}", thread_id(self.hubris, current)))
+        } else if cmd == "qfThreadInfo" {
+            let threads = (0..self.hubris.ntasks())
+                .map(|ndx| HubrisTask::Task(ndx as u32))
+                .chain(std::iter::once(HubrisTask::Kernel))
+                .map(|task| thread_id(self.hubris, task))
+                .collect::<Vec<_>>();
+
+            self.send(&format!("m{}", threads.join(",")))
+        } else if cmd == "qsThreadInfo" {
+            self.send("l")
+        } else if let Some(tid) = cmd.strip_prefix("qThreadExtraInfo,") {
+            let rval = self.lookup_thread(tid).and_then(|task| {
+                Ok(self.hubris.lookup_module(task)?.name.clone())
+            });
+
+            self.reply(rval, |name| hex(name.as_bytes()))
+        } else {
+            self.send("")
+        }
+    }
+
+    fn serve(&mut self) -> Result<()> {
+        self.core.halt()?;
+
+        let rval = self.serve_packets();
+
+        //
+        // Upon disconnect -- or upon failure -- we remove any breakpoints
+        // that we set and let the target go on its way.
+        //
+        let cleared = match self.breakpoints.take() {
+            Some(mut breakpoints) => breakpoints.clear(self.core),
+            None => Ok(()),
+        };
+
+        self.core.run()?;
+
+        rval?;
+        cleared
+    }
+
+    fn serve_packets(&mut self) -> Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let cmd = match packet {
+                Packet::Command(cmd) => cmd,
+                Packet::Interrupt => {
+                    //
+                    // We are already halted; just indicate as much.
+                    //
+                    let reply = self.stopped(GDB_SIGINT)?;
+                    self.send(&reply)?;
+                    continue;
+                }
+            };
+
+            //
+            // We don't support any binary packets, so anything that isn't
+            // ASCII is as unsupported as an empty packet.
+            //
+            if cmd.is_empty() || !cmd.is_ascii() {
+                self.send("")?;
+                continue;
+            }
+
+            let (op, args) = cmd.split_at(1);
+
+            match op {
+                "?" => {
+                    let reply = self.stopped(GDB_SIGTRAP)?;
+                    self.send(&reply)?;
+                }
+                "q" | "Q" => self.query(&cmd)?,
+                "H" => {
+                    let rval = self.select_thread(args);
+                    self.reply(rval, |_| "OK".to_string())?;
+                }
+                "T" => {
+                    let rval = self.lookup_thread(args);
+                    self.reply(rval, |_| "OK".to_string())?;
+                }
+                "g" => {
+                    let rval = self.read_registers();
+                    self.reply(rval, |regs| regs)?;
+                }
+                "G" => {
+                    let rval = self.write_registers(args);
+                    self.reply(rval, |_| "OK".to_string())?;
+                }
+                "p" => {
+                    let rval = Self::register(args).and_then(|reg| {
+                        let saved = self.saved_registers()?;
+                        self.read_register(&saved, reg)
+                    });
+                    self.reply(rval, |val| hex(&val.to_le_bytes()))?;
+                }
+                "P" => {
+                    let rval = args
+                        .split_once('=')
+                        .ok_or_else(|| anyhow!("malformed \"{}\"", args))
+                        .and_then(|(n, val)| {
+                            self.check_writable()?;
+                            let reg = Self::register(n)?;
+                            let val = unhex(val)?;
+                            let val =
+                                u32::from_le_bytes(val.as_slice().try_into()?);
+                            self.core.write_reg(reg, val)
+                        });
+                    self.reply(rval, |_| "OK".to_string())?;
+                }
+                "m" => {
+                    let rval = self.read_memory(args);
+                    self.reply(rval, |data| data)?;
+                }
+                "M" => {
+                    let rval = self.write_memory(args);
+                    self.reply(rval, |_| "OK".to_string())?;
+                }
+                "Z" | "z" => {
+                    //
+                    // An empty response indicates that a breakpoint type is
+                    // unsupported, which allows GDB to fall back (e.g., to
+                    // software watchpoints).
+                    //
+                    if !self.breakpoint_supported(args) {
+                        self.send("")?;
+                        continue;
+                    }
+
+                    let rval = self.breakpoint(args, op == "Z");
+                    self.reply(rval, |_| "OK".to_string())?;
+                }
+                "c" => {
+                    let signal = self.resume()?;
+                    let reply = self.stopped(signal)?;
+                    self.send(&reply)?;
+                }
+                "s" => {
+                    //
+                    // A failure to step (e.g., due to a probe error) is
+                    // reported to GDB rather than ending the session.
+                    //
+                    let rval = if self.core.is_dump() {
+                        Ok(())
+                    } else {
+                        self.core.step()
+                    };
+
+                    let rval = rval.and_then(|_| self.stopped(GDB_SIGTRAP));
+                    self.reply(rval, |reply| reply)?;
+                }
+                "D" => {
+                    self.send("OK")?;
+                    break;
+                }
+                "k" => {
+                    break;
+                }
+                _ => self.send("")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn gdbserver(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    _args: &Args,
+    subargs: &[String],
+) -> Result<()> {
+    let subargs = GdbserverArgs::try_parse_from(subargs)?;
+
+    let listener = TcpListener::bind((subargs.listen.as_str(), subargs.port))
+        .with_context(|| {
+        format!("failed to listen on {}:{}", subargs.listen, subargs.port)
+    })?;
+
+    humility::msg!("listening for GDB on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr()?;
+
+        humility::msg!("connection from {}", peer);
+
+        match GdbServer::new(hubris, core, stream)?.serve() {
+            Ok(_) => humility::msg!("{} disconnected", peer),
+            Err(e) => humility::msg!("connection from {} failed: {}", peer, e),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn init() -> (Command, ClapCommand<'static>) {
+    (
+        Command::Attached {
+            name: "gdbserver",
+            archive: Archive::Required,
+            attach: Attach::Any,
+            validate: Validate::Match,
+            run: gdbserver,
+        },
+        GdbserverArgs::command(),
+    )
+}
@@ -37,12 +37,66 @@
 //! UserLeds.led_toggle() = ()
 //! ```
 //!
+//! Arguments that are structures, arrays, tuples or enums with values can be
+//! specified using either Rust-like syntax or JSON, e.g.:
+//!
+//! ```console
+//! % humility hiffy -c Thermal.set_fan_config \
+//!     -a 'config=FanConfig { fan: 2, pwm: [10, 20, 30], mode: Auto }'
+//! humility: attached via ST-Link
+//! Thermal.set_fan_config() = ()
+//! % humility hiffy -c Thermal.set_fan_config \
+//!     -a 'config={"fan": 2, "pwm": [10, 20, 30], "mode": "Auto"}'
+//! humility: attached via ST-Link
+//! Thermal.set_fan_config() = ()
+//! ```
+//!
+//! Tuples may be specified as `(1, 2)`, and enum variants with values as
+//! `Some(3)`, `Variant { a: 1 }` or (in JSON) `{"Variant": {"a": 1}}`.
+//! Multiple arguments may be separated by commas, or specified with
+//! multiple `-a` options.
+//!
+//! For operations that take a lease, the data for a read lease can be
+//! provided from a file with `-i` (`--input`) or as a hex string with `-x`
+//! (`--input-hex`); for a write lease, the size of the lease must be
+//! specified with `-n` (`--num`), and its contents will be written to the
+//! file specified with `-o` (`--output`) or dumped if no file is specified:
+//!
+//! ```console
+//! % humility hiffy -c Hash.digest_sha256 -a len=5 -x 68656c6c6f
+//! humility: attached via ST-Link
+//! Hash.digest_sha256() = [ 0x2c, 0xf2, 0x4d, 0xba, ... ]
+//! % humility hiffy -c Spi.read -a device_index=0 -n 16 -o spi.bin
+//! humility: attached via ST-Link
+//! Spi.read() = ()
+//! humility: wrote 16 bytes to spi.bin
+//! ```
+//!
+//! Only one lease is supported per call, and its size is limited by the
+//! size of the HIF data area (for read leases) or the HIF scratch area (for
+//! write leases).
+//!
+//! Replies are decoded according to the type of the operation's reply,
+//! including structures, arrays and enums with values; an error is
+//! displayed as the variant of the operation's error type.  To instead emit
+//! the result as JSON (e.g., for consumption by a script), use `-j`
+//! (`--json`):
+//!
+//! ```console
+//! % humility hiffy -c Sensor.get -a id=3 -j
+//! humility: attached via ST-Link
+//! {"Ok":23.0}
+//! % humility hiffy -c Sensor.get -a id=22 -j
+//! humility: attached via ST-Link
+//! {"Err":"NotPresent"}
+//! ```
+//!
 //! To view the raw HIF functions provided to programmatic HIF consumers
 //! within Humility, use `-L` (`--list-functions`).
 //!
 
 use ::idol::syntax::{Operation, Reply};
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Command as ClapCommand;
 use clap::{CommandFactory, Parser};
 use hif::*;
@@ -50,7 +104,10 @@ use humility::core::Core;
 use humility::hubris::*;
 use humility_cmd::hiffy::*;
 use humility_cmd::idol;
-use humility_cmd::{Archive, Args, Attach, Command, Validate};
+use humility_cmd::reflect::Format;
+use humility_cmd::{Archive, Args, Attach, Command, Dumper, Validate};
+use std::fs;
+use std::io::Write;
 
 #[derive(Parser, Debug)]
 #[clap(name = "hiffy", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -83,8 +140,62 @@ struct HiffyArgs {
     task: Option<String>,
 
     /// arguments
-    #[clap(long, short, requires = "call", use_value_delimiter = true)]
+    #[clap(long, short, requires = "call", multiple_occurrences = true)]
     arguments: Vec<String>,
+
+    /// file containing the data for a read lease
+    #[clap(long, short, requires = "call", conflicts_with = "input_hex")]
+    input: Option<String>,
+
+    /// data for a read lease, as a hex string
+    #[clap(long = "input-hex", short = 'x', requires = "call")]
+    input_hex: Option<String>,
+
+    /// file to which to write the data from a write lease
+    #[clap(long, short, requires = "call")]
+    output: Option<String>,
+
+    /// size of a write lease
+    #[clap(
+        long, short, requires = "call", value_name = "bytes",
+        parse(try_from_str = parse_int::parse)
+    )]
+    num: Option<usize>,
+
+    /// print the result of a call as JSON
+    #[clap(long, short, requires = "call")]
+    json: bool,
+}
+
+//
+// Splits arguments on commas -- but only those commas that aren't within
+// a structured value (or a string).
+//
+fn split_arguments(arguments: &str) -> Vec<&str> {
+    let mut rval = vec![];
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in arguments.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                rval.push(arguments[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    rval.push(arguments[start..].trim());
+    rval
 }
 
 fn hiffy_list(hubris: &HubrisArchive, subargs: &HiffyArgs) -> Result<()> {
@@ -166,40 +277,173 @@ fn hiffy_list(hubris: &HubrisArchive, subargs: &HiffyArgs) -> Result<()> {
     Ok(())
 }
 
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex = hex.trim_start_matches("0x");
+    let hex = hex.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+
+    if hex.len() % 2 != 0 {
+        bail!("hex string must have an even number of digits");
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| anyhow!("invalid hex \"{}\"", &hex[i..i + 2]))
+        })
+        .collect()
+}
+
+//
+// Determines the lease (if any) for a call, returning it along with any
+// data for it.
+//
+fn hiffy_lease(
+    subargs: &HiffyArgs,
+    op: &idol::IdolOperation,
+) -> Result<(Option<IdolLease>, Option<Vec<u8>>)> {
+    let input = match (&subargs.input, &subargs.input_hex) {
+        (Some(input), _) => Some(
+            fs::read(input)
+                .with_context(|| format!("failed to read {}", input))?,
+        ),
+        (None, Some(hex)) => Some(parse_hex(hex)?),
+        (None, None) => None,
+    };
+
+    let mut leases = op.operation.leases.iter();
+
+    let (name, lease) = match (leases.next(), leases.next()) {
+        (None, _) => {
+            if input.is_some()
+                || subargs.output.is_some()
+                || subargs.num.is_some()
+            {
+                bail!("{}.{} does not take a lease", op.name.0, op.name.1);
+            }
+
+            return Ok((None, None));
+        }
+        (Some(lease), None) => lease,
+        (Some(_), Some(_)) => {
+            bail!("calls with more than one lease are not supported");
+        }
+    };
+
+    match (lease.read, lease.write) {
+        (true, false) => {
+            if subargs.output.is_some() || subargs.num.is_some() {
+                bail!("lease {} is read, not written", name);
+            }
+
+            match input {
+                Some(input) => {
+                    Ok((Some(IdolLease::Read(input.len())), Some(input)))
+                }
+                None => bail!("lease {} requires input (-i or -x)", name),
+            }
+        }
+        (false, true) => {
+            if input.is_some() {
+                bail!("lease {} is written, not read", name);
+            }
+
+            match subargs.num {
+                Some(num) => Ok((Some(IdolLease::Write(num)), None)),
+                None => bail!("lease {} requires a size (-n)", name),
+            }
+        }
+        _ => bail!("lease {} is both read and written; not supported", name),
+    }
+}
+
 fn hiffy_call(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
     context: &mut HiffyContext,
     op: &idol::IdolOperation,
     args: &[(&str, idol::IdolArgument)],
+    subargs: &HiffyArgs,
 ) -> Result<()> {
     let funcs = context.functions()?;
     let mut ops = vec![];
 
     let payload = op.payload(args)?;
-    context.idol_call_ops(&funcs, op, &payload, &mut ops)?;
+    let (lease, data) = hiffy_lease(subargs, op)?;
+
+    //
+    // Dumping the contents of a write lease would make our JSON unparseable;
+    // if we are emitting JSON, they must go to a file.
+    //
+    if subargs.json
+        && subargs.output.is_none()
+        && matches!(lease, Some(IdolLease::Write(_)))
+    {
+        bail!("--json requires --output for operations with a write lease");
+    }
+
+    context.idol_call_ops_with_lease(&funcs, op, &payload, lease, &mut ops)?;
     ops.push(Op::Done);
 
-    let results = context.run(core, ops.as_slice(), None)?;
+    let results = context.run(core, ops.as_slice(), data.as_deref())?;
 
     if results.len() != 1 {
         bail!("unexpected results length: {:?}", results);
     }
 
-    let result = &results[0];
     let fmt = HubrisPrintFormat {
         newline: false,
         hex: true,
         ..HubrisPrintFormat::default()
     };
 
-    match result {
-        Ok(val) => {
-            let dumped = hubris.printfmt(val, op.ok, &fmt)?;
-            println!("{}.{}() = {}", op.name.0, op.name.1, dumped);
+    //
+    // If we have a write lease, its contents follow our reply.
+    //
+    let written = match (&results[0], lease) {
+        (Ok(val), Some(IdolLease::Write(_))) => {
+            let size = hubris.typesize(op.ok)?;
+
+            if val.len() < size {
+                bail!("short reply: {:x?}", val);
+            }
+
+            Some(&val[size..])
+        }
+        _ => None,
+    };
+
+    let result = op.reply(&results[0])?;
+
+    if subargs.json {
+        println!("{}", serde_json::to_string(&result)?);
+    } else {
+        let mut out = vec![];
+
+        match &result {
+            Ok(val) => {
+                write!(out, "{}.{}() = ", op.name.0, op.name.1)?;
+                val.format(hubris, fmt, &mut out)?;
+            }
+            Err(err) => {
+                write!(out, "Err(")?;
+                err.format(hubris, fmt, &mut out)?;
+                write!(out, ")")?;
+            }
         }
-        Err(e) => {
-            println!("Err({:x?})", e);
+
+        println!("{}", String::from_utf8_lossy(&out));
+    }
+
+    if let Some(written) = written {
+        match &subargs.output {
+            Some(output) => {
+                fs::write(output, written)
+                    .with_context(|| format!("failed to write {}", output))?;
+
+                humility::msg!("wrote {} bytes to {}", written.len(), output);
+            }
+            None => Dumper::new().dump(written, 0),
         }
     }
 
@@ -230,14 +474,12 @@ fn hiffy(
 
         let mut args = vec![];
 
-        for arg in &subargs.arguments {
-            let arg: Vec<&str> = arg.split('=').collect();
+        for arg in subargs.arguments.iter().flat_map(|a| split_arguments(a)) {
+            let arg = arg.split_once('=').ok_or_else(|| {
+                anyhow!("arguments must be argument=value (-l to list)")
+            })?;
 
-            if arg.len() != 2 {
-                bail!("arguments must be argument=value (-l to list)");
-            }
-
-            args.push((arg[0], idol::IdolArgument::String(arg[1])));
+            args.push((arg.0, idol::IdolArgument::String(arg.1)));
         }
 
         let task = match subargs.task {
@@ -250,7 +492,7 @@ fn hiffy(
         };
 
         let op = idol::IdolOperation::new(hubris, func[0], func[1], task)?;
-        hiffy_call(hubris, core, &mut context, &op, &args)?;
+        hiffy_call(hubris, core, &mut context, &op, &args, &subargs)?;
 
         return Ok(());
     }
@@ -303,3 +545,47 @@ pub fn init() -> (Command, ClapCommand<'static>) {
         HiffyArgs::command(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_simple() {
+        assert_eq!(split_arguments("a=1"), vec!["a=1"]);
+        assert_eq!(split_arguments("a=1, b=2,c=3"), vec!["a=1", "b=2", "c=3"]);
+    }
+
+    #[test]
+    fn split_nested() {
+        assert_eq!(
+            split_arguments("a=Foo { x: 1, y: [2, 3] }, b=(4, 5),c=Some(6)"),
+            vec!["a=Foo { x: 1, y: [2, 3] }", "b=(4, 5)", "c=Some(6)"]
+        );
+    }
+
+    #[test]
+    fn split_quoted() {
+        assert_eq!(
+            split_arguments(r#"a="x, y", b="{""#),
+            vec![r#"a="x, y""#, r#"b="{""#]
+        );
+
+        assert_eq!(
+            split_arguments(r#"a="say \"hi, there\"", b=1"#),
+            vec![r#"a="say \"hi, there\"""#, "b=1"]
+        );
+    }
+
+    #[test]
+    fn split_malformed() {
+        //
+        // We don't reject malformed arguments here (that's left to the
+        // literal parser), but we mustn't split within them.
+        //
+        assert_eq!(split_arguments("a=[1, 2, b=3"), vec!["a=[1, 2, b=3"]);
+        assert_eq!(split_arguments(r#"a="1, b=2"#), vec![r#"a="1, b=2"#]);
+        assert_eq!(split_arguments("a=1,"), vec!["a=1", ""]);
+        assert_eq!(split_arguments(""), vec![""]);
+    }
+}
@@ -90,17 +90,135 @@
 //! Controller I2C3, device 0x48, register 0x4 = 0x1f
 //! ```
 //!
+//! Many devices (e.g., larger EEPROMs) have 16-bit register addresses; to
+//! specify the width of a register address, use `--regwidth`.  Register
+//! addresses wider than a byte are sent most significant byte first unless
+//! `--endian little` is specified.  This applies to register reads (including
+//! block reads), writes and scans.  (A register scan of a device with 16-bit
+//! register addresses scans the first 256 registers.)  When flashing, register
+//! addresses are assumed to be 16 bits wide unless otherwise specified.
+//!
+//! ```console
+//! % humility i2c -b mid -d 0x50 --regwidth 2 -r 0x1f0 -n 2
+//! humility: attached via ST-Link V3
+//! Controller I2C4, device 0x50, register 0x1f0 = 0x4f 0x58
+//! ```
+//!
+//! If `--endian` is specified, 2- and 4-byte values are also displayed as a
+//! single value in the specified byte order:
+//!
+//! ```console
+//! % humility i2c -c 3 -d 0x48 -r 0x0 -n 2 --endian big
+//! humility: attached via ST-Link
+//! Controller I2C3, device 0x48, register 0x0 = 0x0c 0x80 (0x0c80)
+//! ```
+//!
+//! To write a file into an EEPROM, use `-f` (`--flash`); to read the contents
+//! of an EEPROM into a file, use `-o` (`--readout`), specifying the size of
+//! the EEPROM via `--size`.  EEPROMs are written and read a page at a time
+//! (16 bytes unless otherwise specified via `--pagesize`), with as many
+//! pages per HIF program as will fit:
+//!
+//! ```console
+//! % humility i2c -b mid -d 0x50 -o fru.bin --size 1024
+//! humility: attached via ST-Link V3
+//! humility: read 1.00KB in 2 seconds
+//! humility: contents written to fru.bin
+//! ```
+//!
+//! To verify that the contents of an EEPROM match a file, use `-v`
+//! (`--verify`).  Any differences are reported by offset, and the command
+//! fails:
+//!
+//! ```console
+//! % humility i2c -b mid -d 0x50 -v fru.bin
+//! humility: attached via ST-Link V3
+//! humility: read 1.00KB in 2 seconds
+//! OFFSET     LENGTH EXPECTED                    FOUND
+//! 0x00000048      2 4f 58                       ff ff
+//! humility i2c failed: verification failed: 2 bytes differ in 1 range
+//! ```
+//!
+//! To read a device's registers and decode the fields within them, use `-D`
+//! (`--decode`).  Register maps are built in for some parts (currently the
+//! ADT7420 and the TMP117), and are found by the part name of the device
+//! in the archive; to use a different register map, specify a TOML file via
+//! `--regmap`.  To decode a single register, specify it via `-r`:
+//!
+//! ```console
+//! % humility i2c -c 3 -d 0x48 -D -r 0x3
+//! humility: attached via ST-Link
+//! Controller I2C3, device 0x48 (adt7420):
+//!
+//! REGISTER             ADDR VALUE
+//! CONFIG               0x03 0x40
+//!   bits |    value   | field
+//!   7:7  | 0x0        | RESOLUTION (13-bit)
+//!   6:5  | 0x2        | OP_MODE (1 SPS)
+//!   4:4  | 0x0        | INT_CT_MODE (interrupt)
+//!   3:3  | 0x0        | INT_POLARITY (active low)
+//!   2:2  | 0x0        | CT_POLARITY (active low)
+//!   1:0  | 0x0        | FAULT_QUEUE (1 fault)
+//! ```
+//!
+//! A register map consists of registers, each with a name, an address, a
+//! width in bytes (1 if not specified) and any fields.  Registers are
+//! assumed to be most significant byte first unless `little_endian` is set.
+//! Each field has a name, a low bit and (if wider than one bit) a high bit
+//! -- and optionally names for its values:
+//!
+//! ```toml
+//! [[register]]
+//! name = "CONFIG"
+//! address = 0x03
+//!
+//! [[register.field]]
+//! name = "OP_MODE"
+//! hi = 6
+//! lo = 5
+//! values = { 0 = "continuous", 1 = "one-shot", 2 = "1 SPS", 3 = "shutdown" }
+//! ```
+//!
+//! To scan every controller, port and mux segment that the archive knows
+//! about and compare what is found against the devices that are expected,
+//! use `-T` (`--topology`).  Each expected device is shown as `ok`,
+//! `missing`, `timed out` or with the error encountered when scanning for
+//! it; any device that responds but is not expected is shown as
+//! `unexpected`.  The muxes on each port (as described by the archive's
+//! I<sup>2</sup>C configuration) are expected devices on that port.  (Devices
+//! on a port, including its muxes, will also respond when scanning the
+//! segments of any mux on that port; these are not considered unexpected on
+//! the segments.)  To limit the scan to a single controller, use `-c`:
+//!
+//! ```console
+//! % humility i2c -T
+//! humility: attached via ST-Link V3
+//! C    PORT MUX:SEG ADDR STATE      DEVICE       DESCRIPTION
+//!    2 F    -       0x48 ok         tmp117       Southwest temperature sensor
+//!    2 F    -       0x49 ok         tmp117       South temperature sensor
+//!    2 F    -       0x4a missing    tmp117       Southeast temperature sensor
+//!    3 H    -       0x4c ok         sbtsi        CPU temperature sensor
+//!    3 H    -       0x70 ok         pca9548      I2C mux 1
+//!    3 H    1:1     0x50 ok         at24csw080   U.2 Sharkfin A VPD
+//!    3 H    1:2     0x50 ok         at24csw080   U.2 Sharkfin B VPD
+//!    3 H    1:2     0x38 unexpected -            -
+//!    4 F    -       0x10 ok         adm1272      Fan hot swap controller
+//!
+//! 7 of 8 expected devices found; 1 unexpected device found
+//! ```
+//!
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use clap::Command as ClapCommand;
 use clap::{CommandFactory, Parser};
 use hif::*;
 use humility::core::Core;
 use humility::hubris::*;
 use humility_cmd::hiffy::*;
+use humility_cmd::i2c::{I2cEndian, I2cRegisterAddressing};
 use humility_cmd::{Archive, Args, Attach, Command, Dumper, Validate};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::fs::File;
 use std::io::Read;
@@ -108,6 +226,7 @@ use std::time::Instant;
 
 use indicatif::{HumanBytes, HumanDuration};
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::Deserialize;
 
 #[derive(Parser, Debug, Default)]
 #[clap(name = "i2c", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -130,7 +249,32 @@ pub struct I2cArgs {
         conflicts_with_all = &["scan", "register", "device"],
         parse(try_from_str = parse_int::parse),
     )]
-    scanreg: Option<u8>,
+    scanreg: Option<u16>,
+
+    /// scan every controller, port and mux segment known to the archive,
+    /// comparing the devices found against those that are expected
+    #[clap(long, short = 'T',
+        conflicts_with_all = &[
+            "scan", "scanreg", "bus", "port", "mux", "device", "register",
+            "raw", "write", "writeraw", "flash"
+        ],
+    )]
+    topology: bool,
+
+    /// read a device's registers and decode them according to its register
+    /// map
+    #[clap(long, short = 'D', requires = "device",
+        conflicts_with_all = &[
+            "scan", "scanreg", "raw", "block", "write", "writeraw", "nbytes",
+            "flash", "topology"
+        ],
+    )]
+    decode: bool,
+
+    /// specifies a register map file to decode with, rather than the
+    /// built-in register map for the device
+    #[clap(long, value_name = "filename", requires = "decode")]
+    regmap: Option<String>,
 
     /// specifies an I2C bus by name
     #[clap(long, short, value_name = "bus",
@@ -158,7 +302,19 @@ pub struct I2cArgs {
     #[clap(long, short, value_name = "register",
         parse(try_from_str = parse_int::parse),
     )]
-    register: Option<u8>,
+    register: Option<u16>,
+
+    /// specifies the width of register addresses, in bytes (1 or 2; 2 when
+    /// flashing)
+    #[clap(long, value_name = "bytes",
+        parse(try_from_str = parse_int::parse),
+    )]
+    regwidth: Option<u8>,
+
+    /// specifies the byte order of multi-byte register addresses and values
+    /// (big or little)
+    #[clap(long, value_name = "endian", parse(try_from_str = parse_endian))]
+    endian: Option<I2cEndian>,
 
     /// indicates a raw operation
     #[clap(long, short = 'R', conflicts_with = "register")]
@@ -188,7 +344,8 @@ pub struct I2cArgs {
     )]
     nbytes: Option<u8>,
 
-    /// flash the specified file, assuming two byte addressing
+    /// flash the specified file, assuming two byte addressing unless
+    /// otherwise specified
     #[clap(long, short,
         conflicts_with_all = &[
             "write", "raw", "nbytes", "register", "scan",
@@ -198,6 +355,502 @@ pub struct I2cArgs {
         requires = "device",
     )]
     flash: Option<String>,
+
+    /// read the contents of an EEPROM into the specified file
+    #[clap(long, short = 'o',
+        conflicts_with_all = &[
+            "write", "raw", "nbytes", "register", "scan", "scanreg",
+            "writeraw", "flash", "decode", "topology"
+        ],
+        value_name = "filename",
+        requires_all = &["device", "size"],
+    )]
+    readout: Option<String>,
+
+    /// verify that the contents of an EEPROM match the specified file
+    #[clap(long, short,
+        conflicts_with_all = &[
+            "write", "raw", "nbytes", "register", "scan", "scanreg",
+            "writeraw", "flash", "decode", "topology", "readout"
+        ],
+        value_name = "filename",
+        requires = "device",
+    )]
+    verify: Option<String>,
+
+    /// size of the EEPROM to read, in bytes
+    #[clap(long, value_name = "bytes",
+        parse(try_from_str = parse_int::parse),
+    )]
+    size: Option<u32>,
+
+    /// EEPROM page size, in bytes, for flashing, reading and verifying
+    #[clap(long, value_name = "bytes", default_value = "16",
+        parse(try_from_str = parse_int::parse),
+    )]
+    pagesize: u8,
+}
+
+fn parse_endian(s: &str) -> Result<I2cEndian> {
+    match s {
+        "big" => Ok(I2cEndian::Big),
+        "little" => Ok(I2cEndian::Little),
+        _ => bail!("expected \"big\" or \"little\""),
+    }
+}
+
+//
+// Determines how the device's registers are addressed.  EEPROMs that are
+// flashed, read out or verified are assumed to have 16-bit addresses unless
+// told otherwise.
+//
+fn i2c_addressing(subargs: &I2cArgs) -> Result<I2cRegisterAddressing> {
+    let width = match subargs.regwidth {
+        Some(width) => width,
+        None if subargs.flash.is_some()
+            || subargs.readout.is_some()
+            || subargs.verify.is_some() =>
+        {
+            2
+        }
+        None => 1,
+    };
+
+    if width != 1 && width != 2 {
+        bail!("register width must be 1 or 2 bytes");
+    }
+
+    for register in [subargs.register, subargs.scanreg].iter().flatten() {
+        if width == 1 && *register > u8::MAX.into() {
+            bail!(
+                "register 0x{:x} requires a register width of 2 \
+                (--regwidth 2)",
+                register
+            );
+        }
+    }
+
+    Ok(I2cRegisterAddressing {
+        width,
+        endian: subargs.endian.unwrap_or(I2cEndian::Big),
+    })
+}
+
+//
+// Register maps describe the registers of a part -- and the fields within
+// them -- such that a device can be read and its registers decoded.  They
+// are TOML files, with built-in maps (found in the `regmaps` directory) keyed
+// by the part name of the device as it appears in the archive.
+//
+const I2C_REGMAPS: &[(&str, &str)] = &[
+    ("adt7420", include_str!("../regmaps/adt7420.toml")),
+    ("tmp117", include_str!("../regmaps/tmp117.toml")),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cRegisterMap {
+    #[serde(rename = "register")]
+    registers: Vec<I2cRegister>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cRegister {
+    name: String,
+    address: u16,
+    #[serde(default = "I2cRegister::default_width")]
+    width: u8,
+    #[serde(default)]
+    little_endian: bool,
+    #[serde(default, rename = "field")]
+    fields: Vec<I2cRegisterField>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cRegisterField {
+    name: String,
+    hi: Option<u8>,
+    lo: u8,
+    #[serde(default)]
+    values: BTreeMap<String, String>,
+}
+
+impl I2cRegister {
+    fn default_width() -> u8 {
+        1
+    }
+
+    fn value(&self, bytes: &[u8]) -> u32 {
+        let mut buf = [0u8; 4];
+
+        if self.little_endian {
+            buf[..bytes.len()].copy_from_slice(bytes);
+            u32::from_le_bytes(buf)
+        } else {
+            buf[4 - bytes.len()..].copy_from_slice(bytes);
+            u32::from_be_bytes(buf)
+        }
+    }
+}
+
+impl I2cRegisterField {
+    fn hi(&self) -> u8 {
+        self.hi.unwrap_or(self.lo)
+    }
+
+    fn extract(&self, value: u32) -> u32 {
+        let mask = (1u64 << (self.hi() + 1)) - 1;
+        (value & mask as u32) >> self.lo
+    }
+
+    fn value_name(&self, value: u32) -> Option<&str> {
+        self.values.iter().find_map(|(k, v)| match parse_int::parse::<u32>(k) {
+            Ok(k) if k == value => Some(v.as_str()),
+            _ => None,
+        })
+    }
+}
+
+impl I2cRegisterMap {
+    fn load(part: Option<&str>, filename: Option<&str>) -> Result<Self> {
+        let (what, contents) = match (filename, part) {
+            (Some(filename), _) => {
+                (filename.to_string(), fs::read_to_string(filename)?)
+            }
+            (None, Some(part)) => {
+                match I2C_REGMAPS.iter().find(|(p, _)| *p == part) {
+                    Some((_, contents)) => {
+                        (format!("{} register map", part), contents.to_string())
+                    }
+                    None => {
+                        bail!(
+                            "no register map for {}; specify one with --regmap",
+                            part
+                        );
+                    }
+                }
+            }
+            (None, None) => {
+                bail!("unknown device; specify a register map with --regmap");
+            }
+        };
+
+        Self::parse(&what, &contents)
+    }
+
+    fn parse(what: &str, contents: &str) -> Result<Self> {
+        let map: Self = toml::from_str(contents)
+            .with_context(|| format!("failed to parse {}", what))?;
+
+        for reg in &map.registers {
+            if reg.width == 0 || reg.width > 4 {
+                bail!("{}: {}: width must be 1 to 4 bytes", what, reg.name);
+            }
+
+            for field in &reg.fields {
+                if field.lo > field.hi() || field.hi() >= reg.width * 8 {
+                    bail!(
+                        "{}: {}.{}: invalid bits {}:{}",
+                        what,
+                        reg.name,
+                        field.name,
+                        field.hi(),
+                        field.lo
+                    );
+                }
+
+                for k in field.values.keys() {
+                    if parse_int::parse::<u32>(k).is_err() {
+                        bail!(
+                            "{}: {}.{}: invalid value \"{}\"",
+                            what,
+                            reg.name,
+                            field.name,
+                            k
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(map)
+    }
+}
+
+#[rustfmt::skip::macros(println)]
+fn i2c_decode(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &I2cArgs,
+    hargs: &humility_cmd::i2c::I2cArgs,
+    context: &mut HiffyContext,
+    funcs: &HiffyFunctions,
+    addressing: &I2cRegisterAddressing,
+) -> Result<()> {
+    let func = funcs.get("I2cRead", 7)?;
+
+    let address = match hargs.address {
+        Some(address) => address,
+        None => bail!("expected device"),
+    };
+
+    //
+    // If we were given the device by address, see if the archive knows what
+    // part is there.
+    //
+    let part = match &hargs.device {
+        Some(device) => Some(device.as_str()),
+        None => hubris
+            .manifest
+            .i2c_devices
+            .iter()
+            .find(|d| {
+                d.controller == hargs.controller
+                    && d.port.index == hargs.port.index
+                    && d.mux == hargs.mux.map(|m| m.0)
+                    && d.segment == hargs.mux.map(|m| m.1)
+                    && d.address == address
+            })
+            .map(|d| d.device.as_str()),
+    };
+
+    let map = I2cRegisterMap::load(part, subargs.regmap.as_deref())?;
+
+    let registers = map
+        .registers
+        .iter()
+        .filter(|r| subargs.register.map_or(true, |reg| reg == r.address))
+        .collect::<Vec<_>>();
+
+    if registers.is_empty() {
+        match subargs.register {
+            Some(reg) => bail!("register 0x{:x} not in register map", reg),
+            None => bail!("register map has no registers"),
+        }
+    }
+
+    let base = hargs.base_ops(address);
+
+    let reads = registers
+        .iter()
+        .map(|r| addressing.read_ops(&base, funcs, r.address, Some(r.width)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let results = addressing.run_reads(core, context, reads, 4)?;
+
+    println!("Controller I2C{}, device 0x{:x}{}:\n", hargs.controller,
+        address, part.map(|p| format!(" ({})", p)).unwrap_or_default());
+
+    println!("{:20} {:4} VALUE", "REGISTER", "ADDR");
+
+    for (reg, result) in registers.iter().zip(&results) {
+        print!("{:20} 0x{:02x} ", reg.name, reg.address);
+
+        let value = match result {
+            Ok(bytes) if bytes.len() == reg.width as usize => reg.value(bytes),
+            Ok(bytes) => {
+                println!("short read ({} bytes)", bytes.len());
+                continue;
+            }
+            Err(err) => {
+                println!("Err({})", func.strerror(*err));
+                continue;
+            }
+        };
+
+        println!("0x{:0width$x}", value, width = reg.width as usize * 2);
+
+        if reg.fields.is_empty() {
+            continue;
+        }
+
+        let mut fields = reg.fields.iter().collect::<Vec<_>>();
+        fields.sort_by(|a, b| b.lo.cmp(&a.lo));
+
+        println!("  bits |    value   | field");
+
+        for field in fields {
+            let bits = field.extract(value);
+
+            print!(
+                " {:>2}:{:<2} | 0x{:<8x} | {}",
+                field.hi(),
+                field.lo,
+                bits,
+                field.name
+            );
+
+            match field.value_name(bits) {
+                Some(name) => println!(" ({})", name),
+                None => println!(),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+//
+// Reads the contents of an EEPROM, a page at a time, indicating our progress
+// as we go.
+//
+fn i2c_eeprom_read(
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    funcs: &HiffyFunctions,
+    hargs: &humility_cmd::i2c::I2cArgs,
+    addressing: &I2cRegisterAddressing,
+    pagesize: u8,
+    size: u32,
+) -> Result<Vec<u8>> {
+    let started = Instant::now();
+    let bar = ProgressBar::new(size as u64);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("humility: reading [{bar:30}] {bytes}/{total_bytes}"),
+    );
+
+    let contents = addressing.read_contents(
+        core,
+        context,
+        funcs,
+        hargs,
+        pagesize,
+        size,
+        |nread| bar.set_position(nread as u64),
+    )?;
+
+    bar.finish_and_clear();
+
+    humility::msg!(
+        "read {} in {}",
+        HumanBytes(size as u64),
+        HumanDuration(started.elapsed())
+    );
+
+    Ok(contents)
+}
+
+//
+// Returns each range of bytes (as an offset and a length) that differs
+// between the contents read from an EEPROM and what was expected.
+//
+fn i2c_eeprom_mismatches(expected: &[u8], found: &[u8]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+
+    for (offset, (e, f)) in expected.iter().zip(found).enumerate() {
+        if e == f {
+            continue;
+        }
+
+        match ranges.last_mut() {
+            Some((start, len)) if *start + *len == offset => *len += 1,
+            _ => ranges.push((offset, 1)),
+        }
+    }
+
+    ranges
+}
+
+//
+// Formats the bytes of a mismatched range, showing only the first few.
+//
+fn i2c_eeprom_bytes(buf: &[u8]) -> String {
+    const SHOWN: usize = 8;
+
+    let mut s = buf
+        .iter()
+        .take(SHOWN)
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if buf.len() > SHOWN {
+        s.push_str(" ...");
+    }
+
+    s
+}
+
+//
+// Compares the contents read from an EEPROM with what was expected, reporting
+// each range of bytes that differs.
+//
+#[rustfmt::skip::macros(println)]
+fn i2c_eeprom_verify(expected: &[u8], found: &[u8]) -> Result<()> {
+    let ranges = i2c_eeprom_mismatches(expected, found);
+
+    if ranges.is_empty() {
+        humility::msg!("verified {}", HumanBytes(expected.len() as u64));
+        return Ok(());
+    }
+
+    println!("{:10} {:>6} {:27} {}", "OFFSET", "LENGTH", "EXPECTED", "FOUND");
+
+    for &(start, len) in &ranges {
+        let end = start + len;
+
+        println!("0x{:08x} {:6} {:27} {}", start, len,
+            i2c_eeprom_bytes(&expected[start..end]),
+            i2c_eeprom_bytes(&found[start..end]));
+    }
+
+    bail!(
+        "verification failed: {} bytes differ in {} range{}",
+        ranges.iter().map(|(_, len)| len).sum::<usize>(),
+        ranges.len(),
+        if ranges.len() == 1 { "" } else { "s" }
+    );
+}
+
+//
+// Prints the value read from a register (or a raw read).  If a byte order
+// has been specified, 2- and 4-byte values are also shown as a single value.
+//
+fn i2c_print_read(subargs: &I2cArgs, val: &[u8]) {
+    let value = match (subargs.endian, val.len()) {
+        (Some(I2cEndian::Big), 2) => {
+            Some(u16::from_be_bytes([val[0], val[1]]) as u32)
+        }
+        (Some(I2cEndian::Little), 2) => {
+            Some(u16::from_le_bytes([val[0], val[1]]) as u32)
+        }
+        (Some(I2cEndian::Big), 4) => {
+            Some(u32::from_be_bytes(val.try_into().unwrap()))
+        }
+        (Some(I2cEndian::Little), 4) => {
+            Some(u32::from_le_bytes(val.try_into().unwrap()))
+        }
+        _ => None,
+    };
+
+    match (subargs.nbytes, value) {
+        (Some(n), Some(value)) if n > 1 => {
+            let bytes =
+                val.iter().map(|b| format!("0x{:02x}", b)).collect::<Vec<_>>();
+            println!(
+                "{} (0x{:0width$x})",
+                bytes.join(" "),
+                value,
+                width = val.len() * 2
+            );
+        }
+        (Some(n), _) if n > 2 => {
+            println!();
+            Dumper::new().dump(val, 0);
+        }
+        (Some(2), _) => {
+            println!("0x{:02x} 0x{:02x}", val[0], val[1]);
+        }
+        (Some(1), _) => {
+            println!("0x{:02x}", val[0]);
+        }
+        _ => {
+            println!("Success");
+        }
+    }
 }
 
 fn i2c_done(
@@ -352,21 +1005,7 @@ fn i2c_done(
                 Err(err) => {
                     println!("Err({})", func.strerror(*err));
                 }
-                Ok(val) => match subargs.nbytes {
-                    Some(n) if n > 2 => {
-                        println!();
-                        Dumper::new().dump(val, 0);
-                    }
-                    Some(2) => {
-                        println!("0x{:02x} 0x{:02x}", val[0], val[1]);
-                    }
-                    Some(1) => {
-                        println!("0x{:02x}", val[0]);
-                    }
-                    _ => {
-                        println!("Success");
-                    }
-                },
+                Ok(val) => i2c_print_read(subargs, val),
             }
         }
     } else {
@@ -390,21 +1029,7 @@ fn i2c_done(
                     Dumper::new().dump(val, 0);
                 }
 
-                Ok(val) => match subargs.nbytes {
-                    Some(n) if n > 2 => {
-                        println!();
-                        Dumper::new().dump(val, 0);
-                    }
-                    Some(2) => {
-                        println!("0x{:02x} 0x{:02x}", val[0], val[1])
-                    }
-                    Some(1) => {
-                        println!("0x{:02x}", val[0])
-                    }
-                    _ => {
-                        println!("Success")
-                    }
-                },
+                Ok(val) => i2c_print_read(subargs, val),
             }
         }
     }
@@ -422,6 +1047,254 @@ fn i2c_done(
     Ok(())
 }
 
+//
+// The state of an address on a bus segment, as determined by a topology scan.
+//
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum I2cPresence {
+    Found,
+    Missing,
+    Reserved,
+    TimedOut,
+    Error(String),
+}
+
+impl I2cPresence {
+    fn from_result(
+        func: &HiffyFunction,
+        result: Option<&Result<Vec<u8>, u32>>,
+    ) -> Self {
+        match result {
+            None => I2cPresence::TimedOut,
+            Some(Ok(_)) => I2cPresence::Found,
+            Some(Err(err)) => match func.errmap.get(err).map(String::as_str) {
+                Some("NoDevice") => I2cPresence::Missing,
+                Some("ReservedAddress") => I2cPresence::Reserved,
+                _ => I2cPresence::Error(func.strerror(*err)),
+            },
+        }
+    }
+}
+
+//
+// Scans every segment that we know about -- every port on every controller
+// for which we are the initiator, and every mux segment on which a device is
+// expected -- and reports on the devices that are expected but missing,
+// found but not expected, or that couldn't be scanned.
+//
+#[rustfmt::skip::macros(println)]
+fn i2c_topology(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &I2cArgs,
+    context: &mut HiffyContext,
+    func: &HiffyFunction,
+) -> Result<()> {
+    let manifest = &hubris.manifest;
+
+    if manifest.i2c_buses.is_empty() {
+        bail!("no I2C buses found; is this an old Hubris image?");
+    }
+
+    let target = manifest
+        .i2c_buses
+        .iter()
+        .filter(|bus| bus.target)
+        .map(|bus| bus.controller)
+        .collect::<HashSet<_>>();
+
+    let wanted = |controller: u8| {
+        !target.contains(&controller)
+            && subargs.controller.map_or(true, |c| c == controller)
+    };
+
+    //
+    // Assemble every segment that we're going to scan, keyed by controller,
+    // port index and mux/segment (if any).
+    //
+    let mut segments = BTreeMap::new();
+
+    for bus in &manifest.i2c_buses {
+        if wanted(bus.controller) {
+            segments.insert((bus.controller, bus.port.index, None), &bus.port);
+        }
+    }
+
+    for d in &manifest.i2c_devices {
+        if !wanted(d.controller) {
+            continue;
+        }
+
+        let mux = match (d.mux, d.segment) {
+            (Some(m), Some(s)) => Some((m, s)),
+            _ => None,
+        };
+
+        segments.insert((d.controller, d.port.index, mux), &d.port);
+    }
+
+    if segments.is_empty() {
+        bail!("no I2C segments to scan");
+    }
+
+    let expected = |controller: u8, port: u8, mux: Option<(u8, u8)>| {
+        manifest.i2c_devices.iter().filter(move |d| {
+            d.controller == controller
+                && d.port.index == port
+                && d.mux == mux.map(|m| m.0)
+                && d.segment == mux.map(|m| m.1)
+        })
+    };
+
+    println!("{:4} {:4} {:7} {:4} {:10} {:12} {}",
+        "C", "PORT", "MUX:SEG", "ADDR", "STATE", "DEVICE", "DESCRIPTION");
+
+    let mut nexpected = 0;
+    let mut nfound = 0;
+    let mut nunexpected = 0;
+
+    //
+    // The devices found on each port itself (that is, not on a mux segment),
+    // keyed by controller and port index.  Because we scan a port before its
+    // segments, these are known by the time we scan the segments.
+    //
+    let mut found = HashMap::new();
+
+    for (&(controller, index, mux), port) in &segments {
+        let mut ops = vec![Op::Push(controller), Op::Push(index)];
+
+        if let Some((m, s)) = mux {
+            ops.push(Op::Push(m));
+            ops.push(Op::Push(s));
+        } else {
+            ops.push(Op::PushNone);
+            ops.push(Op::PushNone);
+        }
+
+        ops.push(Op::PushNone);
+        ops.push(Op::Push(0));
+        ops.push(Op::PushNone);
+        ops.push(Op::Label(Target(0)));
+        ops.push(Op::Drop);
+        ops.push(Op::Swap);
+        ops.push(Op::Push(1));
+        ops.push(Op::Call(func.id));
+        ops.push(Op::Drop);
+        ops.push(Op::Swap);
+        ops.push(Op::Push(1));
+        ops.push(Op::Add);
+        ops.push(Op::Push(128));
+        ops.push(Op::BranchGreaterThanOrEqualTo(Target(0)));
+        ops.push(Op::Done);
+
+        let results = context.run(core, ops.as_slice(), None)?;
+        let presence = |addr: u8| {
+            I2cPresence::from_result(func, results.get(addr as usize))
+        };
+
+        let muxseg = match mux {
+            Some((m, s)) => format!("{}:{}", m, s),
+            None => "-".to_string(),
+        };
+
+        let mut devices = expected(controller, index, mux)
+            .map(|d| (d.address, d.device.as_str(), d.description.clone()))
+            .collect::<Vec<_>>();
+
+        //
+        // A port's muxes are themselves devices on the port.
+        //
+        let muxes = || {
+            manifest
+                .i2c_buses
+                .iter()
+                .filter(|bus| {
+                    bus.controller == controller && bus.port.index == index
+                })
+                .flat_map(|bus| bus.muxes.iter())
+        };
+
+        if mux.is_none() {
+            devices.extend(muxes().map(|m| {
+                (m.address, m.driver.as_str(), format!("I2C mux {}", m.id))
+            }));
+        }
+
+        devices.sort_by_key(|d| d.0);
+
+        for (address, device, description) in &devices {
+            let state = match presence(*address) {
+                I2cPresence::Found => {
+                    nfound += 1;
+                    "ok".to_string()
+                }
+                I2cPresence::Missing => "missing".to_string(),
+                I2cPresence::Reserved => "reserved".to_string(),
+                I2cPresence::TimedOut => "timed out".to_string(),
+                I2cPresence::Error(err) => err,
+            };
+
+            nexpected += 1;
+
+            println!("{:4} {:4} {:7} 0x{:02x} {:10} {:12} {}",
+                controller, port.name, muxseg, address, state, device,
+                description);
+        }
+
+        //
+        // Any device that we find that isn't expected is unexpected -- unless
+        // we are on a mux segment, in which case the devices on the port
+        // itself (including the muxes) will also be found.  We excuse both
+        // the devices expected on the port and those actually found on it,
+        // lest one unexpected device be reported on every segment.
+        //
+        let known = devices
+            .iter()
+            .map(|d| d.0)
+            .chain(mux.iter().flat_map(|_| {
+                expected(controller, index, None)
+                    .map(|d| d.address)
+                    .chain(muxes().map(|m| m.address))
+            }))
+            .collect::<HashSet<_>>();
+
+        let base =
+            found.entry((controller, index)).or_insert_with(HashSet::new);
+
+        for addr in 0..128u8 {
+            if presence(addr) != I2cPresence::Found || known.contains(&addr) {
+                continue;
+            }
+
+            if mux.is_none() {
+                base.insert(addr);
+            } else if base.contains(&addr) {
+                continue;
+            }
+
+            nunexpected += 1;
+
+            println!("{:4} {:4} {:7} 0x{:02x} {:10} {:12} {}",
+                controller, port.name, muxseg, addr, "unexpected", "-", "-");
+        }
+
+        if results.len() < 128 && devices.is_empty() {
+            println!("{:4} {:4} {:7} {:4} {:10} {:12} {}",
+                controller, port.name, muxseg, "-", "timed out", "-", "-");
+        }
+    }
+
+    println!(
+        "\n{} of {} expected devices found; {} unexpected device{} found",
+        nfound,
+        nexpected,
+        nunexpected,
+        if nunexpected == 1 { "" } else { "s" }
+    );
+
+    Ok(())
+}
+
 fn i2c(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
@@ -435,13 +1308,23 @@ fn i2c(
         && subargs.register.is_none()
         && !subargs.raw
         && subargs.flash.is_none()
+        && !subargs.topology
+        && !subargs.decode
+        && subargs.readout.is_none()
+        && subargs.verify.is_none()
     {
         bail!(
-            "must indicate a scan (-s/-S), specify a register (-r), \
-            indicate raw (-R) or flash (-f)"
+            "must indicate a scan (-s/-S/-T), specify a register (-r), \
+            indicate raw (-R), decode (-D), flash (-f), read out (-o) \
+            or verify (-v)"
         );
     }
 
+    if subargs.pagesize == 0 {
+        bail!("page size must be non-zero");
+    }
+
+    let addressing = i2c_addressing(&subargs)?;
     let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
 
     let (fname, args) = if subargs.flash.is_some() {
@@ -456,6 +1339,10 @@ fn i2c(
     let funcs = context.functions()?;
     let func = funcs.get(fname, args)?;
 
+    if subargs.topology {
+        return i2c_topology(hubris, core, &subargs, &mut context, func);
+    }
+
     let hargs = humility_cmd::i2c::I2cArgs::parse(
         hubris,
         &subargs.bus,
@@ -465,6 +1352,105 @@ fn i2c(
         &subargs.device,
     )?;
 
+    if subargs.decode {
+        return i2c_decode(
+            hubris,
+            core,
+            &subargs,
+            &hargs,
+            &mut context,
+            &funcs,
+            &addressing,
+        );
+    }
+
+    if let Some(ref filename) = subargs.readout {
+        let contents = i2c_eeprom_read(
+            core,
+            &mut context,
+            &funcs,
+            &hargs,
+            &addressing,
+            subargs.pagesize,
+            subargs.size.unwrap(),
+        )?;
+
+        fs::write(filename, &contents)?;
+        humility::msg!("contents written to {}", filename);
+
+        return Ok(());
+    }
+
+    if let Some(ref filename) = subargs.verify {
+        let expected = fs::read(filename)?;
+
+        if let Some(size) = subargs.size {
+            if size as usize != expected.len() {
+                bail!("{} is {} bytes, not {}", filename, expected.len(), size);
+            }
+        }
+
+        let contents = i2c_eeprom_read(
+            core,
+            &mut context,
+            &funcs,
+            &hargs,
+            &addressing,
+            subargs.pagesize,
+            expected.len() as u32,
+        )?;
+
+        return i2c_eeprom_verify(&expected, &contents);
+    }
+
+    //
+    // Reads of registers with addresses wider than a byte -- including
+    // register scans -- are batches of writes of the register address
+    // followed by reads.
+    //
+    if addressing.width > 1
+        && subargs.write.is_none()
+        && !subargs.writeraw
+        && (subargs.register.is_some()
+            || (subargs.scan && hargs.address.is_some())
+            || subargs.scanreg.is_some())
+    {
+        let nbytes = if subargs.block {
+            None
+        } else {
+            Some(subargs.nbytes.unwrap_or(1))
+        };
+        let size = nbytes.unwrap_or(u8::MAX) as usize;
+
+        let reads = match (hargs.address, subargs.scanreg) {
+            (Some(address), None) if subargs.scan => {
+                let base = hargs.base_ops(address);
+
+                (0..=u8::MAX)
+                    .map(|r| {
+                        addressing.read_ops(&base, &funcs, r.into(), nbytes)
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            }
+            (Some(address), None) => {
+                let base = hargs.base_ops(address);
+                let register = subargs.register.unwrap();
+                vec![addressing.read_ops(&base, &funcs, register, nbytes)?]
+            }
+            (None, Some(register)) => (0..128)
+                .map(|address| {
+                    let base = hargs.base_ops(address);
+                    addressing.read_ops(&base, &funcs, register, nbytes)
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => bail!("expected device"),
+        };
+
+        let results = addressing.run_reads(core, &mut context, reads, size)?;
+
+        return i2c_done(&subargs, &hargs, &results, func);
+    }
+
     let mut ops = vec![Op::Push(hargs.controller)];
 
     ops.push(Op::Push(hargs.port.index));
@@ -498,8 +1484,12 @@ fn i2c(
         // support as many variantss as we can despite its substantial
         // effect on performance.
         //
-        let addr_size = 2;
-        let block_size = 16u16;
+        if addressing.width == 1 && filelen > u8::MAX.into() {
+            bail!("file is too large for 8-bit register addresses");
+        }
+
+        let addr_size = addressing.width as u16;
+        let block_size = subargs.pagesize as u16;
         let nibble_size = block_size + addr_size;
 
         let data_size = context.data_size();
@@ -541,11 +1531,11 @@ fn i2c(
                     block_size
                 };
 
-                // Plop in our address, 16-bit big endian
-                let b = offset.to_be_bytes();
-                buf[noffs] = b[0];
-                buf[noffs + 1] = b[1];
-                noffs += 2;
+                // Plop in our address
+                for b in addressing.bytes(offset) {
+                    buf[noffs] = b;
+                    noffs += 1;
+                }
 
                 // Read the file contents
                 file.read_exact(&mut buf[noffs..noffs + len as usize])?;
@@ -624,14 +1614,24 @@ fn i2c(
         }
 
         if let Some(ref write) = subargs.write {
-            if let Some(register) = subargs.register {
-                ops.push(Op::Push(register));
-            } else {
-                ops.push(Op::PushNone);
+            let mut arr = vec![];
+
+            //
+            // If our register address is wider than a byte, it is sent as
+            // the leading bytes of our payload.
+            //
+            match subargs.register {
+                Some(register) if addressing.width == 1 => {
+                    ops.push(Op::Push(register as u8));
+                }
+                Some(register) => {
+                    ops.push(Op::PushNone);
+                    arr.extend(addressing.bytes(register));
+                }
+                None => ops.push(Op::PushNone),
             }
 
             let bytes: Vec<&str> = write.split(',').collect();
-            let mut arr = vec![];
 
             for byte in &bytes {
                 if let Ok(val) = parse_int::parse::<u8>(byte) {
@@ -649,14 +1649,20 @@ fn i2c(
         } else if subargs.writeraw {
             //
             // We know that we have a register when -W has been specified; use
-            // this as our 1-byte payload and set our register to None
+            // this as our payload and set our register to None
             //
+            let bytes = addressing.bytes(subargs.register.unwrap());
+
             ops.push(Op::PushNone);
-            ops.push(Op::Push(subargs.register.unwrap()));
-            ops.push(Op::Push(1));
+
+            for byte in &bytes {
+                ops.push(Op::Push(*byte));
+            }
+
+            ops.push(Op::Push(bytes.len() as u8));
         } else {
             if let Some(register) = subargs.register {
-                ops.push(Op::Push(register));
+                ops.push(Op::Push(register as u8));
             } else {
                 ops.push(Op::PushNone);
             }
@@ -684,7 +1690,7 @@ fn i2c(
         ops.push(Op::BranchGreaterThanOrEqualTo(Target(0)));
     } else {
         match subargs.scanreg {
-            Some(reg) => ops.push(Op::Push(reg)),
+            Some(reg) => ops.push(Op::Push(reg as u8)),
             None => ops.push(Op::PushNone),
         }
 
@@ -724,3 +1730,200 @@ pub fn init() -> (Command, ClapCommand<'static>) {
         I2cArgs::command(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(hi: Option<u8>, lo: u8) -> I2cRegisterField {
+        I2cRegisterField {
+            name: "FIELD".to_string(),
+            hi,
+            lo,
+            values: BTreeMap::new(),
+        }
+    }
+
+    fn register(width: u8, little_endian: bool) -> I2cRegister {
+        I2cRegister {
+            name: "REG".to_string(),
+            address: 0,
+            width,
+            little_endian,
+            fields: vec![],
+        }
+    }
+
+    fn regmap(register: &str) -> Result<I2cRegisterMap> {
+        I2cRegisterMap::parse("test", &format!("[[register]]\n{}", register))
+    }
+
+    #[test]
+    fn regmap_builtin() {
+        for (part, _) in I2C_REGMAPS {
+            I2cRegisterMap::load(Some(*part), None).unwrap();
+        }
+
+        assert!(I2cRegisterMap::load(Some("nonexistent"), None).is_err());
+        assert!(I2cRegisterMap::load(None, None).is_err());
+    }
+
+    #[test]
+    fn regmap_parse() {
+        let map = regmap(
+            r#"
+            name = "CONFIG"
+            address = 0x1234
+            width = 2
+            little_endian = true
+
+            [[register.field]]
+            name = "MODE"
+            hi = 15
+            lo = 14
+            values = { 0 = "off", 0x3 = "on" }
+
+            [[register.field]]
+            name = "READY"
+            lo = 0
+            "#,
+        )
+        .unwrap();
+
+        let reg = &map.registers[0];
+        assert_eq!(reg.name, "CONFIG");
+        assert_eq!(reg.address, 0x1234);
+        assert_eq!(reg.width, 2);
+        assert!(reg.little_endian);
+        assert_eq!(reg.fields.len(), 2);
+        assert_eq!(reg.fields[1].hi(), 0);
+
+        let map = regmap("name = \"STATUS\"\naddress = 2").unwrap();
+        assert_eq!(map.registers[0].width, 1);
+        assert!(!map.registers[0].little_endian);
+    }
+
+    #[test]
+    fn regmap_malformed() {
+        for register in [
+            "",
+            "name = \"R\"",
+            "address = 0",
+            "name = \"R\"\naddress = -1",
+            "name = \"R\"\naddress = 0x10000",
+            "name = \"R\"\naddress = 0\nwidth = 0",
+            "name = \"R\"\naddress = 0\nwidth = 5",
+            "name = \"R\"\naddress = 0\nbogus = 1",
+        ] {
+            assert!(regmap(register).is_err(), "{:?}", register);
+        }
+
+        assert!(I2cRegisterMap::parse("test", "[[register]").is_err());
+    }
+
+    #[test]
+    fn regmap_field_bounds() {
+        let with_field = |width: u8, bits: &str| {
+            regmap(&format!(
+                "name = \"R\"\naddress = 0\nwidth = {}\n\
+                [[register.field]]\nname = \"F\"\n{}",
+                width, bits
+            ))
+        };
+
+        assert!(with_field(1, "hi = 7\nlo = 0").is_ok());
+        assert!(with_field(1, "lo = 7").is_ok());
+        assert!(with_field(1, "hi = 8\nlo = 0").is_err());
+        assert!(with_field(1, "lo = 8").is_err());
+        assert!(with_field(2, "hi = 15\nlo = 8").is_ok());
+        assert!(with_field(2, "lo = 16").is_err());
+        assert!(with_field(4, "hi = 31\nlo = 0").is_ok());
+        assert!(with_field(4, "hi = 32\nlo = 0").is_err());
+        assert!(with_field(1, "hi = 2\nlo = 3").is_err());
+        assert!(with_field(1, "hi = 256\nlo = 0").is_err());
+        assert!(
+            with_field(1, "hi = 3\nlo = 0\nvalues = { x = \"bad\" }").is_err()
+        );
+        assert!(with_field(1, "hi = 3\nlo = 0\nbogus = 1").is_err());
+    }
+
+    #[test]
+    fn register_value() {
+        assert_eq!(register(1, false).value(&[0x12]), 0x12);
+        assert_eq!(register(2, false).value(&[0x12, 0x34]), 0x1234);
+        assert_eq!(register(2, true).value(&[0x12, 0x34]), 0x3412);
+        assert_eq!(register(3, false).value(&[0x12, 0x34, 0x56]), 0x123456);
+        assert_eq!(register(3, true).value(&[0x12, 0x34, 0x56]), 0x563412);
+        assert_eq!(
+            register(4, false).value(&[0x12, 0x34, 0x56, 0x78]),
+            0x12345678
+        );
+        assert_eq!(
+            register(4, true).value(&[0x12, 0x34, 0x56, 0x78]),
+            0x78563412
+        );
+    }
+
+    #[test]
+    fn field_extract() {
+        assert_eq!(field(None, 0).extract(0xffff_fffe), 0);
+        assert_eq!(field(None, 0).extract(0x1), 1);
+        assert_eq!(field(None, 7).extract(0x80), 1);
+        assert_eq!(field(None, 8).extract(0x80), 0);
+        assert_eq!(field(None, 31).extract(0x8000_0000), 1);
+        assert_eq!(field(None, 31).extract(0x7fff_ffff), 0);
+
+        assert_eq!(field(Some(7), 0).extract(0x1ff), 0xff);
+        assert_eq!(field(Some(15), 8).extract(0x1_abcd), 0xab);
+        assert_eq!(field(Some(11), 10).extract(0x0c00), 0x3);
+        assert_eq!(field(Some(23), 16).extract(0xff_5a_0000), 0x5a);
+        assert_eq!(field(Some(31), 0).extract(0xffff_ffff), 0xffff_ffff);
+        assert_eq!(field(Some(31), 24).extract(0xa500_0000), 0xa5);
+    }
+
+    #[test]
+    fn field_value_name() {
+        let mut f = field(Some(1), 0);
+        f.values.insert("0".to_string(), "off".to_string());
+        f.values.insert("0x3".to_string(), "on".to_string());
+
+        assert_eq!(f.value_name(0), Some("off"));
+        assert_eq!(f.value_name(3), Some("on"));
+        assert_eq!(f.value_name(1), None);
+    }
+
+    #[test]
+    fn eeprom_mismatches() {
+        let expected = [0u8, 1, 2, 3, 4, 5, 6, 7];
+
+        assert!(i2c_eeprom_mismatches(&expected, &expected).is_empty());
+
+        //
+        // Contiguous differences are a single range; separate differences
+        // are separate ranges.
+        //
+        let found = [0u8, 0xff, 0xff, 0xff, 4, 5, 6, 7];
+        assert_eq!(i2c_eeprom_mismatches(&expected, &found), [(1, 3)]);
+
+        let found = [0xffu8, 1, 0xff, 3, 4, 5, 0xff, 0xff];
+        assert_eq!(
+            i2c_eeprom_mismatches(&expected, &found),
+            [(0, 1), (2, 1), (6, 2)]
+        );
+    }
+
+    #[test]
+    fn eeprom_bytes() {
+        assert_eq!(i2c_eeprom_bytes(&[]), "");
+        assert_eq!(i2c_eeprom_bytes(&[0x1, 0xab]), "01 ab");
+        assert_eq!(i2c_eeprom_bytes(&[0u8; 8]), "00 00 00 00 00 00 00 00");
+
+        //
+        // Only the first 8 bytes are shown.
+        //
+        assert_eq!(
+            i2c_eeprom_bytes(&(0u8..12).collect::<Vec<_>>()),
+            "00 01 02 03 04 05 06 07 ..."
+        );
+    }
+}
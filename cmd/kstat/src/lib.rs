@@ -0,0 +1,306 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility kstat`
+//!
+//! `humility kstat` displays kernel-level state:  the interrupts that are
+//! bound to tasks (along with their state in the NVIC), the regions that
+//! each task may access, and the timers that tasks have pending.
+//!
+//! ```console
+//! % humility kstat
+//! humility: attached via ST-Link V3
+//! task table = 0x20000400, 12 tasks
+//! system time = 1764993
+//!
+//! INTERRUPTS
+//!  IRQ TASK               NOTIFICATION ENABLED PENDING ACTIVE
+//!   39 usart_driver       bit0         yes     no      no
+//!   31 i2c_driver         bit0         yes     no      no
+//!   32 i2c_driver         bit0         yes     no      no
+//!
+//! REGIONS
+//! ID TASK               DESC       BASE       SIZE       ATTR
+//!  0 jefe               0x08000f14 0x08004000 0x00004000 r-x--
+//!  0 jefe               0x08000f20 0x20001000 0x00001000 rw---
+//!  1 rcc_driver         0x08000f2c 0x08008000 0x00002000 r-x--
+//!  1 rcc_driver         0x08000f38 0x20002000 0x00000400 rw---
+//!  1 rcc_driver         0x08000f44 0x40023800 0x00000400 rw-d-
+//! ...
+//!
+//! TIMERS
+//! ID TASK                 DEADLINE NOTIFICATION
+//!  0 jefe                      T+7 bit0 bit1
+//!  9 hiffy                     T+7 bit0
+//! 10 hf                       T+18 bit0
+//! ```
+//!
+//! The region attributes are read (`r`), write (`w`), execute (`x`), device
+//! (`d`) and DMA (`m`).  To display only some of this state, use any of
+//! `-i` (`--irqs`), `-r` (`--regions`), or `-t` (`--timers`); to display the
+//! state for a single task, specify it.
+//!
+//! When run on a dump, the NVIC state is only displayed if the dump
+//! includes it; otherwise, it is displayed as `-`.
+//!
+
+use anyhow::{bail, Result};
+use clap::Command as ClapCommand;
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cmd::doppel::{RegionDesc, Slice, Task, TaskDesc};
+use humility_cmd::reflect::{self, Load, Ptr};
+use humility_cmd::{Archive, Args, Attach, Command, Validate};
+use humility_cortex::nvic::NvicInterrupt;
+
+#[derive(Parser, Debug)]
+#[clap(name = "kstat", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct KstatArgs {
+    /// show interrupt bindings
+    #[clap(long, short)]
+    irqs: bool,
+
+    /// show task regions
+    #[clap(long, short)]
+    regions: bool,
+
+    /// show pending timers
+    #[clap(long, short)]
+    timers: bool,
+
+    /// single task to display
+    task: Option<String>,
+}
+
+struct KstatTask {
+    index: u32,
+    name: String,
+    task: Task,
+    regions: Option<Vec<(u32, RegionDesc)>>,
+}
+
+//
+// The NVIC state for an interrupt is `None` if it could not be read -- e.g.,
+// on a dump that did not capture it.
+//
+struct KstatIrq {
+    irq: u32,
+    task: String,
+    notification: u32,
+    nvic: Option<NvicInterrupt>,
+}
+
+//
+// Reads the region table for a task, returning the address of each
+// descriptor along with the descriptor itself.  Kernels that don't have a
+// region table in the task structure result in `None`.
+//
+fn kstat_regions(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    task: &reflect::Value,
+) -> Result<Option<Vec<(u32, RegionDesc)>>> {
+    let table = match task.as_struct()?.get("region_table") {
+        Some(table) => Slice::from_value(table)?,
+        None => return Ok(None),
+    };
+
+    let mut rval = vec![];
+
+    for ptr in table.load_from::<Ptr>(hubris, core)? {
+        let desc: RegionDesc = ptr.load_from(hubris, core)?;
+
+        if desc.base != 0 {
+            rval.push((ptr.addr(), desc));
+        }
+    }
+
+    Ok(Some(rval))
+}
+
+fn kstat_notification(mask: u32) -> String {
+    (0..32)
+        .filter(|i| mask & (1 << i) != 0)
+        .map(|i| format!("bit{}", i))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn kstat_bit(bit: Option<bool>) -> &'static str {
+    match bit {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "-",
+    }
+}
+
+//
+// Takes our snapshot of kernel state with the target halted, reading the
+// entire task table at a go.
+//
+fn kstat_halted(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &KstatArgs,
+    base: u32,
+    task_count: u32,
+) -> Result<(u64, Vec<KstatTask>, Vec<KstatIrq>)> {
+    let all = !subargs.irqs && !subargs.regions && !subargs.timers;
+    let task_t = hubris.lookup_struct_byname("Task")?;
+    let ticks = core.read_word_64(hubris.lookup_variable("TICKS")?.addr)?;
+    let mut taskblock = vec![0; task_t.size * task_count as usize];
+    core.read_8(base, &mut taskblock)?;
+
+    let mut tasks = vec![];
+
+    for i in 0..task_count {
+        let offs = i as usize * task_t.size;
+        let value: reflect::Value =
+            reflect::load(hubris, &taskblock, task_t, offs)?;
+        let task = Task::from_value(&value)?;
+        let desc: TaskDesc = task.descriptor.load_from(hubris, core)?;
+        let name = hubris.instr_mod(desc.entry_point).unwrap_or("<unknown>");
+
+        if let Some(ref t) = subargs.task {
+            if t != name {
+                continue;
+            }
+        }
+
+        let regions = if all || subargs.regions {
+            kstat_regions(hubris, core, &value)?
+        } else {
+            None
+        };
+
+        tasks.push(KstatTask {
+            index: i,
+            name: name.to_string(),
+            task,
+            regions,
+        });
+    }
+
+    let mut irqs = vec![];
+
+    for t in &tasks {
+        if let Some(bindings) = hubris.manifest.task_irqs.get(&t.name) {
+            for &(irq, notification) in bindings {
+                irqs.push(KstatIrq {
+                    irq,
+                    task: t.name.clone(),
+                    notification,
+                    nvic: NvicInterrupt::read(core, irq).ok(),
+                });
+            }
+        }
+    }
+
+    Ok((ticks, tasks, irqs))
+}
+
+#[rustfmt::skip::macros(println)]
+fn kstat(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    _args: &Args,
+    subargs: &[String],
+) -> Result<()> {
+    let subargs = KstatArgs::try_parse_from(subargs)?;
+    let all = !subargs.irqs && !subargs.regions && !subargs.timers;
+
+    let base = core.read_word_32(hubris.lookup_symword("TASK_TABLE_BASE")?)?;
+    let task_count =
+        core.read_word_32(hubris.lookup_symword("TASK_TABLE_SIZE")?)?;
+
+    if let Some(ref task) = subargs.task {
+        if hubris.lookup_task(task).is_none() {
+            bail!("\"{}\" is not a valid task", task);
+        }
+    }
+
+    core.halt()?;
+    let rval = kstat_halted(hubris, core, &subargs, base, task_count);
+    core.run()?;
+
+    let (ticks, tasks, irqs) = rval?;
+
+    println!("task table = 0x{:08x}, {} tasks", base, task_count);
+    println!("system time = {}", ticks);
+
+    if all || subargs.irqs {
+        println!("\nINTERRUPTS");
+        println!("{:>4} {:18} {:12} {:7} {:7} {:6}",
+            "IRQ", "TASK", "NOTIFICATION", "ENABLED", "PENDING", "ACTIVE");
+
+        for irq in &irqs {
+            println!("{:4} {:18} {:12} {:7} {:7} {:6}",
+                irq.irq,
+                irq.task,
+                kstat_notification(irq.notification),
+                kstat_bit(irq.nvic.map(|n| n.enabled)),
+                kstat_bit(irq.nvic.map(|n| n.pending)),
+                kstat_bit(irq.nvic.map(|n| n.active))
+            );
+        }
+    }
+
+    if all || subargs.regions {
+        println!("\nREGIONS");
+        println!("{:2} {:18} {:10} {:10} {:10} {:5}",
+            "ID", "TASK", "DESC", "BASE", "SIZE", "ATTR");
+
+        for t in &tasks {
+            let regions = match &t.regions {
+                Some(regions) => regions,
+                None => {
+                    println!("{:2} {:18} <no region table>", t.index, t.name);
+                    continue;
+                }
+            };
+
+            for (daddr, desc) in regions {
+                println!("{:2} {:18} 0x{:08x} 0x{:08x} 0x{:08x} {}",
+                    t.index, t.name, daddr, desc.base, desc.size,
+                    desc.attributes
+                );
+            }
+        }
+    }
+
+    if all || subargs.timers {
+        println!("\nTIMERS");
+        println!("{:2} {:18} {:>10} {}",
+            "ID", "TASK", "DEADLINE", "NOTIFICATION");
+
+        for t in &tasks {
+            let timer = &t.task.timer;
+
+            if let Some(deadline) = timer.deadline {
+                let delta = deadline.0 as i64 - ticks as i64;
+
+                println!("{:2} {:18} {:>10} {}",
+                    t.index, t.name, format!("T{:+}", delta),
+                    kstat_notification(timer.to_post.0)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn init() -> (Command, ClapCommand<'static>) {
+    (
+        Command::Attached {
+            name: "kstat",
+            archive: Archive::Required,
+            attach: Attach::Any,
+            validate: Validate::Booted,
+            run: kstat,
+        },
+        KstatArgs::command(),
+    )
+}
@@ -681,7 +681,7 @@ fn summarize(
         ]);
     }
 
-    let mut ops = vec![];
+    let mut batch = HiffyBatch::new();
     let mut work = vec![];
 
     for device in &hubris.manifest.i2c_devices {
@@ -693,26 +693,31 @@ fn summarize(
 
             let harg = I2cArgs::from_device(device);
 
-            ops.push(Op::Push(harg.controller));
-            ops.push(Op::Push(harg.port.index));
-
-            if let Some(mux) = harg.mux {
-                ops.push(Op::Push(mux.0));
-                ops.push(Op::Push(mux.1));
-            } else {
-                ops.push(Op::PushNone);
-                ops.push(Op::PushNone);
-            }
-
-            ops.push(Op::Push(harg.address.unwrap()));
-
             //
-            // We have the arguments for our device pushed.  Now iterate over
-            // each rail, selecting it as needed...
+            // Each rail is its own program, which we batch together.
             //
             for (rnum, rail) in rails.iter().enumerate() {
+                let mut ops = vec![];
                 let mut calls = vec![];
+                let mut rsize = 0;
+
+                ops.push(Op::Push(harg.controller));
+                ops.push(Op::Push(harg.port.index));
+
+                if let Some(mux) = harg.mux {
+                    ops.push(Op::Push(mux.0));
+                    ops.push(Op::Push(mux.1));
+                } else {
+                    ops.push(Op::PushNone);
+                    ops.push(Op::PushNone);
+                }
+
+                ops.push(Op::Push(harg.address.unwrap()));
 
+                //
+                // We have the arguments for our device pushed.  Now select
+                // our rail as needed...
+                //
                 if rails.len() > 1 {
                     ops.push(Op::Push(page));
                     ops.push(Op::Push(rnum as u8));
@@ -728,11 +733,13 @@ fn summarize(
                 //
                 for (code, _) in &commands {
                     driver.command(*code, |cmd| {
-                        let op = match cmd.read_op() {
-                            pmbus::Operation::ReadByte => Op::Push(1),
-                            pmbus::Operation::ReadWord => Op::Push(2),
-                            pmbus::Operation::ReadWord32 => Op::Push(4),
-                            pmbus::Operation::ReadBlock => Op::PushNone,
+                        let (op, size) = match cmd.read_op() {
+                            pmbus::Operation::ReadByte => (Op::Push(1), 1),
+                            pmbus::Operation::ReadWord => (Op::Push(2), 2),
+                            pmbus::Operation::ReadWord32 => (Op::Push(4), 4),
+                            pmbus::Operation::ReadBlock => {
+                                (Op::PushNone, u8::MAX as usize)
+                            }
                             _ => {
                                 return;
                             }
@@ -743,20 +750,18 @@ fn summarize(
                         ops.push(Op::Call(func.id));
                         ops.push(Op::DropN(2));
                         calls.push(*code as u8);
+                        rsize += size;
                     });
                 }
 
+                ops.push(Op::DropN(5));
+                batch.push(ops, rsize);
                 work.push((device, driver, rail, calls));
             }
-
-            ops.push(Op::DropN(5));
         }
     }
 
-    ops.push(Op::Done);
-
-    let results = context.run(core, ops.as_slice(), None)?;
-    let mut base = 0;
+    let results = context.run_batch(core, &batch)?;
 
     print!("{:13} {:16} {:3} {:4}", "DEVICE", "RAIL", "PG?", "#FLT");
 
@@ -768,19 +773,10 @@ fn summarize(
 
     println!();
 
-    for (device, driver, rail, calls) in &work {
+    for ((device, driver, rail, calls), results) in work.iter().zip(&results) {
         summarize_rail(
-            subargs,
-            device,
-            driver,
-            rail,
-            calls,
-            &results[base..base + calls.len()],
-            func,
-            width,
+            subargs, device, driver, rail, calls, results, func, width,
         )?;
-
-        base += calls.len();
     }
 
     Ok(())
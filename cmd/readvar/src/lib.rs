@@ -35,12 +35,32 @@
 //!     })
 //! ```
 //!
+//! To read only part of a variable, specify a path within it, using `.` to
+//! denote a member of a structure (or, as `.0`, a member of a tuple), `[]`
+//! to denote an element of an array, and `.` followed by a variant to denote
+//! the contents of an enum.  Pointers within the path are followed, and a
+//! leading `*` dereferences a pointer that the path itself denotes:
+//!
+//! ```console
+//! % humility readvar CURRENT_TASK_PTR.Some.0.pointer.priority
+//! humility: attached via ST-Link
+//! CURRENT_TASK_PTR.Some.0.pointer.priority (0x2000055c) = Priority(0x2)
+//! % humility readvar 'TASK_STATE.ports[3].status.link_up'
+//! humility: attached via ST-Link
+//! TASK_STATE.ports[3].status.link_up (0x20003c49) = true
+//! ```
+//!
+//! If a variable of the specified name is present in more than one task,
+//! it is read in each; to read only one, qualify the name with the path of
+//! its task (e.g., `task_thermal::THERMAL_RINGBUF`).
+//!
 
 use anyhow::{bail, Result};
 use clap::Command as ClapCommand;
 use clap::{CommandFactory, Parser};
 use humility::core::Core;
 use humility::hubris::*;
+use humility_cmd::varpath::VariablePath;
 use humility_cmd::{Archive, Args, Attach, Command, Validate};
 
 #[derive(Parser, Debug)]
@@ -52,6 +72,7 @@ struct ReadvarArgs {
     /// list variables
     #[clap(long, short)]
     list: bool,
+    /// variable to read, optionally followed by a path within it
     #[clap(conflicts_with = "list")]
     variable: Option<String>,
 }
@@ -59,16 +80,29 @@ struct ReadvarArgs {
 fn readvar_dump(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
+    path: &VariablePath,
     variable: &HubrisVariable,
     subargs: &ReadvarArgs,
 ) -> Result<()> {
-    let mut buf: Vec<u8> = vec![];
-    buf.resize_with(variable.size, Default::default);
-
     let _info = core.halt()?;
-    core.read_8(variable.addr, buf.as_mut_slice())?;
+
+    let rval = path.resolve(hubris, core, variable).and_then(|(addr, goff)| {
+        let size = if path.is_variable() {
+            variable.size
+        } else {
+            hubris.typesize(goff)?
+        };
+
+        let mut buf: Vec<u8> = vec![];
+        buf.resize_with(size, Default::default);
+        core.read_8(addr, buf.as_mut_slice())?;
+
+        Ok((addr, goff, buf))
+    });
+
     core.run()?;
 
+    let (addr, goff, buf) = rval?;
     let hex = !subargs.decimal;
 
     let fmt = HubrisPrintFormat {
@@ -77,9 +111,9 @@ fn readvar_dump(
         ..HubrisPrintFormat::default()
     };
     let name = subargs.variable.as_ref().unwrap();
-    let dumped = hubris.printfmt(&buf, variable.goff, &fmt)?;
+    let dumped = hubris.printfmt(&buf, goff, &fmt)?;
 
-    println!("{} (0x{:08x}) = {}", name, variable.addr, dumped);
+    println!("{} (0x{:08x}) = {}", name, addr, dumped);
 
     Ok(())
 }
@@ -96,13 +130,13 @@ fn readvar(
         return hubris.list_variables();
     }
 
-    let variables = match subargs.variable {
-        Some(ref variable) => hubris.lookup_variables(variable)?,
+    let path = match subargs.variable {
+        Some(ref variable) => VariablePath::parse(variable)?,
         None => bail!("expected variable (use \"-l\" to list)"),
     };
 
-    for v in variables {
-        readvar_dump(hubris, core, v, &subargs)?;
+    for v in path.variables(hubris)? {
+        readvar_dump(hubris, core, &path, v, &subargs)?;
     }
 
     Ok(())
@@ -29,6 +29,57 @@
 //! ...
 //! ```
 //!
+//! To continuously follow ring buffers on a live target, use `-f`
+//! (`--follow`).  The ring buffers are polled (every 100 milliseconds by
+//! default; this can be changed with `-i`), and only entries that have been
+//! recorded -- or whose count has changed -- since the previous poll are
+//! printed.  If more than one ring buffer is being followed, each entry is
+//! preceded by the name of its ring buffer.  To note the kernel time (in
+//! ticks) at which each entry was observed, use `-t` (`--ticks`):
+//!
+//! ```console
+//! % humility ringbuf -f -t task_thermal::THERMAL_RINGBUF
+//! humility: attached via ST-Link V3
+//! humility: following ring buffer task_thermal::THERMAL_RINGBUF in thermal
+//!        TICKS  NDX LINE      GEN    COUNT PAYLOAD
+//!      8362841   14  201       88        1 ControlPwm(0x2d)
+//!      8362841   15  190       88        3 FanRead(0x1)
+//!      8363842   16  201       88        1 ControlPwm(0x2e)
+//!      8363842   15  190       88        4 FanRead(0x1)
+//! ...
+//! ```
+//!
+//! As ring buffers are only polled, entries that are recorded and then
+//! overwritten between polls will not be seen; if this happens, a warning
+//! is emitted.  Following continues until interrupted.
+//!
+//! Entries can be restricted to those whose payload is a particular enum
+//! variant with `--variant`, or to those recorded at a particular source
+//! line with `--line`; either may be specified more than once.  To display
+//! each field of the payload in its own column, use `--fields`:
+//!
+//! ```console
+//! % humility ringbuf --variant FanRead --fields task_thermal::THERMAL_RINGBUF
+//! humility: attached via ST-Link V3
+//! humility: ring buffer task_thermal::THERMAL_RINGBUF in thermal:
+//!  NDX LINE      GEN    COUNT VARIANT FANREAD
+//!   13  190       88        7 FanRead 0x0
+//!   15  190       88        4 FanRead 0x1
+//! ```
+//!
+//! Fields are named by their path within the payload; the fields of an enum
+//! variant's contents are named by the variant.  To export entries for
+//! post-processing, use `--json` (which emits one JSON object per entry, and
+//! may be combined with `--follow`) or `--csv` (which emits a single CSV
+//! document with a column for each field of every entry's payload):
+//!
+//! ```console
+//! % humility ringbuf --json --line 190 task_thermal::THERMAL_RINGBUF
+//! humility: attached via ST-Link V3
+//! {"ringbuf":"task_thermal::THERMAL_RINGBUF","task":"thermal","index":13,...
+//! {"ringbuf":"task_thermal::THERMAL_RINGBUF","task":"thermal","index":15,...
+//! ```
+//!
 //! See the [`ringbuf`
 //! documentation](https://github.com/oxidecomputer/hubris/blob/master/lib/ringbuf/src/lib.rs) for more details.
 
@@ -37,9 +88,13 @@ use clap::Command as ClapCommand;
 use clap::{CommandFactory, Parser};
 use humility::core::Core;
 use humility::hubris::*;
-use humility_cmd::doppel::{Ringbuf, StaticCell};
-use humility_cmd::reflect::{self, Format, Load, Value};
+use humility_cmd::doppel::{Ringbuf, RingbufEntry};
+use humility_cmd::reflect::{self, Format, Value};
 use humility_cmd::{Archive, Args, Attach, Command, Validate};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[clap(name = "ringbuf", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -50,64 +105,413 @@ struct RingbufArgs {
     /// print only a single ringbuffer by name
     #[clap(conflicts_with = "list")]
     variable: Option<String>,
+    /// follow ring buffers, printing new entries as they are recorded
+    #[clap(long, short, conflicts_with = "list")]
+    follow: bool,
+    /// interval at which to poll ring buffers when following
+    #[clap(
+        long, short, requires = "follow", default_value = "100",
+        value_name = "ms", parse(try_from_str = parse_int::parse)
+    )]
+    interval: u64,
+    /// print the kernel time (in ticks) at which entries were observed
+    #[clap(long, short, requires = "follow")]
+    ticks: bool,
+    /// print only entries whose payload is the specified enum variant
+    #[clap(
+        long,
+        conflicts_with = "list",
+        multiple_occurrences = true,
+        value_name = "variant"
+    )]
+    variant: Vec<String>,
+    /// print only entries recorded at the specified source line
+    #[clap(
+        long,
+        conflicts_with = "list",
+        multiple_occurrences = true,
+        value_name = "line"
+    )]
+    line: Vec<u16>,
+    /// print each field of the payload in its own column
+    #[clap(long, conflicts_with_all = &["list", "follow"])]
+    fields: bool,
+    /// emit entries as JSON, one object per line
+    #[clap(long, short, conflicts_with_all = &["list", "fields"])]
+    json: bool,
+    /// emit entries as CSV, with each field of the payload in its own column
+    #[clap(long, conflicts_with_all = &["list", "follow", "fields", "json"])]
+    csv: bool,
 }
 
-fn ringbuf_dump(
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    ringbuf: &'a str,
+    task: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ticks: Option<u64>,
+    index: usize,
+    line: u16,
+    generation: u16,
+    count: u32,
+    payload: &'a Value,
+}
+
+fn ringbuf_entry(
+    hubris: &HubrisArchive,
+    slot: usize,
+    entry: &RingbufEntry,
+) -> Result<String> {
+    let fmt = HubrisPrintFormat { hex: true, ..HubrisPrintFormat::default() };
+
+    let mut dumped = vec![];
+    entry.payload.format(hubris, fmt, &mut dumped)?;
+    let dumped = String::from_utf8(dumped)?;
+
+    Ok(format!(
+        "{:4} {:4} {:8} {:8} {}",
+        slot, entry.line, entry.generation, entry.count, dumped
+    ))
+}
+
+//
+// Returns true if the entry should be displayed, given any variants or lines
+// that have been specified.
+//
+fn ringbuf_matches(subargs: &RingbufArgs, entry: &RingbufEntry) -> bool {
+    let variant = match &entry.payload {
+        Value::Enum(e) => Some(e.disc()),
+        _ => None,
+    };
+
+    (subargs.variant.is_empty()
+        || variant.map_or(false, |v| subargs.variant.iter().any(|n| n == v)))
+        && (subargs.line.is_empty() || subargs.line.contains(&entry.line))
+}
+
+//
+// Flattens the payloads of the specified entries, returning each column
+// (along with its width) in the order in which it was first seen.
+//
+fn ringbuf_columns<'a>(
+    hubris: &HubrisArchive,
+    fmt: HubrisPrintFormat,
+    payloads: impl Iterator<Item = &'a Value>,
+) -> Result<(IndexMap<String, usize>, Vec<IndexMap<String, String>>)> {
+    let mut columns = IndexMap::new();
+    let mut rows = vec![];
+
+    for payload in payloads {
+        let mut fields = IndexMap::new();
+        reflect::flatten(hubris, fmt, "", payload, &mut fields)?;
+
+        for (name, val) in &fields {
+            let width = columns.entry(name.clone()).or_insert(name.len());
+            *width = std::cmp::max(*width, val.len());
+        }
+
+        rows.push(fields);
+    }
+
+    Ok((columns, rows))
+}
+
+fn ringbuf_json(
+    ringbuf: &str,
+    task: &str,
+    ticks: Option<u64>,
+    slot: usize,
+    entry: &RingbufEntry,
+) -> Result<String> {
+    Ok(serde_json::to_string(&JsonEntry {
+        ringbuf,
+        task,
+        ticks,
+        index: slot,
+        line: entry.line,
+        generation: entry.generation,
+        count: entry.count,
+        payload: &entry.payload,
+    })?)
+}
+
+//
+// Reads the specified ring buffer, returning its entries (oldest first) that
+// match any specified criteria.
+//
+fn ringbuf_entries(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
     definition: &HubrisStruct,
     ringbuf_var: &HubrisVariable,
-) -> Result<()> {
-    let mut buf: Vec<u8> = vec![];
-    buf.resize_with(ringbuf_var.size, Default::default);
-
+    subargs: &RingbufArgs,
+) -> Result<Vec<(usize, RingbufEntry)>> {
     let _info = core.halt()?;
-    core.read_8(ringbuf_var.addr, buf.as_mut_slice())?;
+    let ringbuf = Ringbuf::read(hubris, core, definition, ringbuf_var);
     core.run()?;
 
-    // There are two possible shapes of ringbufs, depending on the age of the
-    // firmware.
-    // - Raw Ringbuf that is not wrapped by anything.
-    // - Safe Ringbuf that is inside a StaticCell.
-    //
-    // Here we will attempt to handle them both -- first raw, then fallback.
-    let ringbuf_val: Value =
-        Value::Struct(reflect::load_struct(hubris, &buf, definition, 0)?);
+    Ok(ringbuf?
+        .entries()
+        .filter(|(_, entry)| ringbuf_matches(subargs, entry))
+        .map(|(slot, entry)| (slot, entry.clone()))
+        .collect())
+}
+
+fn ringbuf_dump(
+    hubris: &HubrisArchive,
+    entries: &[(usize, RingbufEntry)],
+    subargs: &RingbufArgs,
+) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
 
-    let ringbuf: Ringbuf = Ringbuf::from_value(&ringbuf_val).or_else(|_e| {
-        let cell: StaticCell = StaticCell::from_value(&ringbuf_val)?;
-        Ringbuf::from_value(&cell.cell.value)
-    })?;
+    print!("{:>4} {:>4} {:>8} {:>8}", "NDX", "LINE", "GEN", "COUNT");
+
+    if !subargs.fields {
+        println!(" PAYLOAD");
+
+        for (slot, entry) in entries {
+            println!("{}", ringbuf_entry(hubris, *slot, entry)?);
+        }
 
-    let ndx = if let Some(x) = ringbuf.last {
-        x as usize
-    } else {
         return Ok(());
-    };
+    }
 
     let fmt = HubrisPrintFormat { hex: true, ..HubrisPrintFormat::default() };
+    let payloads = entries.iter().map(|(_, entry)| &entry.payload);
+    let (columns, rows) = ringbuf_columns(hubris, fmt, payloads)?;
+
+    for (name, width) in &columns {
+        print!(" {:<width$}", name.to_uppercase(), width = *width);
+    }
+
+    println!();
+
+    for ((slot, entry), fields) in entries.iter().zip(rows) {
+        print!(
+            "{:4} {:4} {:8} {:8}",
+            slot, entry.line, entry.generation, entry.count
+        );
+
+        for (name, width) in &columns {
+            let val = fields.get(name).map_or("-", |v| v.as_str());
+            print!(" {:<width$}", val, width = *width);
+        }
+
+        println!();
+    }
+
+    Ok(())
+}
+
+fn ringbuf_csv(
+    hubris: &HubrisArchive,
+    entries: &[(&str, &str, usize, RingbufEntry)],
+) -> Result<()> {
+    let fmt = HubrisPrintFormat::default();
+    let payloads = entries.iter().map(|(_, _, _, entry)| &entry.payload);
+    let (columns, rows) = ringbuf_columns(hubris, fmt, payloads)?;
+
+    let mut wtr = csv::Writer::from_writer(std::io::stdout());
+
+    let mut header =
+        vec!["ringbuf", "task", "index", "line", "generation", "count"];
+    header.extend(columns.keys().map(|c| c.as_str()));
+    wtr.write_record(&header)?;
 
-    println!("{:>4} {:>4} {:>8} {:>8} PAYLOAD", "NDX", "LINE", "GEN", "COUNT",);
+    for ((ringbuf, task, slot, entry), fields) in entries.iter().zip(rows) {
+        let mut record = vec![
+            ringbuf.to_string(),
+            task.to_string(),
+            slot.to_string(),
+            entry.line.to_string(),
+            entry.generation.to_string(),
+            entry.count.to_string(),
+        ];
 
-    for i in 0..ringbuf.buffer.len() {
-        let slot = (ndx + i + 1) % ringbuf.buffer.len();
-        let entry = &ringbuf.buffer[slot];
+        for name in columns.keys() {
+            record.push(fields.get(name).cloned().unwrap_or_default());
+        }
+
+        wtr.write_record(&record)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+//
+// A ring buffer that we are following, along with the generation and count
+// of each of its entries as of our last poll.
+//
+struct Followed<'a> {
+    name: &'a str,
+    variable: &'a HubrisVariable,
+    definition: &'a HubrisStruct,
+    seen: Vec<Option<(u16, u32)>>,
+    last: Option<usize>,
+}
+
+impl<'a> Followed<'a> {
+    //
+    // Returns the entries that are new (or have been recounted) since we
+    // last looked, oldest first -- and whether the ring buffer has wrapped
+    // entirely around since our last poll.
+    //
+    fn update<'b>(
+        &mut self,
+        ringbuf: &'b Ringbuf,
+    ) -> (Vec<(usize, &'b RingbufEntry)>, bool) {
+        let mut rval = vec![];
+
+        let ndx = match ringbuf.last {
+            Some(ndx) => ndx as usize,
+            None => return (rval, false),
+        };
 
-        if entry.generation == 0 {
-            continue;
+        let len = ringbuf.buffer.len();
+        self.seen.resize(len, None);
+
+        //
+        // If the slot that was last written when we last looked has a new
+        // generation, every entry has been overwritten since then -- and we
+        // have likely missed some.
+        //
+        let wrapped = match self.last {
+            Some(last) if last < len => match self.seen[last] {
+                Some((gen, _)) => ringbuf.buffer[last].generation != gen,
+                None => false,
+            },
+            _ => false,
+        };
+
+        for (slot, entry) in ringbuf.entries() {
+            let current = Some((entry.generation, entry.count));
+
+            if self.seen[slot] != current {
+                self.seen[slot] = current;
+                rval.push((slot, entry));
+            }
         }
 
-        let mut dumped = vec![];
-        entry.payload.format(hubris, fmt, &mut dumped)?;
-        let dumped = String::from_utf8(dumped)?;
+        self.last = Some(ndx);
+
+        (rval, wrapped)
+    }
+}
+
+fn ringbuf_follow(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    ringbufs: &[(&str, &HubrisVariable)],
+    subargs: &RingbufArgs,
+) -> Result<()> {
+    if core.is_dump() {
+        bail!("can only follow ring buffers on a live target");
+    }
+
+    let mut followed = vec![];
+
+    for &(name, variable) in ringbufs {
+        humility::msg!(
+            "following ring buffer {} in {}",
+            name,
+            taskname(hubris, variable).unwrap_or("???")
+        );
+
+        followed.push(Followed {
+            name,
+            variable,
+            definition: hubris.lookup_struct(variable.goff)?,
+            seen: vec![],
+            last: None,
+        });
+    }
+
+    let ticks = if subargs.ticks {
+        Some(hubris.lookup_variable("TICKS")?.addr)
+    } else {
+        None
+    };
+
+    let width = followed.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    let named = followed.len() > 1;
+
+    if !subargs.json {
+        if ticks.is_some() {
+            print!("{:>12} ", "TICKS");
+        }
+
+        if named {
+            print!("{:<width$} ", "BUFFER", width = width);
+        }
 
         println!(
-            "{:4} {:4} {:8} {:8} {}",
-            slot, entry.line, entry.generation, entry.count, dumped
+            "{:>4} {:>4} {:>8} {:>8} PAYLOAD",
+            "NDX", "LINE", "GEN", "COUNT"
         );
     }
 
-    Ok(())
+    loop {
+        //
+        // We read all of our ring buffers (and the time) with the target
+        // halted, running it again before we do anything that might fail.
+        //
+        core.halt()?;
+
+        let now = ticks.map(|addr| core.read_word_64(addr));
+
+        let snapshots = followed
+            .iter()
+            .map(|f| Ringbuf::read(hubris, core, f.definition, f.variable))
+            .collect::<Vec<_>>();
+
+        core.run()?;
+
+        let now = now.transpose()?;
+
+        for (f, ringbuf) in followed.iter_mut().zip(snapshots) {
+            let ringbuf = ringbuf?;
+            let (entries, wrapped) = f.update(&ringbuf);
+
+            if wrapped {
+                humility::msg!(
+                    "ring buffer {} wrapped between polls; \
+                    entries may have been missed",
+                    f.name
+                );
+            }
+
+            for (slot, entry) in entries {
+                if !ringbuf_matches(subargs, entry) {
+                    continue;
+                }
+
+                if subargs.json {
+                    let task = taskname(hubris, f.variable).unwrap_or("???");
+                    println!(
+                        "{}",
+                        ringbuf_json(f.name, task, now, slot, entry)?
+                    );
+                    continue;
+                }
+
+                if let Some(now) = now {
+                    print!("{:12} ", now);
+                }
+
+                if named {
+                    print!("{:<width$} ", f.name, width = width);
+                }
+
+                println!("{}", ringbuf_entry(hubris, slot, entry)?);
+            }
+        }
+
+        thread::sleep(Duration::from_millis(subargs.interval));
+    }
 }
 
 fn taskname<'a>(
@@ -162,23 +566,53 @@ fn ringbuf(
         return Ok(());
     }
 
+    if subargs.follow {
+        return ringbuf_follow(hubris, core, &ringbufs, &subargs);
+    }
+
+    let text = !subargs.json && !subargs.csv;
+    let mut rows = vec![];
+
     for v in ringbufs {
+        let task = taskname(hubris, v.1).unwrap_or("???");
+
         // Try not to use `?` here, because it causes one bad ringbuf to make
         // them all unavailable.
-        humility::msg!(
-            "ring buffer {} in {}:",
-            v.0,
-            taskname(hubris, v.1).unwrap_or("???")
-        );
-        if let Ok(def) = hubris.lookup_struct(v.1.goff) {
-            if let Err(e) = ringbuf_dump(hubris, core, def, v.1) {
+        if text {
+            humility::msg!("ring buffer {} in {}:", v.0, task);
+        }
+
+        let def = match hubris.lookup_struct(v.1.goff) {
+            Ok(def) => def,
+            Err(_) => {
+                humility::msg!("could not look up type: {:?}", v.1.goff);
+                continue;
+            }
+        };
+
+        let entries = match ringbuf_entries(hubris, core, def, v.1, &subargs) {
+            Ok(entries) => entries,
+            Err(e) => {
                 humility::msg!("ringbuf dump failed: {}", e);
+                continue;
             }
+        };
+
+        if subargs.json {
+            for (slot, entry) in &entries {
+                println!("{}", ringbuf_json(v.0, task, None, *slot, entry)?);
+            }
+        } else if subargs.csv {
+            rows.extend(entries.into_iter().map(|(s, e)| (v.0, task, s, e)));
         } else {
-            humility::msg!("could not look up type: {:?}", v.1.goff);
+            ringbuf_dump(hubris, &entries, &subargs)?;
         }
     }
 
+    if subargs.csv {
+        ringbuf_csv(hubris, &rows)?;
+    }
+
     Ok(())
 }
 
@@ -21,7 +21,6 @@
 use anyhow::{bail, Context, Result};
 use clap::Command as ClapCommand;
 use clap::{CommandFactory, Parser};
-use hif::*;
 use humility::core::Core;
 use humility::hubris::*;
 use humility_cmd::hiffy::*;
@@ -135,7 +134,7 @@ fn print(
     devices: &Option<HashSet<&String>>,
     named: &Option<HashSet<&String>>,
 ) -> Result<()> {
-    let mut ops = vec![];
+    let mut batch = HiffyBatch::new();
     let funcs = context.functions()?;
     let op = idol::IdolOperation::new(hubris, "Sensor", "get", None)
         .context("is the 'sensor' task present?")?;
@@ -179,13 +178,13 @@ fn print(
 
         rvals.push(s);
 
+        let mut ops = vec![];
         let payload =
             op.payload(&[("id", idol::IdolArgument::Scalar(i as u64))])?;
         context.idol_call_ops(&funcs, &op, &payload, &mut ops)?;
+        batch.push(ops, ok.size);
     }
 
-    ops.push(Op::Done);
-
     for r in &rvals {
         print!(" {:>12}", r.name.to_uppercase());
     }
@@ -199,11 +198,11 @@ fn print(
     println!();
 
     loop {
-        let results = context.run(core, ops.as_slice(), None)?;
+        let results = context.run_batch(core, &batch)?;
 
         let mut rval = vec![];
 
-        for r in results {
+        for r in results.into_iter().flatten() {
             if let Ok(val) = r {
                 rval.push(Some(f32::from_le_bytes(val[0..4].try_into()?)));
             } else {
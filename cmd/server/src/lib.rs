@@ -0,0 +1,366 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility server`
+//!
+//! `humility server` attaches to a live target and holds it open, serving
+//! requests over a local Unix domain socket.  While a server is running,
+//! other Humility commands run against a live target will transparently
+//! use it rather than attaching to the probe themselves, saving the time
+//! required to attach (and allowing many commands to be run in quick
+//! succession):
+//!
+//! ```console
+//! % humility server &
+//! humility: attached via ST-Link V3
+//! humility: listening on /run/user/1000/humility.sock
+//! % humility tasks
+//! humility: attached via server at /run/user/1000/humility.sock
+//! system time = 1923847
+//! ...
+//! ```
+//!
+//! By default, the socket is `humility.sock` in `XDG_RUNTIME_DIR` or (if
+//! that isn't set) in a `humility-<uid>` directory in the system's temporary
+//! directory that is created to be accessible only by its owner; this can be
+//! changed by setting `HUMILITY_SERVER` (for both the server and its clients)
+//! or with `--socket`.  The socket itself is accessible only by its owner,
+//! and clients will refuse to use a socket that is owned by another user or
+//! that is accessible by others.  Commands that explicitly specify a probe
+//! (via `-p` or `HUMILITY_PROBE`) or a dump will not use the server.
+//!
+//! HIF programs are executed by the server on behalf of its clients, using
+//! a single HIF context whose functions are discovered only once:  clients
+//! take the function table from the server (and rely on the server's check
+//! of the HIF version) rather than walking the archive's DWARF information
+//! and reading the target themselves.  Scripts may also make Idol calls
+//! directly by writing a JSON request (one per line) to the socket, and
+//! reading the JSON response:
+//!
+//! ```console
+//! % echo '{"Call":{"call":"Sensor.get","args":[["id","3"]]}}' | \
+//!     nc -U /run/user/1000/humility.sock
+//! {"Reply":{"Ok":23.0}}
+//! ```
+//!
+//! A reply is decoded according to its type, with structures represented as
+//! objects, arrays and tuples as arrays, and enums as the name of their
+//! variant (or, for variants with values, an object mapping the name to the
+//! value).  A failed call results in an `Err` reply bearing the name of the
+//! error returned by the operation; a call that could not be made at all
+//! results in an `Error` response.
+//!
+//! The server serves one connection at a time, and runs until killed.
+//!
+
+use anyhow::{anyhow, bail, Result};
+use clap::Command as ClapCommand;
+use clap::{CommandFactory, Parser};
+use hif::*;
+use humility::arch::ARMRegister;
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cmd::hiffy::*;
+use humility_cmd::idol;
+use humility_cmd::server::{Request, Response};
+use humility_cmd::{Archive, Args, Command};
+use num_traits::FromPrimitive;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[clap(name = "server", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct ServerArgs {
+    /// sets timeout for Idol calls
+    #[clap(
+        long, short = 'T', default_value = "5000", value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// path of the socket on which to listen
+    #[clap(long, short)]
+    socket: Option<PathBuf>,
+}
+
+struct Server<'a> {
+    hubris: &'a HubrisArchive,
+    core: &'a mut dyn Core,
+    context: HiffyContext<'a>,
+    funcs: HiffyFunctions,
+    timeout: u32,
+    reset: bool,
+}
+
+impl<'a> Server<'a> {
+    fn register(reg: u16) -> Result<ARMRegister> {
+        ARMRegister::from_u16(reg)
+            .ok_or_else(|| anyhow!("invalid register {}", reg))
+    }
+
+    fn call(
+        &mut self,
+        call: &str,
+        task: &Option<String>,
+        args: &[(String, String)],
+    ) -> Result<Response> {
+        let hubris = self.hubris;
+        let func: Vec<&str> = call.split('.').collect();
+
+        if func.len() != 2 {
+            bail!("calls must be interface.operation");
+        }
+
+        let task = match task {
+            Some(task) => Some(
+                hubris
+                    .lookup_task(task)
+                    .ok_or_else(|| anyhow!("unknown task \"{}\"", task))?,
+            ),
+            None => None,
+        };
+
+        let op = idol::IdolOperation::new(hubris, func[0], func[1], task)?;
+
+        let args = args
+            .iter()
+            .map(|(arg, val)| (arg.as_str(), idol::IdolArgument::String(val)))
+            .collect::<Vec<_>>();
+
+        let payload = op.payload(&args)?;
+        let mut ops = vec![];
+
+        self.context.idol_call_ops(&self.funcs, &op, &payload, &mut ops)?;
+        ops.push(Op::Done);
+
+        self.context.set_timeout(self.timeout);
+        let results = self.context.run(self.core, ops.as_slice(), None)?;
+
+        if results.len() != 1 {
+            bail!("unexpected results length: {:?}", results);
+        }
+
+        Ok(Response::Reply(match op.reply(&results[0])? {
+            Ok(val) => Ok(serde_json::to_value(&val)?),
+            Err(err) => Err(serde_json::to_value(&err)?),
+        }))
+    }
+
+    fn request(&mut self, request: &Request) -> Result<Response> {
+        //
+        // A HIF program that fails leaves our context in a state from which
+        // it cannot run another; if the last one failed, we replace the
+        // context with a fresh one before running another.  If that fails
+        // (e.g., because the target is wedged), we fail only this request,
+        // and try again on the next.
+        //
+        if self.reset
+            && matches!(request, Request::Hiffy { .. } | Request::Call { .. })
+        {
+            self.context =
+                HiffyContext::new(self.hubris, self.core, self.timeout)?;
+            self.reset = false;
+        }
+
+        match request {
+            Request::Hiffy { ops, data, timeout } => {
+                self.context.set_timeout(*timeout);
+                let results =
+                    self.context.run(self.core, ops, data.as_deref())?;
+                Ok(Response::Results(results))
+            }
+            Request::Call { call, task, args } => self.call(call, task, args),
+            Request::Functions => Ok(Response::Functions(
+                self.funcs.0.values().cloned().collect(),
+            )),
+            _ => self.core_request(request),
+        }
+    }
+
+    fn core_request(&mut self, request: &Request) -> Result<Response> {
+        let core = &mut *self.core;
+
+        Ok(match request {
+            Request::Info => {
+                let (probe, serial) = core.info();
+                Response::Info(probe, serial)
+            }
+            Request::ReadWord32(addr) => {
+                Response::Word(core.read_word_32(*addr)?)
+            }
+            Request::Read8(addr, len) => {
+                let mut buf = vec![0u8; *len];
+                core.read_8(*addr, &mut buf)?;
+                Response::Bytes(buf)
+            }
+            Request::ReadReg(reg) => {
+                Response::Word(core.read_reg(Self::register(*reg)?)?)
+            }
+            Request::WriteReg(reg, val) => {
+                core.write_reg(Self::register(*reg)?, *val)?;
+                Response::Ok
+            }
+            Request::InitSwv => {
+                core.init_swv()?;
+                Response::Ok
+            }
+            Request::ReadSwv => Response::Bytes(core.read_swv()?),
+            Request::WriteWord32(addr, val) => {
+                core.write_word_32(*addr, *val)?;
+                Response::Ok
+            }
+            Request::Write8(addr, data) => {
+                core.write_8(*addr, data)?;
+                Response::Ok
+            }
+            Request::Halt => {
+                core.halt()?;
+                Response::Ok
+            }
+            Request::Run => {
+                core.run()?;
+                Response::Ok
+            }
+            Request::Step => {
+                core.step()?;
+                Response::Ok
+            }
+            Request::Hiffy { .. }
+            | Request::Call { .. }
+            | Request::Functions => unreachable!(),
+        })
+    }
+
+    fn serve(&mut self, stream: UnixStream) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => match self.request(&request) {
+                    Ok(response) => response,
+                    Err(err) => {
+                        humility::msg!("request failed: {}", err);
+
+                        if let Request::Hiffy { .. } | Request::Call { .. } =
+                            request
+                        {
+                            self.reset = true;
+                        }
+
+                        Response::Error(err.to_string())
+                    }
+                },
+                Err(err) => Response::Error(format!("bad request: {}", err)),
+            };
+
+            let mut response = serde_json::to_string(&response)?;
+            response.push('\n');
+            writer.write_all(response.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn bind(path: &Path) -> Result<UnixListener> {
+    //
+    // If the socket already exists, we want to fail if there is a server
+    // behind it -- and otherwise remove it, as it has been left behind.  We
+    // are careful to only ever remove a socket that is our own:  the path
+    // may have been specified by the user, and connecting to anything that
+    // isn't a socket (e.g., a regular file) will also be refused.
+    //
+    match UnixStream::connect(path) {
+        Ok(_) => bail!("server already running at {}", path.display()),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            if !std::fs::symlink_metadata(path)?.file_type().is_socket() {
+                bail!("{} exists and is not a socket", path.display());
+            }
+
+            humility_cmd::server::check_private(path)?;
+            std::fs::remove_file(path)?;
+        }
+        Err(_) => {}
+    }
+
+    //
+    // The socket must be accessible only by us; we create it with a umask
+    // that assures this, rather than changing its mode after the fact (which
+    // would leave a window in which others could connect to it).
+    //
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+
+    let listener = listener?;
+    humility_cmd::server::check_private(path)?;
+
+    Ok(listener)
+}
+
+fn server(
+    hubris: &mut HubrisArchive,
+    args: &Args,
+    subargs: &[String],
+) -> Result<()> {
+    let subargs = ServerArgs::try_parse_from(subargs)?;
+    let hubris = &*hubris;
+
+    if args.dump.is_some() {
+        bail!("must be run against a live system");
+    }
+
+    let mut c = humility_cmd::attach_live(args)?;
+    let core = c.as_mut();
+
+    hubris.validate(core, HubrisValidate::Booted)?;
+
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
+    let funcs = context.functions()?;
+
+    let path = match subargs.socket {
+        Some(path) => path,
+        None => humility_cmd::server::socket_path()?,
+    };
+    let listener = bind(&path)?;
+
+    humility::msg!("listening on {}", path.display());
+
+    let mut server = Server {
+        hubris,
+        core,
+        context,
+        funcs,
+        timeout: subargs.timeout,
+        reset: false,
+    };
+
+    for stream in listener.incoming() {
+        if let Err(err) = server.serve(stream?) {
+            humility::msg!("connection failed: {}", err);
+        }
+
+        //
+        // Don't let a client that went away leave the target halted.
+        //
+        server.core.run()?;
+    }
+
+    Ok(())
+}
+
+pub fn init() -> (Command, ClapCommand<'static>) {
+    (
+        Command::Unattached {
+            name: "server",
+            archive: Archive::Required,
+            run: server,
+        },
+        ServerArgs::command(),
+    )
+}
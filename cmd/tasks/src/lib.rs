@@ -110,18 +110,85 @@
 //!
 //! These options can naturally be combined, e.g. `humility tasks -slvr`.
 //!
+//! To consume task state programmatically, use the `--json` flag to emit a
+//! JSON document that contains the system time and, for each task, its
+//! scheduling state, fault information, priority, generation and timer.
+//! When combined with `-r` and/or `-s` (and `-l`), each task will
+//! additionally contain its registers and/or its stack backtrace:
+//!
+//! ```console
+//! % humility -d hubris.core.4 tasks --json pong
+//! humility: attached to dump
+//! {
+//!   "system_time": 1791860,
+//!   "tasks": [
+//!     {
+//!       "id": 7,
+//!       "name": "pong",
+//!       "current": false,
+//!       "state": {
+//!         "Faulted": {
+//!           "fault": {
+//!             "Injected": {
+//!               "index": 0,
+//!               "generation": 0
+//!             }
+//!           },
+//!           "original_state": {
+//!             "InRecv": null
+//!           }
+//!         }
+//!       },
+//!       "generation": 0,
+//!       "priority": 3,
+//!       "timer": {
+//!         "deadline": null,
+//!         "to_post": 0
+//!       }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! To see the state of the kernel itself, use `-k` (`--kernel`).  This
+//! shows the exception that the kernel is handling (if any), the message
+//! with which it panicked (if it has), an explanation of any fault status
+//! bits that are set, and a backtrace of the kernel's main stack.  When the
+//! kernel took an exception, the backtrace continues through the exception
+//! frame into the code that was running when it was taken; this works
+//! equally well on a dump:
+//!
+//! ```console
+//! % humility -d ./hubris.core.0 tasks -k
+//! humility: attached to dump
+//! system time = 94227
+//! ID TASK                 GEN PRI STATE
+//! ...
+//! kernel: in HardFault, panicked: "panicked at 'attempt to add with overflow'"
+//!    fault: HardFault: escalated from a fault that could not be handled (FORCED)
+//!    |
+//!    +--->  0x20000fa8 0x08001a3e kern::fail::die_impl
+//!           0x20000fb8 0x08001a5c kern::fail::die
+//!           0x20000fd0 0x08001c12 rust_begin_unwind
+//!                                 <exception>
+//!           0x20000ff0 0x08000d4a kern::arch::arm_m::safe_sys_tick_handler
+//!           0x20001000 0x08000c80 SysTick
+//! ```
+//!
 
 use anyhow::{bail, Result};
 use clap::Command as ClapCommand;
 use clap::{CommandFactory, Parser};
-use humility::arch::ARMRegister;
+use humility::arch::{is_exc_return, ARMRegister};
 use humility::core::Core;
 use humility::hubris::*;
 use humility_cmd::doppel::{self, Task, TaskDesc, TaskId, TaskState};
 use humility_cmd::reflect::{self, Format, Load};
 use humility_cmd::{Archive, Args, Attach, Command, Validate};
-use num_traits::FromPrimitive;
-use std::collections::HashMap;
+use humility_cortex::fault::{exception_name, FaultStatus};
+use num_traits::{FromPrimitive, ToPrimitive};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Parser, Debug)]
 #[clap(name = "tasks", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -146,6 +213,14 @@ struct TasksArgs {
     #[clap(long, short)]
     verbose: bool,
 
+    /// print task state as JSON
+    #[clap(long, conflicts_with = "verbose")]
+    json: bool,
+
+    /// show kernel state and backtrace
+    #[clap(long, short, conflicts_with = "json")]
+    kernel: bool,
+
     /// single task to display
     task: Option<String>,
 }
@@ -197,6 +272,17 @@ fn print_stack(
             println!("0x{:08x} 0x{:08x}", frame.cfa, *pc);
         }
 
+        //
+        // If this frame was entered via an exception (as when the kernel
+        // faults), the frames that follow are those that took it.
+        //
+        if let Some(lr) = frame.registers.get(&ARMRegister::LR) {
+            if is_exc_return(*lr) && i + 1 < stack.len() {
+                print!("   {}      ", bar);
+                println!("{:22}<exception>", "");
+            }
+        }
+
         if i + 1 < stack.len() {
             print!("   {}      ", bar);
         }
@@ -209,6 +295,62 @@ fn print_stack(
     }
 }
 
+//
+// Displays the state of the kernel:  the exception that it is handling (if
+// any), the message with which it panicked (if it has), any fault status,
+// and its backtrace.
+//
+fn print_kernel(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &TasksArgs,
+) -> Result<()> {
+    let thread = match hubris.kernel_thread(core) {
+        Ok(thread) => thread,
+        Err(e) => {
+            println!("kernel: not running: {}", e);
+            return Ok(());
+        }
+    };
+
+    let psr = thread.registers.get(&ARMRegister::PSR).unwrap();
+
+    match psr & 0x1ff {
+        0 => print!("kernel: running"),
+        vector => print!("kernel: in {}", exception_name(vector)),
+    }
+
+    if let Some(epitaph) = hubris.kernel_epitaph(core)? {
+        print!(", panicked: \"{}\"", epitaph);
+    }
+
+    println!();
+
+    match FaultStatus::read(core) {
+        Ok(status) => {
+            for explanation in status.explain() {
+                println!("   fault: {}", explanation);
+            }
+        }
+        Err(_) => {
+            println!("   fault status not available");
+        }
+    }
+
+    match thread.stack(hubris, core) {
+        Ok(stack) => print_stack(hubris, &stack, subargs),
+        Err(e) => {
+            println!("   stack unwind failed: {:?} ", e);
+        }
+    }
+
+    if subargs.registers {
+        print_regs(&thread.registers, subargs.verbose);
+    }
+
+    Ok(())
+}
+
 fn print_regs(regs: &HashMap<ARMRegister, u32>, additional: bool) {
     let bar = if additional { "|" } else { " " };
 
@@ -231,6 +373,149 @@ fn print_regs(regs: &HashMap<ARMRegister, u32>, additional: bool) {
     println!();
 }
 
+#[derive(Serialize)]
+struct JsonTasks {
+    system_time: u64,
+    tasks: Vec<JsonTask>,
+}
+
+#[derive(Serialize)]
+struct JsonTask {
+    id: u32,
+    name: String,
+    current: bool,
+    #[serde(flatten)]
+    task: Task,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    panic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registers: Option<BTreeMap<String, u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack: Option<Vec<JsonFrame>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack_error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonFrame {
+    cfa: u32,
+    pc: u32,
+    symbol: Option<String>,
+    inlined: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u64>,
+}
+
+fn json_stack(
+    hubris: &HubrisArchive,
+    stack: &[HubrisStackFrame],
+    subargs: &TasksArgs,
+) -> Vec<JsonFrame> {
+    let mut frames = vec![];
+
+    let lookup = |goff| {
+        if subargs.line {
+            hubris.lookup_src(goff).map(|src| (src.fullpath(), src.line))
+        } else {
+            None
+        }
+    };
+
+    for frame in stack {
+        let pc = *frame.registers.get(&ARMRegister::PC).unwrap();
+
+        if let Some(ref inlined) = frame.inlined {
+            for inline in inlined {
+                let src = lookup(inline.origin);
+
+                frames.push(JsonFrame {
+                    cfa: frame.cfa,
+                    pc: inline.addr,
+                    symbol: Some(inline.name.to_string()),
+                    inlined: true,
+                    file: src.as_ref().map(|(file, _)| file.clone()),
+                    line: src.map(|(_, line)| line),
+                });
+            }
+        }
+
+        let src = frame.sym.and_then(|sym| lookup(sym.goff));
+
+        frames.push(JsonFrame {
+            cfa: frame.cfa,
+            pc,
+            symbol: frame.sym.map(|sym| sym.demangled_name.clone()),
+            inlined: false,
+            file: src.as_ref().map(|(file, _)| file.clone()),
+            line: src.map(|(_, line)| line),
+        });
+    }
+
+    frames
+}
+
+fn json_task(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    i: u32,
+    task: Task,
+    desc: &TaskDesc,
+    current: bool,
+    subargs: &TasksArgs,
+) -> Result<JsonTask> {
+    let name = hubris.instr_mod(desc.entry_point).unwrap_or("<unknown>");
+
+    let panic = match task.state {
+        TaskState::Faulted { fault: doppel::FaultInfo::Panic, .. } => {
+            panic_message(hubris, core, i)?
+        }
+        _ => None,
+    };
+
+    let mut rval = JsonTask {
+        id: i,
+        name: name.to_string(),
+        current,
+        task,
+        panic,
+        registers: None,
+        stack: None,
+        stack_error: None,
+    };
+
+    if subargs.stack || subargs.registers {
+        let thread = hubris.thread(core, HubrisTask::Task(i))?;
+        let regs = &thread.registers;
+
+        if subargs.stack {
+            match thread.stack(hubris, core) {
+                Ok(stack) => {
+                    rval.stack = Some(json_stack(hubris, &stack, subargs));
+                }
+                Err(e) => {
+                    rval.stack_error = Some(format!("{:?}", e));
+                }
+            }
+        }
+
+        if subargs.registers {
+            let mut sorted = regs.iter().collect::<Vec<_>>();
+            sorted.sort_by_key(|(reg, _)| reg.to_u16());
+
+            rval.registers = Some(
+                sorted
+                    .into_iter()
+                    .map(|(reg, val)| (reg.to_string(), *val))
+                    .collect(),
+            );
+        }
+    }
+
+    Ok(rval)
+}
+
 #[rustfmt::skip::macros(println)]
 fn tasks(
     hubris: &HubrisArchive,
@@ -262,14 +547,18 @@ fn tasks(
         let mut taskblock = vec![0; task_t.size * task_count as usize];
         core.read_8(base, &mut taskblock)?;
 
-        if !subargs.stack {
+        if !subargs.stack && !subargs.kernel {
             core.run()?;
         }
 
-        println!("system time = {}", ticks);
+        let mut json = vec![];
 
-        println!("{:2} {:15} {:>8} {:3} {:9}",
-            "ID", "TASK", "GEN", "PRI", "STATE");
+        if !subargs.json {
+            println!("system time = {}", ticks);
+
+            println!("{:2} {:15} {:>8} {:3} {:9}",
+                "ID", "TASK", "GEN", "PRI", "STATE");
+        }
 
         let mut any_names_truncated = false;
         for i in 0..task_count {
@@ -297,6 +586,19 @@ fn tasks(
                 (deadline.0 as i64 - ticks as i64, task.timer.to_post.0)
             });
 
+            if subargs.json {
+                json.push(json_task(
+                    hubris,
+                    core,
+                    i,
+                    task,
+                    &desc,
+                    addr == cur,
+                    &subargs,
+                )?);
+                continue;
+            }
+
             {
                 let mut modname = module.to_string();
                 if modname.len() > 14 {
@@ -324,11 +626,10 @@ fn tasks(
             println!();
 
             if subargs.stack || subargs.registers {
-                let t = HubrisTask::Task(i);
-                let regs = hubris.registers(core, t)?;
+                let thread = hubris.thread(core, HubrisTask::Task(i))?;
 
                 if subargs.stack {
-                    match hubris.stack(core, t, desc.initial_stack, &regs) {
+                    match thread.stack(hubris, core) {
                         Ok(stack) => print_stack(hubris, &stack, &subargs),
                         Err(e) => {
                             println!("   stack unwind failed: {:?} ", e);
@@ -337,7 +638,7 @@ fn tasks(
                 }
 
                 if subargs.registers {
-                    print_regs(&regs, subargs.verbose);
+                    print_regs(&thread.registers, subargs.verbose);
                 }
             }
 
@@ -359,15 +660,32 @@ fn tasks(
             }
         }
 
-        if any_names_truncated {
-            println!("Note: task names were truncated to fit. Use \
-                humility manifest to see them.");
+        if subargs.json {
+            let json = JsonTasks { system_time: ticks, tasks: json };
+            println!("{}", serde_json::to_string_pretty(&json)?);
         }
 
-        if subargs.stack {
+        //
+        // The kernel is displayed with the target halted; we want to be sure
+        // to run it again even if we fail to display it.
+        //
+        let rval = if subargs.kernel {
+            print_kernel(hubris, core, &subargs)
+        } else {
+            Ok(())
+        };
+
+        if subargs.stack || subargs.kernel {
             core.run()?;
         }
 
+        rval?;
+
+        if any_names_truncated {
+            println!("Note: task names were truncated to fit. Use \
+                humility manifest to see them.");
+        }
+
         if subargs.task.is_some() && !found {
             bail!("\"{}\" is not a valid task", subargs.task.unwrap());
         }
@@ -512,18 +830,10 @@ fn explain_fault_info(
             print!("in syscall: ");
             explain_usage_error(ue);
         }
-        FaultInfo::Panic => {
-            let r = hubris.registers(core, HubrisTask::Task(task_index))?;
-            let msg_base = *r.get(&ARMRegister::R4).unwrap();
-            let msg_len = *r.get(&ARMRegister::R5).unwrap();
-            let msg_len = msg_len.min(255) as usize;
-            let mut buf = vec![0; msg_len];
-            core.read_8(msg_base, &mut buf)?;
-            match std::str::from_utf8(&buf) {
-                Ok(msg) => print!("{}", msg),
-                Err(_) => print!("panic with invalid message"),
-            }
-        }
+        FaultInfo::Panic => match panic_message(hubris, core, task_index)? {
+            Some(msg) => print!("{}", msg),
+            None => print!("panic with invalid message"),
+        },
         FaultInfo::FromServer(task_id, reason) => {
             print!("reply fault: task id {}, reason {:?}", task_id, reason);
         }
@@ -531,6 +841,25 @@ fn explain_fault_info(
     Ok(())
 }
 
+///
+/// Returns the panic message of a panicked task, or `None` if the message
+/// is not valid UTF-8.
+///
+fn panic_message(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    task_index: u32,
+) -> Result<Option<String>> {
+    let r = hubris.registers(core, HubrisTask::Task(task_index))?;
+    let msg_base = *r.get(&ARMRegister::R4).unwrap();
+    let msg_len = *r.get(&ARMRegister::R5).unwrap();
+    let msg_len = msg_len.min(255) as usize;
+    let mut buf = vec![0; msg_len];
+    core.read_8(msg_base, &mut buf)?;
+
+    Ok(std::str::from_utf8(&buf).ok().map(|msg| msg.to_string()))
+}
+
 fn explain_usage_error(e: doppel::UsageError) {
     use doppel::UsageError::*;
     match e {
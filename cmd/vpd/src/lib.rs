@@ -0,0 +1,1067 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility vpd`
+//!
+//! `humility vpd` reads and parses the EEPROMs that contain a board's vital
+//! product data (VPD):  its identity, including (for example) its serial
+//! number, part number and base MAC address.  VPD EEPROMs are found via the
+//! I<sup>2</sup>C devices in the archive; to list them, use `-l` (`--list`):
+//!
+//! ```console
+//! % humility vpd -l
+//! humility: attached via ST-Link V3
+//! ID C P  MUX ADDR DEVICE        DESCRIPTION
+//!  0 2 F  -   0x50 at24csw080    Mainboard VPD
+//!  1 3 H  1:1 0x50 at24csw080    U.2 Sharkfin A VPD
+//!  2 3 H  1:2 0x50 at24csw080    U.2 Sharkfin B VPD
+//! ```
+//!
+//! Without any arguments, each VPD EEPROM is read and parsed.  Two formats
+//! are understood:  IPMI FRU (in which case the common header and each of
+//! the chassis, board and product info areas and any MultiRecord area are
+//! parsed) and ONIE TlvInfo.
+//!
+//! ```console
+//! % humility vpd -i 0
+//! humility: attached via ST-Link V3
+//! I2C2, port F, dev 0x50 (at24csw080): Mainboard VPD
+//!     format                   IPMI FRU
+//!     board.mfg_date           2021-11-04 18:03
+//!     board.manufacturer       Oxide Computer Company
+//!     board.product            Gimlet
+//!     board.serial             BRM42220017
+//!     board.part               913-0000019
+//!     board.fru_file_id        -
+//! ```
+//!
+//! To limit the EEPROMs read, specify an ID (as shown by `--list`) via `-i`
+//! or a device name via `-d`.  To emit the parsed contents as JSON, use
+//! `--json`.
+//!
+//! To check that each EEPROM holds data in a recognized format with valid
+//! checksums, use `-V` (`--validate`); the command will fail if any EEPROM
+//! does not validate:
+//!
+//! ```console
+//! % humility vpd -V
+//! humility: attached via ST-Link V3
+//! I2C2, port F, dev 0x50 (at24csw080): ok (IPMI FRU)
+//! I2C3, port H, seg 1:1, dev 0x50 (at24csw080): ok (ONIE TlvInfo)
+//! I2C3, port H, seg 1:2, dev 0x50 (at24csw080): EEPROM is blank
+//! humility vpd failed: 1 of 3 EEPROMs failed validation
+//! ```
+//!
+
+use anyhow::{bail, Result};
+use clap::Command as ClapCommand;
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cmd::hiffy::*;
+use humility_cmd::i2c::{I2cArgs, I2cEndian, I2cRegisterAddressing};
+use humility_cmd::{Archive, Args, Attach, Command, Validate};
+use indexmap::IndexMap;
+use serde::Serialize;
+
+#[derive(Parser, Debug)]
+#[clap(name = "vpd", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct VpdArgs {
+    /// sets timeout
+    #[clap(
+        long, short, default_value = "5000", value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout: u32,
+
+    /// list VPD EEPROMs
+    #[clap(long, short, conflicts_with_all = &["json", "validate"])]
+    list: bool,
+
+    /// specifies a VPD EEPROM by ID
+    #[clap(long, short, value_name = "id", conflicts_with = "device",
+        parse(try_from_str = parse_int::parse),
+    )]
+    id: Option<usize>,
+
+    /// specifies VPD EEPROMs by device name
+    #[clap(long, short, value_name = "device")]
+    device: Option<String>,
+
+    /// emit parsed VPD as JSON
+    #[clap(long, conflicts_with = "validate")]
+    json: bool,
+
+    /// validate the format and checksums of each VPD EEPROM
+    #[clap(long, short = 'V')]
+    validate: bool,
+}
+
+//
+// The EEPROMs that we know to hold VPD, along with their size (in bytes)
+// and the width of their addresses (also in bytes).
+//
+const VPD_EEPROMS: &[(&str, u32, u8)] =
+    &[("at24csw080", 1024, 2), ("at24c02", 256, 1), ("m24c02", 256, 1)];
+
+//
+// The size of each read; this is small enough to comfortably fit in the
+// HIF return stack, and to be a multiple of the page size of any EEPROM.
+//
+const VPD_READ_SIZE: u8 = 16;
+
+#[derive(Debug, Default, Serialize)]
+struct Vpd {
+    format: String,
+    fields: IndexMap<String, String>,
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonVpd<'a> {
+    id: usize,
+    device: &'a str,
+    name: Option<&'a str>,
+    description: &'a str,
+    controller: u8,
+    port: &'a str,
+    mux: Option<u8>,
+    segment: Option<u8>,
+    address: u8,
+    #[serde(flatten)]
+    vpd: Vpd,
+}
+
+impl Vpd {
+    fn field(&mut self, name: &str, value: String) {
+        let mut key = name.to_string();
+        let mut n = 1;
+
+        while self.fields.contains_key(&key) {
+            key = format!("{}{}", name, n);
+            n += 1;
+        }
+
+        self.fields.insert(key, value);
+    }
+
+    fn error(&mut self, err: String) {
+        self.errors.push(err);
+    }
+}
+
+fn vpd_checksum(buf: &[u8]) -> bool {
+    buf.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+//
+// Returns the civil date (year, month, day) for the specified number of days
+// since the Unix epoch.
+//
+fn vpd_civil(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    (yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+//
+// Decodes an IPMI FRU type/length field.
+//
+fn vpd_fru_decode(kind: u8, data: &[u8]) -> String {
+    match kind {
+        0b00 => {
+            if data.is_empty() {
+                String::new()
+            } else {
+                data.iter()
+                    .fold("0x".to_string(), |s, b| s + &format!("{:02x}", b))
+            }
+        }
+        0b01 => data
+            .iter()
+            .flat_map(|b| [b >> 4, b & 0xf])
+            .map(|nibble| match nibble {
+                0..=9 => (b'0' + nibble) as char,
+                0xa => ' ',
+                0xb => '-',
+                0xc => '.',
+                _ => '?',
+            })
+            .collect(),
+        0b10 => {
+            let nchars = data.len() * 8 / 6;
+
+            (0..nchars)
+                .map(|i| {
+                    let bit = i * 6;
+                    let lo = data[bit / 8] as u16;
+                    let hi = *data.get(bit / 8 + 1).unwrap_or(&0) as u16;
+                    let val = (((hi << 8) | lo) >> (bit % 8)) & 0x3f;
+                    (val as u8 + 0x20) as char
+                })
+                .collect()
+        }
+        _ => data
+            .iter()
+            .map(|&b| b as char)
+            .collect::<String>()
+            .trim_end_matches(|c| c == '\0' || c == ' ')
+            .to_string(),
+    }
+}
+
+//
+// Parses the type/length fields in an IPMI FRU info area, starting at the
+// specified offset and ending with the end-of-fields marker.
+//
+fn vpd_fru_fields(
+    vpd: &mut Vpd,
+    area: &[u8],
+    offset: usize,
+    prefix: &str,
+    names: &[&str],
+) {
+    let mut offset = offset;
+
+    for n in 0.. {
+        let tl = match area.get(offset) {
+            Some(0xc1) => break,
+            Some(tl) => *tl,
+            None => {
+                vpd.error(format!("{} area: missing end-of-fields", prefix));
+                break;
+            }
+        };
+
+        let len = (tl & 0x3f) as usize;
+
+        let data = match area.get(offset + 1..offset + 1 + len) {
+            Some(data) => data,
+            None => {
+                vpd.error(format!("{} area: field {} is truncated", prefix, n));
+                break;
+            }
+        };
+
+        let name = match names.get(n) {
+            Some(name) => format!("{}.{}", prefix, name),
+            None => format!("{}.custom", prefix),
+        };
+
+        let value = vpd_fru_decode(tl >> 6, data);
+        vpd.field(
+            &name,
+            if value.is_empty() { "-".to_string() } else { value },
+        );
+
+        offset += 1 + len;
+    }
+}
+
+fn vpd_parse_fru(vpd: &mut Vpd, buf: &[u8]) {
+    vpd.format = "IPMI FRU".to_string();
+
+    let header = &buf[..8];
+
+    if !vpd_checksum(header) {
+        vpd.error("common header checksum mismatch".to_string());
+        return;
+    }
+
+    let areas = [(2, "chassis"), (3, "board"), (4, "product")];
+
+    for (ndx, prefix) in areas {
+        if header[ndx] == 0 {
+            continue;
+        }
+
+        let start = header[ndx] as usize * 8;
+
+        let len = match buf.get(start + 1) {
+            Some(len) => *len as usize * 8,
+            None => {
+                vpd.error(format!("{} area is beyond end of EEPROM", prefix));
+                continue;
+            }
+        };
+
+        let area = match buf.get(start..start + len) {
+            Some(area) if len >= 3 => area,
+            _ => {
+                vpd.error(format!(
+                    "{} area has invalid length {}",
+                    prefix, len
+                ));
+                continue;
+            }
+        };
+
+        if !vpd_checksum(area) {
+            vpd.error(format!("{} area checksum mismatch", prefix));
+        }
+
+        match prefix {
+            "chassis" => {
+                vpd.field("chassis.type", format!("0x{:02x}", area[2]));
+                vpd_fru_fields(vpd, area, 3, prefix, &["part", "serial"]);
+            }
+            "board" => {
+                let minutes = match area.get(3..6) {
+                    Some(m) => u32::from_le_bytes([m[0], m[1], m[2], 0]),
+                    None => 0,
+                };
+
+                //
+                // The manufacturing date is in minutes since 1996-01-01
+                // (which is 9496 days after the Unix epoch); zero denotes
+                // an unspecified date.
+                //
+                vpd.field(
+                    "board.mfg_date",
+                    if minutes == 0 {
+                        "-".to_string()
+                    } else {
+                        let (y, m, d) =
+                            vpd_civil(9496 + (minutes / 1440) as i64);
+                        let (hh, mm) = ((minutes % 1440) / 60, minutes % 60);
+                        format!("{}-{:02}-{:02} {:02}:{:02}", y, m, d, hh, mm)
+                    },
+                );
+
+                vpd_fru_fields(
+                    vpd,
+                    area,
+                    6,
+                    prefix,
+                    &[
+                        "manufacturer",
+                        "product",
+                        "serial",
+                        "part",
+                        "fru_file_id",
+                    ],
+                );
+            }
+            _ => {
+                vpd_fru_fields(
+                    vpd,
+                    area,
+                    3,
+                    prefix,
+                    &[
+                        "manufacturer",
+                        "product",
+                        "part",
+                        "version",
+                        "serial",
+                        "asset_tag",
+                        "fru_file_id",
+                    ],
+                );
+            }
+        }
+    }
+
+    if header[5] == 0 {
+        return;
+    }
+
+    //
+    // The MultiRecord area consists of records, each with a 5-byte header
+    // that has its own checksum; we check each record, but don't otherwise
+    // interpret them.
+    //
+    let mut offset = header[5] as usize * 8;
+
+    loop {
+        let hdr = match buf.get(offset..offset + 5) {
+            Some(hdr) => hdr,
+            None => {
+                vpd.error("MultiRecord area is truncated".to_string());
+                break;
+            }
+        };
+
+        if !vpd_checksum(hdr) {
+            vpd.error(format!(
+                "MultiRecord header checksum mismatch at 0x{:x}",
+                offset
+            ));
+            break;
+        }
+
+        let len = hdr[2] as usize;
+
+        match buf.get(offset + 5..offset + 5 + len) {
+            Some(data) => {
+                let sum =
+                    data.iter().fold(hdr[3], |sum, b| sum.wrapping_add(*b));
+
+                if sum != 0 {
+                    vpd.error(format!(
+                        "MultiRecord checksum mismatch at 0x{:x}",
+                        offset
+                    ));
+                }
+
+                vpd.field(
+                    "multirecord",
+                    format!("type 0x{:02x}, {} bytes", hdr[0], len),
+                );
+            }
+            None => {
+                vpd.error("MultiRecord area is truncated".to_string());
+                break;
+            }
+        }
+
+        if hdr[1] & 0x80 != 0 {
+            break;
+        }
+
+        offset += 5 + len;
+    }
+}
+
+fn vpd_parse_onie(vpd: &mut Vpd, buf: &[u8]) {
+    vpd.format = "ONIE TlvInfo".to_string();
+
+    const HEADER: usize = 11;
+
+    let total = u16::from_be_bytes([buf[9], buf[10]]) as usize;
+
+    let tlvs = match buf.get(HEADER..HEADER + total) {
+        Some(tlvs) => tlvs,
+        None => {
+            vpd.error(format!("TLV length {} exceeds EEPROM size", total));
+            return;
+        }
+    };
+
+    let mut offset = 0;
+    let mut crc = false;
+
+    while offset < tlvs.len() {
+        let (kind, len) = match tlvs.get(offset..offset + 2) {
+            Some(tl) => (tl[0], tl[1] as usize),
+            None => {
+                vpd.error("TLV is truncated".to_string());
+                break;
+            }
+        };
+
+        let value = match tlvs.get(offset + 2..offset + 2 + len) {
+            Some(value) => value,
+            None => {
+                vpd.error(format!("TLV 0x{:02x} is truncated", kind));
+                break;
+            }
+        };
+
+        let string = || String::from_utf8_lossy(value).to_string();
+        let hex =
+            || value.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+        let (name, value) = match kind {
+            0x21 => ("product_name", string()),
+            0x22 => ("part_number", string()),
+            0x23 => ("serial_number", string()),
+            0x24 if len == 6 => (
+                "mac_base",
+                value
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(":"),
+            ),
+            0x25 => ("manufacture_date", string()),
+            0x26 if len == 1 => ("device_version", value[0].to_string()),
+            0x27 => ("label_revision", string()),
+            0x28 => ("platform_name", string()),
+            0x29 => ("onie_version", string()),
+            0x2a if len == 2 => (
+                "mac_count",
+                u16::from_be_bytes([value[0], value[1]]).to_string(),
+            ),
+            0x2b => ("manufacturer", string()),
+            0x2c => ("country_code", string()),
+            0x2d => ("vendor", string()),
+            0x2e => ("diag_version", string()),
+            0x2f => ("service_tag", string()),
+            0xfd => ("vendor_extension", format!("0x{}", hex())),
+            0xfe if len == 4 => {
+                //
+                // The CRC-32 covers everything that precedes its value,
+                // including the type and length of the CRC TLV itself.
+                //
+                let expected = u32::from_be_bytes(value.try_into().unwrap());
+                let computed = crc32fast::hash(&buf[..HEADER + offset + 2]);
+
+                if expected != computed {
+                    vpd.error(format!(
+                        "CRC-32 mismatch: expected 0x{:08x}, found 0x{:08x}",
+                        expected, computed
+                    ));
+                }
+
+                crc = true;
+                ("crc32", format!("0x{:08x}", expected))
+            }
+            0x24 | 0x26 | 0x2a | 0xfe => {
+                vpd.error(format!(
+                    "TLV 0x{:02x} has unexpected length {}",
+                    kind, len
+                ));
+                ("invalid", format!("0x{:02x}: 0x{}", kind, hex()))
+            }
+            _ => ("unknown", format!("0x{:02x}: 0x{}", kind, hex())),
+        };
+
+        vpd.field(name, value);
+        offset += 2 + len;
+
+        if crc {
+            break;
+        }
+    }
+
+    if !crc {
+        vpd.error("missing CRC-32".to_string());
+    }
+}
+
+fn vpd_parse(buf: &[u8]) -> Vpd {
+    let mut vpd = Vpd::default();
+
+    if buf.len() >= 11 && buf.starts_with(b"TlvInfo\0") {
+        vpd_parse_onie(&mut vpd, buf);
+    } else if buf.len() >= 8 && buf[0] == 0x01 {
+        vpd_parse_fru(&mut vpd, buf);
+    } else if buf.iter().all(|&b| b == 0xff) {
+        vpd.format = "blank".to_string();
+        vpd.error("EEPROM is blank".to_string());
+    } else {
+        vpd.format = "unrecognized".to_string();
+        vpd.error("EEPROM contents are in an unrecognized format".to_string());
+    }
+
+    vpd
+}
+
+//
+// Reads the entire contents of an EEPROM.  For EEPROMs with 16-bit
+// addresses, each read is preceded by a write of the address.
+//
+fn vpd_read(
+    core: &mut dyn Core,
+    context: &mut HiffyContext,
+    funcs: &HiffyFunctions,
+    device: &HubrisI2cDevice,
+    size: u32,
+    width: u8,
+) -> Result<Vec<u8>> {
+    let hargs = I2cArgs::from_device(device);
+    let addressing = I2cRegisterAddressing { width, endian: I2cEndian::Big };
+
+    addressing.read_contents(
+        core,
+        context,
+        funcs,
+        &hargs,
+        VPD_READ_SIZE,
+        size,
+        |_| {},
+    )
+}
+
+#[rustfmt::skip::macros(println)]
+fn vpd(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    _args: &Args,
+    subargs: &[String],
+) -> Result<()> {
+    let subargs = VpdArgs::try_parse_from(subargs)?;
+
+    let eeproms = hubris
+        .manifest
+        .i2c_devices
+        .iter()
+        .filter_map(|d| {
+            VPD_EEPROMS
+                .iter()
+                .find(|(part, _, _)| *part == d.device)
+                .map(|&(_, size, width)| (d, size, width))
+        })
+        .enumerate()
+        .filter(|(ndx, (d, _, _))| {
+            subargs.id.map_or(true, |id| id == *ndx)
+                && subargs.device.as_ref().map_or(true, |name| {
+                    *name == d.device || d.name.as_ref() == Some(name)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    if eeproms.is_empty() {
+        bail!("no matching VPD EEPROMs found");
+    }
+
+    if subargs.list {
+        println!("{:2} {:1} {:2} {:3} {:4} {:13} DESCRIPTION",
+            "ID", "C", "P", "MUX", "ADDR", "DEVICE");
+
+        for (ndx, (d, _, _)) in &eeproms {
+            let mux = match (d.mux, d.segment) {
+                (Some(m), Some(s)) => format!("{}:{}", m, s),
+                _ => "-".to_string(),
+            };
+
+            println!("{:2} {:1} {:2} {:3} 0x{:02x} {:13} {}",
+                ndx, d.controller, d.port.name, mux, d.address, d.device,
+                d.description);
+        }
+
+        return Ok(());
+    }
+
+    let mut context = HiffyContext::new(hubris, core, subargs.timeout)?;
+    let funcs = context.functions()?;
+
+    let mut json = vec![];
+    let mut failed = 0;
+
+    for (ndx, (d, size, width)) in &eeproms {
+        let vpd = match vpd_read(core, &mut context, &funcs, d, *size, *width) {
+            Ok(contents) => vpd_parse(&contents),
+            Err(err) => Vpd {
+                format: "unknown".to_string(),
+                errors: vec![format!("{}", err)],
+                ..Vpd::default()
+            },
+        };
+
+        let hargs = I2cArgs::from_device(d);
+
+        if !vpd.errors.is_empty() {
+            failed += 1;
+        }
+
+        if subargs.json {
+            json.push(JsonVpd {
+                id: *ndx,
+                device: &d.device,
+                name: d.name.as_deref(),
+                description: &d.description,
+                controller: d.controller,
+                port: &d.port.name,
+                mux: d.mux,
+                segment: d.segment,
+                address: d.address,
+                vpd,
+            });
+        } else if subargs.validate {
+            if vpd.errors.is_empty() {
+                println!("{} ({}): ok ({})", hargs, d.device, vpd.format);
+            } else {
+                println!("{} ({}): {}", hargs, d.device, vpd.errors.join("; "));
+            }
+        } else {
+            println!("{} ({}): {}", hargs, d.device, d.description);
+            println!("    {:24} {}", "format", vpd.format);
+
+            for (name, value) in &vpd.fields {
+                println!("    {:24} {}", name, value);
+            }
+
+            for err in &vpd.errors {
+                println!("    error: {}", err);
+            }
+        }
+    }
+
+    if subargs.json {
+        println!("{}", serde_json::to_string_pretty(&json)?);
+    }
+
+    if subargs.validate && failed != 0 {
+        bail!("{} of {} EEPROMs failed validation", failed, eeproms.len());
+    }
+
+    Ok(())
+}
+
+pub fn init() -> (Command, ClapCommand<'static>) {
+    (
+        Command::Attached {
+            name: "vpd",
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Booted,
+            run: vpd,
+        },
+        VpdArgs::command(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum(buf: &[u8]) -> u8 {
+        0u8.wrapping_sub(buf.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)))
+    }
+
+    fn ascii(s: &str) -> Vec<u8> {
+        let mut field = vec![0xc0 | s.len() as u8];
+        field.extend_from_slice(s.as_bytes());
+        field
+    }
+
+    //
+    // Builds a FRU info area from the bytes that follow its version and
+    // length, adding the end-of-fields marker, padding and checksum.
+    //
+    fn fru_area(body: &[u8]) -> Vec<u8> {
+        let mut area = vec![0x01, 0];
+        area.extend_from_slice(body);
+        area.push(0xc1);
+
+        while (area.len() + 1) % 8 != 0 {
+            area.push(0);
+        }
+
+        area[1] = ((area.len() + 1) / 8) as u8;
+        area.push(checksum(&area));
+        area
+    }
+
+    //
+    // Builds a FRU image from the specified chassis, board, product and
+    // MultiRecord areas (by index in the common header), in that order.
+    //
+    fn fru(areas: &[(usize, Vec<u8>)]) -> Vec<u8> {
+        let mut header = vec![0x01, 0, 0, 0, 0, 0, 0, 0];
+        let mut buf = vec![];
+
+        for (ndx, area) in areas {
+            header[*ndx] = ((header.len() + buf.len()) / 8) as u8;
+            buf.extend_from_slice(area);
+
+            while buf.len() % 8 != 0 {
+                buf.push(0);
+            }
+        }
+
+        header[7] = checksum(&header[..7]);
+        header.extend_from_slice(&buf);
+        header
+    }
+
+    fn board() -> Vec<u8> {
+        let mut body = vec![0x00, 0xfd, 0x9c, 0xc0];
+
+        for field in
+            ["Oxide", "Gimlet", "", "913-0000019", "fru.bin", "ab", "cd"]
+        {
+            body.extend_from_slice(&ascii(field));
+        }
+
+        fru_area(&body)
+    }
+
+    fn onie(tlvs: &[(u8, &[u8])], crc: bool) -> Vec<u8> {
+        let mut buf = b"TlvInfo\0\x01\0\0".to_vec();
+
+        for (kind, value) in tlvs {
+            buf.push(*kind);
+            buf.push(value.len() as u8);
+            buf.extend_from_slice(value);
+        }
+
+        if crc {
+            buf.extend_from_slice(&[0xfe, 4]);
+        }
+
+        let total = (buf.len() - 11 + if crc { 4 } else { 0 }) as u16;
+        buf[9..11].copy_from_slice(&total.to_be_bytes());
+
+        if crc {
+            let crc = crc32fast::hash(&buf);
+            buf.extend_from_slice(&crc.to_be_bytes());
+        }
+
+        buf
+    }
+
+    fn fields(vpd: &Vpd) -> Vec<(&str, &str)> {
+        vpd.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+    }
+
+    #[test]
+    fn fru_board() {
+        let vpd = vpd_parse(&fru(&[(3, board())]));
+
+        assert_eq!(vpd.format, "IPMI FRU");
+        assert_eq!(vpd.errors, Vec::<String>::new());
+        assert_eq!(
+            fields(&vpd),
+            vec![
+                ("board.mfg_date", "2020-01-01 01:01"),
+                ("board.manufacturer", "Oxide"),
+                ("board.product", "Gimlet"),
+                ("board.serial", "-"),
+                ("board.part", "913-0000019"),
+                ("board.fru_file_id", "fru.bin"),
+                ("board.custom", "ab"),
+                ("board.custom1", "cd"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fru_chassis_and_product() {
+        let mut chassis = vec![0x17];
+        chassis.extend(ascii("PN"));
+        chassis.extend(ascii("SN"));
+
+        let mut product = vec![0x00];
+        product.extend(ascii("Oxide"));
+
+        let vpd = vpd_parse(&fru(&[
+            (2, fru_area(&chassis)),
+            (4, fru_area(&product)),
+        ]));
+
+        assert_eq!(vpd.errors, Vec::<String>::new());
+        assert_eq!(
+            fields(&vpd),
+            vec![
+                ("chassis.type", "0x17"),
+                ("chassis.part", "PN"),
+                ("chassis.serial", "SN"),
+                ("product.manufacturer", "Oxide"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fru_header_checksum() {
+        let mut buf = fru(&[(3, board())]);
+        buf[7] = buf[7].wrapping_add(1);
+
+        let vpd = vpd_parse(&buf);
+        assert_eq!(vpd.errors, vec!["common header checksum mismatch"]);
+        assert!(vpd.fields.is_empty());
+    }
+
+    #[test]
+    fn fru_area_checksum() {
+        let mut buf = fru(&[(3, board())]);
+        let last = buf.len() - 1;
+        buf[last] = buf[last].wrapping_add(1);
+
+        let vpd = vpd_parse(&buf);
+        assert_eq!(vpd.errors, vec!["board area checksum mismatch"]);
+        assert_eq!(vpd.fields["board.manufacturer"], "Oxide");
+    }
+
+    #[test]
+    fn fru_truncated_area() {
+        let buf = fru(&[(3, board())]);
+
+        let vpd = vpd_parse(&buf[..buf.len() - 1]);
+        assert_eq!(
+            vpd.errors,
+            vec![format!("board area has invalid length {}", buf.len() - 8)]
+        );
+
+        let vpd = vpd_parse(&buf[..9]);
+        assert_eq!(vpd.errors, vec!["board area is beyond end of EEPROM"]);
+
+        //
+        // An area whose length is too short to hold even its header is
+        // invalid, as is one that lies beyond the EEPROM.
+        //
+        let mut buf = fru(&[(3, board())]);
+        buf[9] = 0;
+        assert_eq!(
+            vpd_parse(&buf).errors,
+            vec!["board area has invalid length 0"]
+        );
+
+        let mut buf = fru(&[(3, board())]);
+        buf[3] = 0x80;
+        buf[7] = checksum(&buf[..7]);
+        assert_eq!(
+            vpd_parse(&buf).errors,
+            vec!["board area is beyond end of EEPROM"]
+        );
+    }
+
+    #[test]
+    fn fru_truncated_fields() {
+        //
+        // A field that runs past the end of its area...
+        //
+        let mut body = vec![0x00];
+        body.extend(ascii("Oxide"));
+        body.push(0xc0 | 0x3f);
+
+        let vpd = vpd_parse(&fru(&[(4, fru_area(&body))]));
+        assert_eq!(vpd.errors, vec!["product area: field 1 is truncated"]);
+        assert_eq!(fields(&vpd), vec![("product.manufacturer", "Oxide")]);
+
+        //
+        // ...and an area that lacks an end-of-fields marker (here, because
+        // its last field runs through its checksum).
+        //
+        let mut area = vec![0x01, 1, 0x00, 0xc4, b'a', b'b', b'c'];
+        area.push(checksum(&area));
+
+        let vpd = vpd_parse(&fru(&[(4, area)]));
+        assert_eq!(vpd.errors, vec!["product area: missing end-of-fields"]);
+    }
+
+    #[test]
+    fn fru_multirecord() {
+        let record = |kind: u8, last: bool, data: &[u8]| {
+            let mut hdr =
+                vec![kind, if last { 0x82 } else { 0x02 }, data.len() as u8];
+            hdr.push(checksum(data));
+            hdr.push(checksum(&hdr));
+            hdr.extend_from_slice(data);
+            hdr
+        };
+
+        let mut records = record(0x00, false, &[1, 2, 3]);
+        records.extend(record(0xc0, true, &[]));
+
+        let vpd = vpd_parse(&fru(&[(5, records.clone())]));
+        assert_eq!(vpd.errors, Vec::<String>::new());
+        assert_eq!(
+            fields(&vpd),
+            vec![
+                ("multirecord", "type 0x00, 3 bytes"),
+                ("multirecord1", "type 0xc0, 0 bytes"),
+            ]
+        );
+
+        let mut bad = records.clone();
+        bad[5] = bad[5].wrapping_add(1);
+        let vpd = vpd_parse(&fru(&[(5, bad)]));
+        assert_eq!(vpd.errors, vec!["MultiRecord checksum mismatch at 0x8"]);
+
+        let mut bad = records.clone();
+        bad[4] = bad[4].wrapping_add(1);
+        let vpd = vpd_parse(&fru(&[(5, bad)]));
+        assert_eq!(
+            vpd.errors,
+            vec!["MultiRecord header checksum mismatch at 0x8"]
+        );
+
+        let buf = fru(&[(5, records)]);
+        let vpd = vpd_parse(&buf[..8 + 6]);
+        assert_eq!(vpd.errors, vec!["MultiRecord area is truncated"]);
+    }
+
+    #[test]
+    fn fru_decode() {
+        assert_eq!(vpd_fru_decode(0b00, &[]), "");
+        assert_eq!(vpd_fru_decode(0b00, &[0xde, 0xad]), "0xdead");
+        assert_eq!(vpd_fru_decode(0b01, &[0x12, 0xab, 0xcf]), "12 -.?");
+        assert_eq!(vpd_fru_decode(0b10, &[0x29, 0xdc, 0xa6]), "IPMI");
+        assert_eq!(vpd_fru_decode(0b11, b"abc\0 \0"), "abc");
+    }
+
+    #[test]
+    fn onie_tlvs() {
+        let vpd = vpd_parse(&onie(
+            &[
+                (0x21, b"Gimlet"),
+                (0x24, &[0xa8, 0x40, 0x25, 0x00, 0x00, 0x01]),
+                (0x2a, &[0x01, 0x00]),
+                (0x26, &[3]),
+                (0x99, &[0xaa]),
+            ],
+            true,
+        ));
+
+        assert_eq!(vpd.format, "ONIE TlvInfo");
+        assert_eq!(vpd.errors, Vec::<String>::new());
+
+        let fields = fields(&vpd);
+        assert_eq!(
+            fields[..5],
+            [
+                ("product_name", "Gimlet"),
+                ("mac_base", "a8:40:25:00:00:01"),
+                ("mac_count", "256"),
+                ("device_version", "3"),
+                ("unknown", "0x99: 0xaa"),
+            ]
+        );
+        assert_eq!(fields[5].0, "crc32");
+    }
+
+    #[test]
+    fn onie_crc_mismatch() {
+        let mut buf = onie(&[(0x21, b"Gimlet")], true);
+        buf[13] ^= 0x20;
+
+        let vpd = vpd_parse(&buf);
+        assert_eq!(vpd.errors.len(), 1);
+        assert!(vpd.errors[0].starts_with("CRC-32 mismatch"));
+        assert_eq!(vpd.fields["product_name"], "gimlet");
+
+        let mut buf = onie(&[(0x21, b"Gimlet")], true);
+        let last = buf.len() - 1;
+        buf[last] ^= 1;
+        assert!(vpd_parse(&buf).errors[0].starts_with("CRC-32 mismatch"));
+    }
+
+    #[test]
+    fn onie_malformed() {
+        let vpd = vpd_parse(&onie(&[(0x21, b"Gimlet")], false));
+        assert_eq!(vpd.errors, vec!["missing CRC-32"]);
+
+        let vpd = vpd_parse(&onie(&[(0x24, &[1, 2, 3])], true));
+        assert_eq!(vpd.errors, vec!["TLV 0x24 has unexpected length 3"]);
+        assert_eq!(vpd.fields["invalid"], "0x24: 0x010203");
+
+        let mut buf = onie(&[(0x21, b"Gimlet")], false);
+        buf[10] += 1;
+        let vpd = vpd_parse(&buf);
+        assert_eq!(vpd.errors, vec!["TLV length 9 exceeds EEPROM size"]);
+
+        let mut buf = onie(&[(0x21, b"Gimlet")], false);
+        buf[12] = 7;
+        let vpd = vpd_parse(&buf);
+        assert_eq!(vpd.errors, vec!["TLV 0x21 is truncated", "missing CRC-32"]);
+
+        let mut buf = onie(&[(0x21, b"Gimlet")], false);
+        buf.push(0x22);
+        buf[10] += 1;
+        let vpd = vpd_parse(&buf);
+        assert_eq!(vpd.errors, vec!["TLV is truncated", "missing CRC-32"]);
+    }
+
+    #[test]
+    fn unrecognized() {
+        let vpd = vpd_parse(&[0xff; 16]);
+        assert_eq!(vpd.format, "blank");
+
+        let vpd = vpd_parse(&[0x02; 16]);
+        assert_eq!(vpd.format, "unrecognized");
+
+        let vpd = vpd_parse(b"TlvInfo\0");
+        assert_eq!(vpd.format, "unrecognized");
+    }
+}
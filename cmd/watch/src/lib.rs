@@ -0,0 +1,235 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility watch`
+//!
+//! `humility watch` periodically samples one or more variables on a live
+//! system, printing each sample with any values that have changed since the
+//! previous sample highlighted.  Variables may be specified with paths
+//! within them, as with `humility readvar`:
+//!
+//! ```console
+//! % humility watch FAN_MODE 'FAN_CONFIG.pwm[2]'
+//! humility: attached via ST-Link V3
+//!      0.000 FAN_MODE = Auto
+//!      0.000 FAN_CONFIG.pwm[2] = 0x1e
+//!      1.001 FAN_MODE = Auto
+//!      1.001 FAN_CONFIG.pwm[2] = 0x1e
+//!      2.002 FAN_MODE = Manual
+//!      2.002 FAN_MODE.Manual.pwm = 0x32
+//!      2.002 FAN_CONFIG.pwm[2] = 0x32
+//! ...
+//! ```
+//!
+//! Each line is preceded by the time (in seconds) since sampling began.
+//! Structures, tuples and arrays are displayed as their individual fields;
+//! an enum is displayed as its variant, followed by the fields of the
+//! variant's contents (if any).  To display only the fields that have
+//! changed, use `-c` (`--changes`).
+//!
+//! Variables are sampled every second by default; an interval (in
+//! milliseconds) can be specified with `-i` (`--interval`), and the number
+//! of samples to take with `-n` (`--num`).  If `-o` (`--output`) is
+//! provided, every field of every sample is additionally logged to the
+//! specified CSV file as a row consisting of the time, the name of the
+//! field, and its value.  The target is halted while it is sampled.
+//!
+
+use anyhow::{bail, Result};
+use clap::Command as ClapCommand;
+use clap::{CommandFactory, Parser};
+use colored::Colorize;
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cmd::reflect::{self, Value};
+use humility_cmd::varpath::VariablePath;
+use humility_cmd::{Archive, Args, Attach, Command, Validate};
+use indexmap::IndexMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[clap(name = "watch", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct WatchArgs {
+    /// interval between samples
+    #[clap(
+        long, short, default_value = "1000", value_name = "ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    interval: u64,
+
+    /// number of samples to take
+    #[clap(
+        long, short, value_name = "samples",
+        parse(try_from_str = parse_int::parse)
+    )]
+    num: Option<usize>,
+
+    /// display only fields that have changed
+    #[clap(long, short)]
+    changes: bool,
+
+    /// values in decimal instead of hex
+    #[clap(long, short)]
+    decimal: bool,
+
+    /// CSV file to which to log samples
+    #[clap(long, short)]
+    output: Option<String>,
+
+    /// variables to watch, optionally with paths within them
+    #[clap(required = true)]
+    variables: Vec<String>,
+}
+
+struct Watched<'a> {
+    expr: &'a str,
+    path: VariablePath<'a>,
+    variable: &'a HubrisVariable,
+}
+
+fn watch_read(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    watched: &Watched,
+) -> Result<Value> {
+    let (addr, goff) = watched.path.resolve(hubris, core, watched.variable)?;
+    let ty = hubris.lookup_type(goff)?;
+
+    if let HubrisType::Union(_) = ty {
+        bail!("cannot watch union {}", ty.name(hubris)?);
+    }
+
+    let mut buf = vec![0u8; ty.size(hubris)?];
+    core.read_8(addr, &mut buf)?;
+
+    reflect::load_value(hubris, &buf, ty, 0)
+}
+
+//
+// Takes a sample of each watched variable, returning its fields.  A variable
+// that cannot be read (e.g., because its path names a variant of an enum
+// that is currently some other variant) results in an error in lieu of its
+// value.
+//
+fn watch_sample(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    watched: &[Watched],
+    fmt: HubrisPrintFormat,
+) -> Result<IndexMap<String, String>> {
+    core.halt()?;
+
+    let values =
+        watched.iter().map(|w| watch_read(hubris, core, w)).collect::<Vec<_>>();
+
+    core.run()?;
+
+    let mut fields = IndexMap::new();
+
+    for (w, value) in watched.iter().zip(values) {
+        match value {
+            Ok(value) => {
+                reflect::flatten(hubris, fmt, w.expr, &value, &mut fields)?
+            }
+            Err(err) => {
+                fields.insert(w.expr.to_string(), format!("<{}>", err));
+            }
+        }
+    }
+
+    Ok(fields)
+}
+
+fn watch(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    _args: &Args,
+    subargs: &[String],
+) -> Result<()> {
+    let subargs = WatchArgs::try_parse_from(subargs)?;
+    let mut watched = vec![];
+
+    for expr in &subargs.variables {
+        let path = VariablePath::parse(expr)?;
+        let variable = path.variable(hubris)?;
+        watched.push(Watched { expr, path, variable });
+    }
+
+    let fmt = HubrisPrintFormat {
+        hex: !subargs.decimal,
+        ..HubrisPrintFormat::default()
+    };
+
+    let mut output = match &subargs.output {
+        Some(output) => {
+            let mut wtr = csv::Writer::from_path(output)?;
+            wtr.write_record(&["time", "field", "value"])?;
+            Some(wtr)
+        }
+        None => None,
+    };
+
+    let interval = Duration::from_millis(subargs.interval);
+    let start = Instant::now();
+    let mut last: Option<IndexMap<String, String>> = None;
+    let mut nsamples = 0;
+
+    loop {
+        let now = Instant::now();
+        let time = format!("{:.3}", (now - start).as_secs_f64());
+        let fields = watch_sample(hubris, core, &watched, fmt)?;
+
+        for (name, value) in &fields {
+            let changed = match &last {
+                Some(last) => last.get(name) != Some(value),
+                None => false,
+            };
+
+            if subargs.changes && last.is_some() && !changed {
+                continue;
+            }
+
+            if changed {
+                println!("{:>10} {} = {}", time, name, value.bold());
+            } else {
+                println!("{:>10} {} = {}", time, name, value);
+            }
+        }
+
+        if let Some(ref mut output) = output {
+            for (name, value) in &fields {
+                output.write_record(&[&time, name, value])?;
+            }
+
+            output.flush()?;
+        }
+
+        last = Some(fields);
+        nsamples += 1;
+
+        if let Some(num) = subargs.num {
+            if nsamples >= num {
+                break;
+            }
+        }
+
+        thread::sleep(interval.saturating_sub(now.elapsed()));
+    }
+
+    Ok(())
+}
+
+pub fn init() -> (Command, ClapCommand<'static>) {
+    (
+        Command::Attached {
+            name: "watch",
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Match,
+            run: watch,
+        },
+        WatchArgs::command(),
+    )
+}
@@ -0,0 +1,145 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ## `humility writevar`
+//!
+//! `humility writevar` allows one to write a global static variable on a
+//! live system, e.g. to change a tuning parameter or to enable debugging
+//! within a task.  Specify the variable and its new value:
+//!
+//! ```console
+//! % humility writevar DEBUG_LEVEL 3
+//! humility: attached via ST-Link V3
+//! DEBUG_LEVEL (0x20001a3c): 0x0 -> 0x3
+//! ```
+//!
+//! A part of a variable (e.g., a member of a structure or an element of an
+//! array) can be written by specifying its path, as with `humility readvar`:
+//!
+//! ```console
+//! % humility writevar 'FAN_CONFIG.pwm[2]' 40
+//! humility: attached via ST-Link V3
+//! FAN_CONFIG.pwm[2] (0x20004e1a): 0x1e -> 0x28
+//! ```
+//!
+//! Values are checked against (and encoded according to) the type of the
+//! variable, and are specified in the same way as arguments to `humility
+//! hiffy`:  numbers for base types, variant names for enums, and Rust-like
+//! or JSON syntax for structures, arrays, and enums with values:
+//!
+//! ```console
+//! % humility writevar FAN_MODE 'Manual { pwm: 50 }'
+//! humility: attached via ST-Link V3
+//! FAN_MODE (0x20004e10): Auto -> Manual {
+//!         pwm: 0x32
+//!     }
+//! ```
+//!
+//! If the variable is present in more than one task, its name must be
+//! qualified with the path of the task (e.g., `task_thermal::FAN_MODE`);
+//! use `humility readvar -l` to list variables.  The target is halted while
+//! the variable is written.
+//!
+
+use anyhow::{bail, Context, Result};
+use clap::Command as ClapCommand;
+use clap::{CommandFactory, Parser};
+use humility::core::Core;
+use humility::hubris::*;
+use humility_cmd::idol::{self, IdolLiteral};
+use humility_cmd::varpath::VariablePath;
+use humility_cmd::{Archive, Args, Attach, Command, Validate};
+
+#[derive(Parser, Debug)]
+#[clap(name = "writevar", about = env!("CARGO_PKG_DESCRIPTION"))]
+struct WritevarArgs {
+    /// variable to write, optionally with a member or element within it
+    variable: String,
+
+    /// value to write
+    value: String,
+}
+
+//
+// Writes the value with the target halted, returning the address and type
+// written along with the contents before and after the write.
+//
+fn writevar_halted(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    path: &VariablePath,
+    literal: &IdolLiteral,
+) -> Result<(u32, HubrisGoff, Vec<u8>, Vec<u8>)> {
+    let variable = path.variable(hubris)?;
+    let (addr, goff) = path.resolve(hubris, core, variable)?;
+    let size = hubris.typesize(goff)?;
+
+    let mut buf = vec![0u8; size];
+
+    idol::encode(hubris, goff, literal, &mut buf)
+        .with_context(|| format!("illegal value for {}", path.name))?;
+
+    let mut old = vec![0u8; size];
+    let mut new = vec![0u8; size];
+
+    core.read_8(addr, &mut old)?;
+    core.write_8(addr, &buf)?;
+    core.read_8(addr, &mut new)?;
+
+    if new != buf {
+        bail!(
+            "write to 0x{:08x} did not take effect; is it in read-only memory?",
+            addr
+        );
+    }
+
+    Ok((addr, goff, old, new))
+}
+
+fn writevar(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    _args: &Args,
+    subargs: &[String],
+) -> Result<()> {
+    let subargs = WritevarArgs::try_parse_from(subargs)?;
+
+    let path = VariablePath::parse(&subargs.variable)?;
+    let literal = IdolLiteral::parse(&subargs.value)?;
+
+    core.halt()?;
+    let rval = writevar_halted(hubris, core, &path, &literal);
+    core.run()?;
+
+    let (addr, goff, old, new) = rval?;
+
+    let fmt = HubrisPrintFormat {
+        newline: true,
+        hex: true,
+        ..HubrisPrintFormat::default()
+    };
+
+    println!(
+        "{} (0x{:08x}): {} -> {}",
+        subargs.variable,
+        addr,
+        hubris.printfmt(&old, goff, &fmt)?,
+        hubris.printfmt(&new, goff, &fmt)?
+    );
+
+    Ok(())
+}
+
+pub fn init() -> (Command, ClapCommand<'static>) {
+    (
+        Command::Attached {
+            name: "writevar",
+            archive: Archive::Required,
+            attach: Attach::LiveOnly,
+            validate: Validate::Match,
+            run: writevar,
+        },
+        WritevarArgs::command(),
+    )
+}
@@ -141,6 +141,7 @@ register!(CFSR, 0xe000_ed28,
     impl Debug;
     pub usage_divide_by_zero, _: 16 + 9;
     pub usage_unaligned, _: 16 + 8;
+    pub usage_stack_overflow, _: 16 + 4;
     pub usage_no_coprocessor, _: 16 + 3;
     pub usage_invalid_pc, _: 16 + 2;
     pub usage_invalid_state, _: 16 + 1;
@@ -169,6 +170,68 @@ register!(HFSR, 0xe000_ed2c,
     pub vector_fault, _: 1;
 );
 
+/*
+ * Interrupt Control and State Register
+ */
+register!(ICSR, 0xe000_ed04,
+    #[derive(Copy, Clone)]
+    pub struct ICSR(u32);
+    impl Debug;
+    pub nmi_pending, _: 31;
+    pub pendsv_pending, _: 28;
+    pub systick_pending, _: 26;
+    pub isr_pending, _: 22;
+    pub vect_pending, _: 20, 12;
+    pub ret_to_base, _: 11;
+    pub vect_active, _: 8, 0;
+);
+
+/*
+ * System Handler Control and State Register
+ */
+register!(SHCSR, 0xe000_ed24,
+    #[derive(Copy, Clone)]
+    pub struct SHCSR(u32);
+    impl Debug;
+    pub usage_fault_enabled, _: 18;
+    pub bus_fault_enabled, _: 17;
+    pub mem_fault_enabled, _: 16;
+    pub svcall_pended, _: 15;
+    pub bus_fault_pended, _: 14;
+    pub mem_fault_pended, _: 13;
+    pub usage_fault_pended, _: 12;
+    pub systick_active, _: 11;
+    pub pendsv_active, _: 10;
+    pub monitor_active, _: 8;
+    pub svcall_active, _: 7;
+    pub nmi_active, _: 5;
+    pub secure_fault_active, _: 4;
+    pub usage_fault_active, _: 3;
+    pub hard_fault_active, _: 2;
+    pub bus_fault_active, _: 1;
+    pub mem_fault_active, _: 0;
+);
+
+/*
+ * MemManage Fault Address Register
+ */
+register!(MMFAR, 0xe000_ed34,
+    #[derive(Copy, Clone)]
+    pub struct MMFAR(u32);
+    impl Debug;
+    pub address, _: 31, 0;
+);
+
+/*
+ * BusFault Address Register
+ */
+register!(BFAR, 0xe000_ed38,
+    #[derive(Copy, Clone)]
+    pub struct BFAR(u32);
+    impl Debug;
+    pub address, _: 31, 0;
+);
+
 /*
  * Debug Fault Status Register
  */
@@ -0,0 +1,368 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::debug::*;
+use anyhow::Result;
+use humility::core::Core;
+
+/// Returns the name of the exception with the specified vector number, as
+/// found in (e.g.) `ICSR.VECTACTIVE`.
+pub fn exception_name(vector: u32) -> String {
+    match vector {
+        0 => "none (thread mode)".to_string(),
+        1 => "Reset".to_string(),
+        2 => "NMI".to_string(),
+        3 => "HardFault".to_string(),
+        4 => "MemManage".to_string(),
+        5 => "BusFault".to_string(),
+        6 => "UsageFault".to_string(),
+        7 => "SecureFault".to_string(),
+        11 => "SVCall".to_string(),
+        12 => "DebugMonitor".to_string(),
+        14 => "PendSV".to_string(),
+        15 => "SysTick".to_string(),
+        16.. => format!("IRQ {}", vector - 16),
+        _ => format!("reserved exception {}", vector),
+    }
+}
+
+/// A snapshot of the exception and fault status registers in the System
+/// Control Block.
+#[derive(Copy, Clone, Debug)]
+pub struct FaultStatus {
+    pub icsr: ICSR,
+    pub shcsr: SHCSR,
+    pub cfsr: CFSR,
+    pub hfsr: HFSR,
+    pub mmfar: MMFAR,
+    pub bfar: BFAR,
+}
+
+impl FaultStatus {
+    pub fn read(core: &mut dyn Core) -> Result<Self> {
+        Ok(Self {
+            icsr: ICSR::read(core)?,
+            shcsr: SHCSR::read(core)?,
+            cfsr: CFSR::read(core)?,
+            hfsr: HFSR::read(core)?,
+            mmfar: MMFAR::read(core)?,
+            bfar: BFAR::read(core)?,
+        })
+    }
+
+    /// Returns true if any fault status bits are set
+    pub fn is_faulted(&self) -> bool {
+        u32::from(self.cfsr) != 0 || u32::from(self.hfsr) != 0
+    }
+
+    /// Returns the names of the exceptions that are active, with the
+    /// currently executing exception first.
+    pub fn active(&self) -> Vec<String> {
+        let shcsr = &self.shcsr;
+        let current = self.icsr.vect_active();
+        let mut rval = vec![];
+
+        if current != 0 {
+            rval.push(exception_name(current));
+        }
+
+        let handlers = [
+            (shcsr.nmi_active(), 2),
+            (shcsr.hard_fault_active(), 3),
+            (shcsr.mem_fault_active(), 4),
+            (shcsr.bus_fault_active(), 5),
+            (shcsr.usage_fault_active(), 6),
+            (shcsr.secure_fault_active(), 7),
+            (shcsr.svcall_active(), 11),
+            (shcsr.monitor_active(), 12),
+            (shcsr.pendsv_active(), 14),
+            (shcsr.systick_active(), 15),
+        ];
+
+        for (active, vector) in handlers {
+            if active && vector != current {
+                rval.push(exception_name(vector));
+            }
+        }
+
+        rval
+    }
+
+    /// Returns the names of the exceptions that are pending, with the
+    /// highest priority pending exception first.
+    pub fn pending(&self) -> Vec<String> {
+        let icsr = &self.icsr;
+        let shcsr = &self.shcsr;
+        let highest = icsr.vect_pending();
+        let mut rval = vec![];
+
+        if highest != 0 {
+            rval.push(exception_name(highest));
+        }
+
+        let handlers = [
+            (icsr.nmi_pending(), 2),
+            (shcsr.mem_fault_pended(), 4),
+            (shcsr.bus_fault_pended(), 5),
+            (shcsr.usage_fault_pended(), 6),
+            (shcsr.svcall_pended(), 11),
+            (icsr.pendsv_pending(), 14),
+            (icsr.systick_pending(), 15),
+        ];
+
+        for (pending, vector) in handlers {
+            if pending && vector != highest {
+                rval.push(exception_name(vector));
+            }
+        }
+
+        rval
+    }
+
+    /// Explains each fault status bit that is set, in the order in which
+    /// the fault status registers are laid out.
+    pub fn explain(&self) -> Vec<String> {
+        let cfsr = &self.cfsr;
+        let hfsr = &self.hfsr;
+        let mut rval = vec![];
+
+        let mut explain = |set: bool, what: &str, bit: &str| {
+            if set {
+                rval.push(format!("{} ({})", what, bit));
+            }
+        };
+
+        explain(
+            cfsr.mem_instr_access(),
+            "MemManage: instruction fetch from memory that does not permit \
+            execution",
+            "IACCVIOL",
+        );
+        explain(
+            cfsr.mem_data_access(),
+            "MemManage: data access to memory that does not permit it",
+            "DACCVIOL",
+        );
+        explain(
+            cfsr.mem_exception_return(),
+            "MemManage: fault on unstacking for a return from exception",
+            "MUNSTKERR",
+        );
+        explain(
+            cfsr.mem_exception_entry(),
+            "MemManage: fault on stacking for exception entry",
+            "MSTKERR",
+        );
+        explain(
+            cfsr.mem_lazy_fp(),
+            "MemManage: fault during lazy floating-point state preservation",
+            "MLSPERR",
+        );
+        explain(
+            cfsr.bus_instr_prefetch(),
+            "BusFault: bus error on instruction prefetch",
+            "IBUSERR",
+        );
+        explain(
+            cfsr.bus_precise_data(),
+            "BusFault: precise data bus error",
+            "PRECISERR",
+        );
+        explain(
+            cfsr.bus_imprecise_data(),
+            "BusFault: imprecise data bus error; the faulting instruction \
+            is not known",
+            "IMPRECISERR",
+        );
+        explain(
+            cfsr.bus_exception_return(),
+            "BusFault: fault on unstacking for a return from exception",
+            "UNSTKERR",
+        );
+        explain(
+            cfsr.bus_exception_entry(),
+            "BusFault: fault on stacking for exception entry",
+            "STKERR",
+        );
+        explain(
+            cfsr.bus_lazy_fp(),
+            "BusFault: fault during lazy floating-point state preservation",
+            "LSPERR",
+        );
+        explain(
+            cfsr.usage_undefined_instr(),
+            "UsageFault: attempt to execute an undefined instruction",
+            "UNDEFINSTR",
+        );
+        explain(
+            cfsr.usage_invalid_state(),
+            "UsageFault: attempt to execute in an invalid state (e.g., with \
+            the Thumb bit clear)",
+            "INVSTATE",
+        );
+        explain(
+            cfsr.usage_invalid_pc(),
+            "UsageFault: invalid EXC_RETURN on return from exception",
+            "INVPC",
+        );
+        explain(
+            cfsr.usage_no_coprocessor(),
+            "UsageFault: attempt to use a coprocessor (e.g., the FPU) that \
+            is absent or disabled",
+            "NOCP",
+        );
+        explain(
+            cfsr.usage_stack_overflow(),
+            "UsageFault: stack overflow detected by stack limit check",
+            "STKOF",
+        );
+        explain(
+            cfsr.usage_unaligned(),
+            "UsageFault: unaligned memory access",
+            "UNALIGNED",
+        );
+        explain(
+            cfsr.usage_divide_by_zero(),
+            "UsageFault: divide by zero",
+            "DIVBYZERO",
+        );
+        explain(
+            hfsr.vector_fault(),
+            "HardFault: bus fault on vector table read",
+            "VECTTBL",
+        );
+        explain(
+            hfsr.forced_fault(),
+            "HardFault: escalated from a fault that could not be handled",
+            "FORCED",
+        );
+        explain(
+            hfsr.debug_fault(),
+            "HardFault: debug event with halting debug disabled",
+            "DEBUGEVT",
+        );
+
+        if cfsr.mem_addr_valid() {
+            rval.push(format!(
+                "MemManage fault address is 0x{:08x} (MMFAR)",
+                self.mmfar.address()
+            ));
+        }
+
+        if cfsr.bus_addr_valid() {
+            rval.push(format!(
+                "BusFault address is 0x{:08x} (BFAR)",
+                self.bfar.address()
+            ));
+        }
+
+        rval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fault_status(
+        icsr: u32,
+        shcsr: u32,
+        cfsr: u32,
+        hfsr: u32,
+    ) -> FaultStatus {
+        FaultStatus {
+            icsr: ICSR::from(icsr),
+            shcsr: SHCSR::from(shcsr),
+            cfsr: CFSR::from(cfsr),
+            hfsr: HFSR::from(hfsr),
+            mmfar: MMFAR::from(0x10),
+            bfar: BFAR::from(0x2000_1000),
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(exception_name(0), "none (thread mode)");
+        assert_eq!(exception_name(3), "HardFault");
+        assert_eq!(exception_name(15), "SysTick");
+        assert_eq!(exception_name(16), "IRQ 0");
+        assert_eq!(exception_name(8), "reserved exception 8");
+    }
+
+    #[test]
+    fn quiescent() {
+        let status = fault_status(0, 0, 0, 0);
+
+        assert!(!status.is_faulted());
+        assert!(status.active().is_empty());
+        assert!(status.pending().is_empty());
+        assert!(status.explain().is_empty());
+    }
+
+    #[test]
+    fn active() {
+        //
+        // In a HardFault (VECTACTIVE = 3) taken from SVCall:  the current
+        // exception comes first, and isn't repeated.
+        //
+        let status = fault_status(3, (1 << 2) | (1 << 7), 0, 0);
+        assert_eq!(status.active(), ["HardFault", "SVCall"]);
+
+        //
+        // An IRQ is named by its number.
+        //
+        let status = fault_status(16 + 5, 1 << 7, 0, 0);
+        assert_eq!(status.active(), ["IRQ 5", "SVCall"]);
+    }
+
+    #[test]
+    fn pending() {
+        //
+        // SysTick is the highest priority pending exception (VECTPENDING =
+        // 15), with PendSV and SVCall also pending.
+        //
+        let icsr = (15 << 12) | (1 << 26) | (1 << 28);
+        let status = fault_status(icsr, 1 << 15, 0, 0);
+
+        assert_eq!(status.pending(), ["SysTick", "SVCall", "PendSV"]);
+    }
+
+    #[test]
+    fn explain() {
+        //
+        // A precise bus fault (with a valid BFAR) and a divide by zero,
+        // both escalated to a HardFault.
+        //
+        let cfsr = (1 << 9) | (1 << 15) | (1 << 25);
+        let status = fault_status(3, 1 << 2, cfsr, 1 << 30);
+
+        assert!(status.is_faulted());
+        assert_eq!(
+            status.explain(),
+            [
+                "BusFault: precise data bus error (PRECISERR)",
+                "UsageFault: divide by zero (DIVBYZERO)",
+                "HardFault: escalated from a fault that could not be handled \
+                (FORCED)",
+                "BusFault address is 0x20001000 (BFAR)",
+            ]
+        );
+
+        //
+        // A MemManage fault reports MMFAR only if it is valid.
+        //
+        let status = fault_status(4, 1, 1 << 1, 0);
+        assert_eq!(
+            status.explain(),
+            ["MemManage: data access to memory that does not permit it \
+            (DACCVIOL)"]
+        );
+
+        let status = fault_status(4, 1, (1 << 1) | (1 << 7), 0);
+        assert_eq!(
+            status.explain()[1],
+            "MemManage fault address is 0x00000010 (MMFAR)"
+        );
+    }
+}
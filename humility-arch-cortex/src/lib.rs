@@ -5,7 +5,9 @@
 pub mod debug;
 pub mod dwt;
 pub mod etm;
+pub mod fault;
 pub mod itm;
+pub mod nvic;
 pub mod scs;
 pub mod swo;
 pub mod tpiu;
@@ -0,0 +1,123 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::debug::{ARMCore, Register, CPUID};
+use crate::register;
+use anyhow::Result;
+use bitfield::bitfield;
+use humility::core::Core;
+use num_traits::FromPrimitive;
+
+/*
+ * Interrupt Controller Type Register
+ */
+register!(ICTR, 0xe000_e004,
+    #[derive(Copy, Clone)]
+    pub struct ICTR(u32);
+    impl Debug;
+    pub intlinesnum, _: 3, 0;
+);
+
+/*
+ * The NVIC registers that have one bit per IRQ:  the Interrupt Set-Enable
+ * Registers, the Interrupt Set-Pending Registers and the Interrupt Active Bit
+ * Registers.  Each is an array of words, with IRQ n at bit (n % 32) of word
+ * (n / 32).
+ */
+pub const NVIC_ISER: u32 = 0xe000_e100;
+pub const NVIC_ISPR: u32 = 0xe000_e200;
+pub const NVIC_IABR: u32 = 0xe000_e300;
+
+/*
+ * The Interrupt Priority Registers, with one byte per IRQ:  IRQ n is at byte
+ * (n % 4) of word (n / 4).  These are word-accessible only on ARMv6-M, so we
+ * always read them as words.
+ */
+pub const NVIC_IPR: u32 = 0xe000_e400;
+
+fn nvic_priority(ipr: u32, irq: u32) -> u8 {
+    (ipr >> ((irq % 4) * 8)) as u8
+}
+
+/// The state of a single interrupt in the NVIC.
+#[derive(Copy, Clone, Debug)]
+pub struct NvicInterrupt {
+    pub irq: u32,
+    pub enabled: bool,
+    pub pending: bool,
+    pub active: bool,
+    pub priority: u8,
+}
+
+impl NvicInterrupt {
+    pub fn read(core: &mut dyn Core, irq: u32) -> Result<Self> {
+        let word = (irq / 32) * 4;
+        let bit = 1 << (irq % 32);
+
+        let ipr = core.read_word_32(NVIC_IPR + (irq / 4) * 4)?;
+
+        Ok(Self {
+            irq,
+            enabled: core.read_word_32(NVIC_ISER + word)? & bit != 0,
+            pending: core.read_word_32(NVIC_ISPR + word)? & bit != 0,
+            active: core.read_word_32(NVIC_IABR + word)? & bit != 0,
+            priority: nvic_priority(ipr, irq),
+        })
+    }
+
+    /// Returns true if the interrupt is enabled, pending or active
+    pub fn is_interesting(&self) -> bool {
+        self.enabled || self.pending || self.active
+    }
+}
+
+/// Returns the number of interrupt lines that the NVIC supports.  ICTR is
+/// not implemented on ARMv6-M (Cortex-M0, Cortex-M0+ and Cortex-M1), which
+/// supports at most 32 interrupts; on those cores, we assume 32.
+pub fn nvic_lines(core: &mut dyn Core) -> Result<u32> {
+    let cpuid = CPUID::read(core)?;
+
+    match ARMCore::from_u32(cpuid.partno()) {
+        Some(ARMCore::CortexM0 | ARMCore::CortexM0Plus | ARMCore::CortexM1) => {
+            Ok(32)
+        }
+        _ => {
+            let ictr = ICTR::read(core)?;
+            Ok((ictr.intlinesnum() + 1) * 32)
+        }
+    }
+}
+
+/// Reads the state of every interrupt that the NVIC supports, reading each
+/// register word but once.
+pub fn nvic_interrupts(core: &mut dyn Core) -> Result<Vec<NvicInterrupt>> {
+    let lines = nvic_lines(core)?;
+    let mut priorities = vec![];
+    let mut rval = vec![];
+
+    for word in 0..lines / 4 {
+        priorities.push(core.read_word_32(NVIC_IPR + word * 4)?);
+    }
+
+    for word in 0..lines / 32 {
+        let offs = word * 4;
+        let enabled = core.read_word_32(NVIC_ISER + offs)?;
+        let pending = core.read_word_32(NVIC_ISPR + offs)?;
+        let active = core.read_word_32(NVIC_IABR + offs)?;
+
+        for bit in 0..32 {
+            let irq = word * 32 + bit;
+
+            rval.push(NvicInterrupt {
+                irq,
+                enabled: enabled & (1 << bit) != 0,
+                pending: pending & (1 << bit) != 0,
+                active: active & (1 << bit) != 0,
+                priority: nvic_priority(priorities[irq as usize / 4], irq),
+            });
+        }
+    }
+
+    Ok(rval)
+}
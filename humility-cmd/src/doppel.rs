@@ -35,42 +35,119 @@
 
 use crate::reflect::{Load, Ptr, Value};
 use anyhow::{anyhow, bail, Result};
+use humility::core::Core;
+use humility::hubris::{HubrisArchive, HubrisStruct, HubrisVariable};
+use serde::{Serialize, Serializer};
 use std::convert::TryInto;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub struct TaskDesc {
     pub entry_point: u32,
     pub initial_stack: u32,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub struct Task {
     pub state: TaskState,
     pub generation: GenOrRestartCount,
     pub priority: Priority,
+    #[serde(skip)]
     pub descriptor: Ptr,
     pub timer: TimerState,
 }
 
+/// Double of a region descriptor in the kernel.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
+pub struct RegionDesc {
+    pub base: u32,
+    pub size: u32,
+    pub attributes: RegionAttributes,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
+pub struct RegionAttributes {
+    pub bits: u32,
+}
+
+impl RegionAttributes {
+    //
+    // Regrettably copied out of Hubris -- there isn't DWARF for this.
+    //
+    pub const READ: u32 = 1 << 0;
+    pub const WRITE: u32 = 1 << 1;
+    pub const EXECUTE: u32 = 1 << 2;
+    pub const DEVICE: u32 = 1 << 3;
+    pub const DMA: u32 = 1 << 4;
+}
+
+impl std::fmt::Display for RegionAttributes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let flags = [
+            (Self::READ, 'r'),
+            (Self::WRITE, 'w'),
+            (Self::EXECUTE, 'x'),
+            (Self::DEVICE, 'd'),
+            (Self::DMA, 'm'),
+        ];
+
+        for (bit, c) in flags {
+            write!(f, "{}", if self.bits & bit != 0 { c } else { '-' })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Double of a slice reference (`&[T]`), which the kernel uses for things
+/// like region tables.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+pub struct Slice {
+    pub data_ptr: Ptr,
+    pub length: u32,
+}
+
+impl Slice {
+    /// Reads the elements of the slice and maps each into a Rust `T`.
+    pub fn load_from<T: Load>(
+        &self,
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+    ) -> Result<Vec<T>> {
+        let ty = hubris.lookup_type(self.data_ptr.dest_goff(hubris)?)?;
+        let size = ty.size(hubris)?;
+        let mut buf = vec![0; size * self.length as usize];
+
+        if buf.is_empty() {
+            return Ok(vec![]);
+        }
+
+        core.read_8(self.data_ptr.addr(), &mut buf)?;
+
+        (0..self.length as usize)
+            .map(|i| crate::reflect::load(hubris, &buf, ty, i * size))
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub struct Generation(pub u8);
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub struct Priority(pub u8);
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub struct Timestamp(pub u64);
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub struct NotificationSet(pub u32);
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub struct TimerState {
     pub deadline: Option<Timestamp>,
     pub to_post: NotificationSet,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub enum TaskState {
     /// Task is healthy and can be scheduled subject to the `SchedState`
     /// requirements.
@@ -86,7 +163,7 @@ pub enum TaskState {
     },
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub enum SchedState {
     /// This task is ignored for scheduling purposes.
     Stopped,
@@ -101,7 +178,7 @@ pub enum SchedState {
     InRecv(Option<TaskId>),
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub enum FaultInfo {
     /// The task has violated memory access rules. This may have come from a
     /// memory protection fault while executing the task (in the case of
@@ -139,7 +216,7 @@ pub enum FaultInfo {
     FromServer(TaskId, ReplyFaultReason),
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub enum UsageError {
     /// A program used an undefined syscall number.
     BadSyscallNumber,
@@ -161,7 +238,7 @@ pub enum UsageError {
     BadKernelMessage,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub enum FaultSource {
     /// User code did something that was intercepted by the processor.
     User,
@@ -170,7 +247,7 @@ pub enum FaultSource {
 }
 
 /// Reasons a server might cite when using the `REPLY_FAULT` syscall.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Load)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Load, Serialize)]
 pub enum ReplyFaultReason {
     /// The message indicated some operation number that is unknown to the
     /// server -- which almost certainly indicates that the client intended the
@@ -199,7 +276,11 @@ pub enum ReplyFaultReason {
     AccessViolation = 5,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+// At the time of this writing, we're in the midst of transitioning from storing
+// 6-bit generations in the kernel to storing 32-bit restart counts, where the
+// bottom 6 bits give the generation. Handle both shapes here.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Load)]
+#[load(untagged)]
 pub enum GenOrRestartCount {
     Gen(Generation),
     RestartCount(u32),
@@ -214,19 +295,9 @@ impl From<GenOrRestartCount> for u32 {
     }
 }
 
-impl crate::reflect::Load for GenOrRestartCount {
-    fn from_value(v: &Value) -> Result<Self> {
-        // At the time of this writing, we're in the midst of transitioning from
-        // storing 6-bit generations in the kernel to storing 32-bit restart
-        // counts, where the bottom 6 bits give the generation. Handle both
-        // shapes here.
-        if let Ok(g) = Generation::from_value(v) {
-            Ok(Self::Gen(g))
-        } else if let Ok(n) = u32::from_value(v) {
-            Ok(Self::RestartCount(n))
-        } else {
-            bail!("unexpected generation/restart count shape: {:?}", v);
-        }
+impl Serialize for GenOrRestartCount {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u32(u32::from(*self))
     }
 }
 
@@ -254,6 +325,17 @@ impl std::fmt::Display for TaskId {
     }
 }
 
+impl Serialize for TaskId {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = s.serialize_struct("TaskId", 2)?;
+        state.serialize_field("index", &self.index())?;
+        state.serialize_field("generation", &self.generation())?;
+        state.end()
+    }
+}
+
 impl crate::reflect::Load for TaskId {
     fn from_value(v: &Value) -> Result<Self> {
         // In some older kernel cores there's a single case where a task ID is
@@ -299,6 +381,55 @@ pub struct Ringbuf {
     pub buffer: Vec<RingbufEntry>,
 }
 
+impl Ringbuf {
+    /// Reads the ring buffer in the specified variable, which has the
+    /// specified definition.
+    pub fn read(
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+        definition: &HubrisStruct,
+        variable: &HubrisVariable,
+    ) -> Result<Self> {
+        let mut buf = vec![0; variable.size];
+        core.read_8(variable.addr, &mut buf)?;
+
+        // There are two possible shapes of ringbufs, depending on the age of
+        // the firmware.
+        // - Raw Ringbuf that is not wrapped by anything.
+        // - Safe Ringbuf that is inside a StaticCell.
+        //
+        // Here we will attempt to handle them both -- first raw, then
+        // fallback.
+        let ringbuf_val = Value::Struct(crate::reflect::load_struct(
+            hubris, &buf, definition, 0,
+        )?);
+
+        Self::from_value(&ringbuf_val).or_else(|_e| {
+            let cell: StaticCell = StaticCell::from_value(&ringbuf_val)?;
+            Self::from_value(&cell.cell.value)
+        })
+    }
+
+    /// Returns the entries that have been written, oldest first, along with
+    /// the slot that each occupies.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, &RingbufEntry)> {
+        //
+        // If nothing has been written, there is no oldest entry; otherwise,
+        // the oldest is the one after the last to have been written.
+        //
+        let len = self.buffer.len();
+        let (first, count) = match self.last {
+            Some(ndx) => (ndx as usize + 1, len),
+            None => (0, 0),
+        };
+
+        (0..count)
+            .map(move |i| (first + i) % len)
+            .map(move |slot| (slot, &self.buffer[slot]))
+            .filter(|(_, entry)| entry.generation != 0)
+    }
+}
+
 #[derive(Clone, Debug, Load)]
 pub struct StaticCell {
     pub cell: UnsafeCell,
@@ -8,11 +8,20 @@ use hif::*;
 use humility::core::Core;
 use humility::hubris::*;
 use postcard::{take_from_bytes, to_slice};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::thread;
 use std::time::{Duration, Instant};
 
+//
+// The most that a single call can add to the return stack beyond the size of
+// its payload:  one byte of variant, plus either the payload length (which we
+// assume to be encoded in at most three bytes) or a failure code (which is
+// encoded in at most five).
+//
+const FUNCTION_RESULT_OVERHEAD: usize = 6;
+
 #[derive(Debug, PartialEq)]
 enum State {
     Initialized,
@@ -37,9 +46,11 @@ pub struct HiffyContext<'a> {
     kicked: Option<Instant>,
     timeout: u32,
     state: State,
+    server: bool,
+    pending: Option<Vec<Result<Vec<u8>, u32>>>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct HiffyFunction {
     pub id: TargetFunction,
     pub name: String,
@@ -109,6 +120,16 @@ impl HiffyFunction {
     }
 }
 
+/// A lease to accompany an Idol call, denoting either data of the specified
+/// size to be read by the server (which is taken from the HIF data area) or
+/// a buffer of the specified size to be written by the server (whose
+/// contents follow the reply in the call's result)
+#[derive(Copy, Clone, Debug)]
+pub enum IdolLease {
+    Read(usize),
+    Write(usize),
+}
+
 /// Simple wrapper `struct` that exposes a checked `get(name, nargs)`
 #[derive(Debug)]
 pub struct HiffyFunctions(pub HashMap<String, HiffyFunction>);
@@ -137,104 +158,25 @@ impl HiffyFunctions {
     pub fn is_empty(&self) -> bool {
         self.0.len() == 0
     }
-}
-
-impl<'a> HiffyContext<'a> {
-    fn variable(
-        hubris: &'a HubrisArchive,
-        name: &str,
-        wordsize: bool,
-    ) -> Result<&'a HubrisVariable> {
-        let v = hubris
-            .lookup_variable(name)
-            .context("expected hiffy interface not found")?;
-
-        if wordsize && v.size != 4 {
-            bail!("expected {} to be size 4, found {}", name, v.size);
-        }
 
-        Ok(v)
-    }
-
-    fn read_word(
-        hubris: &'a HubrisArchive,
-        core: &mut dyn Core,
-        name: &str,
-    ) -> Result<u32> {
-        let v = Self::variable(hubris, name, true)?;
-
-        let result = core
-            .read_word_32(v.addr)
-            .context(format!("couldn't read {}", name))?;
-
-        Ok(result)
-    }
-
-    fn definition(hubris: &'a HubrisArchive, name: &str) -> Result<HubrisGoff> {
+    /// Loads the HIF functions described by the archive's `HIFFY_FUNCTIONS`
+    /// definition.  This does not require an attached target, making it
+    /// usable by consumers (like the mock target) that need to know the
+    /// function table before any HIF program is run.
+    pub fn from_archive(hubris: &HubrisArchive) -> Result<Self> {
         let goff = hubris
-            .lookup_definition(name)
+            .lookup_definition("HIFFY_FUNCTIONS")
             .context("expected hiffy definition not found")?;
 
-        Ok(*goff)
+        Self::from_definition(hubris, *goff)
     }
 
-    pub fn new(
-        hubris: &'a HubrisArchive,
-        core: &mut dyn Core,
-        timeout: u32,
-    ) -> Result<HiffyContext<'a>> {
-        core.halt()?;
-
-        let (major, minor) = (
-            Self::read_word(hubris, core, "HIFFY_VERSION_MAJOR"),
-            Self::read_word(hubris, core, "HIFFY_VERSION_MINOR"),
-        );
-
-        core.run()?;
-
-        let target = (major?, minor?);
-        let ours = (HIF_VERSION_MAJOR, HIF_VERSION_MINOR);
-
-        //
-        // For now, we insist on an exact version match between Humility
-        // and Hubris.
-        //
-        if ours != target {
-            #[rustfmt::skip]
-            bail!(
-                "HIF version mismatch: target has {}.{}; ours is {}.{}",
-                target.0, target.1, ours.0, ours.1
-            );
-        }
-
-        Ok(Self {
-            hubris,
-            ready: Self::variable(hubris, "HIFFY_READY", true)?,
-            kick: Self::variable(hubris, "HIFFY_KICK", true)?,
-            text: Self::variable(hubris, "HIFFY_TEXT", false)?,
-            data: Self::variable(hubris, "HIFFY_DATA", false)?,
-            rstack: Self::variable(hubris, "HIFFY_RSTACK", false)?,
-            requests: Self::variable(hubris, "HIFFY_REQUESTS", true)?,
-            // scratch: Self::variable(hubris, "HIFFY_SCRATCH", false)?,
-            errors: Self::variable(hubris, "HIFFY_ERRORS", true)?,
-            failure: Self::variable(hubris, "HIFFY_FAILURE", false)?,
-            functions: Self::definition(hubris, "HIFFY_FUNCTIONS")?,
-            cached: None,
-            kicked: None,
-            timeout,
-            state: State::Initialized,
-        })
-    }
-
-    pub fn data_size(&self) -> usize {
-        self.data.size
-    }
-
-    pub fn functions(&mut self) -> Result<HiffyFunctions> {
-        let hubris = self.hubris;
-
+    fn from_definition(
+        hubris: &HubrisArchive,
+        goff: HubrisGoff,
+    ) -> Result<Self> {
         let goff = hubris
-            .lookup_enum(self.functions)?
+            .lookup_enum(goff)?
             .lookup_variant_byname("Some")?
             .goff
             .ok_or_else(|| anyhow!("malconstructed functions"))?;
@@ -314,7 +256,168 @@ impl<'a> HiffyContext<'a> {
             rval.insert(func.name.clone(), func);
         }
 
-        Ok(HiffyFunctions(rval))
+        Ok(Self(rval))
+    }
+}
+
+/// A collection of HIF programs to be run together by
+/// [HiffyContext::run_batch], which will pack as many of them as will fit
+/// into each HIF program that it executes.
+#[derive(Debug, Default)]
+pub struct HiffyBatch {
+    programs: Vec<(Vec<Op>, usize)>,
+}
+
+impl HiffyBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a program to the batch, returning its index in the results.
+    /// The program must be straight-line code (that is, without labels or
+    /// branches) that leaves the stack as it found it; it need not end with
+    /// [Op::Done].  `rsize` is the total size of the payloads that the
+    /// program's calls can return.
+    pub fn push(&mut self, ops: Vec<Op>, rsize: usize) -> usize {
+        self.programs.push((ops, rsize));
+        self.programs.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+}
+
+impl<'a> HiffyContext<'a> {
+    fn variable(
+        hubris: &'a HubrisArchive,
+        name: &str,
+        wordsize: bool,
+    ) -> Result<&'a HubrisVariable> {
+        let v = hubris
+            .lookup_variable(name)
+            .context("expected hiffy interface not found")?;
+
+        if wordsize && v.size != 4 {
+            bail!("expected {} to be size 4, found {}", name, v.size);
+        }
+
+        Ok(v)
+    }
+
+    fn read_word(
+        hubris: &'a HubrisArchive,
+        core: &mut dyn Core,
+        name: &str,
+    ) -> Result<u32> {
+        let v = Self::variable(hubris, name, true)?;
+
+        let result = core
+            .read_word_32(v.addr)
+            .context(format!("couldn't read {}", name))?;
+
+        Ok(result)
+    }
+
+    fn definition(hubris: &'a HubrisArchive, name: &str) -> Result<HubrisGoff> {
+        let goff = hubris
+            .lookup_definition(name)
+            .context("expected hiffy definition not found")?;
+
+        Ok(*goff)
+    }
+
+    pub fn new(
+        hubris: &'a HubrisArchive,
+        core: &mut dyn Core,
+        timeout: u32,
+    ) -> Result<HiffyContext<'a>> {
+        let server = crate::server::connected();
+
+        //
+        // If we are attached via a server, it has already checked the
+        // version (in creating its own context), and there's no need for us
+        // to do so again.
+        //
+        if !server {
+            Self::check_version(hubris, core)?;
+        }
+
+        Ok(Self {
+            hubris,
+            ready: Self::variable(hubris, "HIFFY_READY", true)?,
+            kick: Self::variable(hubris, "HIFFY_KICK", true)?,
+            text: Self::variable(hubris, "HIFFY_TEXT", false)?,
+            data: Self::variable(hubris, "HIFFY_DATA", false)?,
+            rstack: Self::variable(hubris, "HIFFY_RSTACK", false)?,
+            requests: Self::variable(hubris, "HIFFY_REQUESTS", true)?,
+            // scratch: Self::variable(hubris, "HIFFY_SCRATCH", false)?,
+            errors: Self::variable(hubris, "HIFFY_ERRORS", true)?,
+            failure: Self::variable(hubris, "HIFFY_FAILURE", false)?,
+            functions: Self::definition(hubris, "HIFFY_FUNCTIONS")?,
+            cached: None,
+            kicked: None,
+            timeout,
+            state: State::Initialized,
+            server,
+            pending: None,
+        })
+    }
+
+    fn check_version(
+        hubris: &'a HubrisArchive,
+        core: &mut dyn Core,
+    ) -> Result<()> {
+        core.halt()?;
+
+        let (major, minor) = (
+            Self::read_word(hubris, core, "HIFFY_VERSION_MAJOR"),
+            Self::read_word(hubris, core, "HIFFY_VERSION_MINOR"),
+        );
+
+        core.run()?;
+
+        let target = (major?, minor?);
+        let ours = (HIF_VERSION_MAJOR, HIF_VERSION_MINOR);
+
+        //
+        // For now, we insist on an exact version match between Humility
+        // and Hubris.
+        //
+        if ours != target {
+            #[rustfmt::skip]
+            bail!(
+                "HIF version mismatch: target has {}.{}; ours is {}.{}",
+                target.0, target.1, ours.0, ours.1
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn set_timeout(&mut self, timeout: u32) {
+        self.timeout = timeout;
+    }
+
+    pub fn data_size(&self) -> usize {
+        self.data.size
+    }
+
+    pub fn functions(&mut self) -> Result<HiffyFunctions> {
+        //
+        // If we are attached via a server, it has already discovered the
+        // functions; we take them from it rather than walk the DWARF
+        // information ourselves.
+        //
+        if self.server {
+            return crate::server::functions();
+        }
+
+        HiffyFunctions::from_definition(self.hubris, self.functions)
     }
 
     /// Convenience routine to translate an Idol call into HIF operations
@@ -325,7 +428,45 @@ impl<'a> HiffyContext<'a> {
         payload: &[u8],
         ops: &mut Vec<Op>,
     ) -> Result<()> {
-        let send = funcs.get("Send", 4)?;
+        self.idol_call_ops_with_lease(funcs, op, payload, None, ops)
+    }
+
+    /// Translates an Idol call that takes a lease into HIF operations
+    pub fn idol_call_ops_with_lease(
+        &self,
+        funcs: &HiffyFunctions,
+        op: &idol::IdolOperation,
+        payload: &[u8],
+        lease: Option<IdolLease>,
+        ops: &mut Vec<Op>,
+    ) -> Result<()> {
+        let reply = self.hubris.typesize(op.ok)?;
+
+        let (send, nargs) = match lease {
+            None => (funcs.get("Send", 4)?, 4),
+            Some(IdolLease::Read(len)) => {
+                if len > self.data_size() {
+                    bail!(
+                        "lease size ({}) exceeds maximum data size ({})",
+                        len,
+                        self.data_size()
+                    );
+                }
+
+                (funcs.get("SendLeaseRead", 5)?, 5)
+            }
+            Some(IdolLease::Write(len)) => {
+                if reply + len > self.scratch_size() {
+                    bail!(
+                        "reply and lease size ({}) exceeds maximum size ({})",
+                        reply + len,
+                        self.scratch_size()
+                    );
+                }
+
+                (funcs.get("SendLeaseWrite", 5)?, 5)
+            }
+        };
 
         let push = |val: u32| {
             if val <= u8::MAX as u32 {
@@ -343,7 +484,7 @@ impl<'a> HiffyContext<'a> {
             bail!("interface matches invalid task {:?}", op.task);
         }
 
-        let size = u8::try_from(4 + payload.len())
+        let size = u8::try_from(nargs + payload.len())
             .map_err(|_| anyhow!("payload size exceeds maximum size"))?;
 
         ops.push(push(op.code as u32));
@@ -353,7 +494,15 @@ impl<'a> HiffyContext<'a> {
         }
 
         ops.push(push(payload.len() as u32));
-        ops.push(push(self.hubris.typesize(op.ok)? as u32));
+        ops.push(push(reply as u32));
+
+        match lease {
+            Some(IdolLease::Read(len)) | Some(IdolLease::Write(len)) => {
+                ops.push(push(len as u32));
+            }
+            None => {}
+        }
+
         ops.push(Op::Call(send.id));
         ops.push(Op::DropN(size));
 
@@ -385,6 +534,18 @@ impl<'a> HiffyContext<'a> {
             }
         }
 
+        //
+        // If we are attached via a server, the server executes the program
+        // with its own context; we hold on to the results until asked.
+        //
+        if self.server {
+            let results = crate::server::hiffy(ops, data, self.timeout)?;
+            self.pending = Some(results);
+            self.kicked = Some(Instant::now());
+            self.state = State::Kicked;
+            return Ok(());
+        }
+
         let mut text: Vec<u8> = vec![];
         text.resize_with(self.text.size, Default::default);
 
@@ -425,6 +586,126 @@ impl<'a> HiffyContext<'a> {
         Ok(())
     }
 
+    /// Checks that a program can be batched, returning the number of calls
+    /// it makes and the size of its serialized text
+    fn batchable(ops: &[Op], buf: &mut [u8]) -> Result<(usize, usize)> {
+        let mut depth = 0i64;
+        let mut calls = 0;
+        let mut size = 0;
+
+        for op in ops {
+            size += to_slice(op, buf)
+                .context("HIF program exceeds maximum text size")?
+                .len();
+
+            depth += match op {
+                Op::Push(_) | Op::Push16(_) | Op::Push32(_) | Op::PushNone => 1,
+                Op::Drop | Op::Add => -1,
+                Op::DropN(n) => -(*n as i64),
+                Op::Swap => 0,
+                Op::Call(_) => {
+                    calls += 1;
+                    0
+                }
+                _ => bail!("{:?} cannot be batched", op),
+            };
+
+            if depth < 0 {
+                bail!("batched program underflows its stack");
+            }
+        }
+
+        if depth != 0 {
+            bail!("batched program leaves {} item(s) on the stack", depth);
+        }
+
+        Ok((calls, size))
+    }
+
+    /// Runs the accumulated programs, moving their results into `rval`
+    fn run_batched(
+        &mut self,
+        core: &mut dyn Core,
+        ops: &mut Vec<Op>,
+        calls: &mut Vec<usize>,
+        rval: &mut Vec<Vec<Result<Vec<u8>, u32>>>,
+    ) -> Result<()> {
+        ops.push(Op::Done);
+
+        let mut results = self.run(core, ops.as_slice(), None)?;
+        let expected = calls.iter().sum::<usize>();
+
+        if results.len() != expected {
+            bail!("expected {} results, found {}", expected, results.len());
+        }
+
+        for ncalls in calls.iter() {
+            rval.push(results.drain(..*ncalls).collect());
+        }
+
+        ops.clear();
+        calls.clear();
+
+        Ok(())
+    }
+
+    /// Blocking execution of a batch of programs, returning the results of
+    /// each.  Programs are packed together into as few HIF programs as their
+    /// text and return stack requirements allow.
+    pub fn run_batch(
+        &mut self,
+        core: &mut dyn Core,
+        batch: &HiffyBatch,
+    ) -> Result<Vec<Vec<Result<Vec<u8>, u32>>>> {
+        let mut buf = vec![0u8; self.text.size];
+        let done = to_slice(&Op::Done, &mut buf)?.len();
+
+        let mut rval = vec![];
+        let mut ops = vec![];
+        let mut calls = vec![];
+        let (mut tsize, mut rsize) = (done, 0);
+
+        for (ndx, (program, presults)) in batch.programs.iter().enumerate() {
+            let program = match program.split_last() {
+                Some((Op::Done, program)) => program,
+                _ => program.as_slice(),
+            };
+
+            let (ncalls, ptext) = Self::batchable(program, &mut buf)
+                .with_context(|| format!("batched program {}", ndx))?;
+
+            //
+            // Each program needs room for its results, plus one byte for
+            // the terminating FunctionResult::Done.
+            //
+            let presults = presults + ncalls * FUNCTION_RESULT_OVERHEAD;
+
+            if ptext + done > self.text.size || presults + 1 > self.rstack.size
+            {
+                bail!("batched program {} is too large to be run", ndx);
+            }
+
+            if tsize + ptext > self.text.size
+                || rsize + presults + 1 > self.rstack.size
+            {
+                self.run_batched(core, &mut ops, &mut calls, &mut rval)?;
+                tsize = done;
+                rsize = 0;
+            }
+
+            ops.extend_from_slice(program);
+            calls.push(ncalls);
+            tsize += ptext;
+            rsize += presults;
+        }
+
+        if !calls.is_empty() {
+            self.run_batched(core, &mut ops, &mut calls, &mut rval)?;
+        }
+
+        Ok(rval)
+    }
+
     /// Blocking execution of a program, returning the results
     pub fn run(
         &mut self,
@@ -444,6 +725,11 @@ impl<'a> HiffyContext<'a> {
             bail!("invalid state for waiting: {:?}", self.state);
         }
 
+        if self.pending.is_some() {
+            self.state = State::ResultsReady;
+            return Ok(true);
+        }
+
         core.halt()?;
 
         let vars = (
@@ -508,6 +794,11 @@ impl<'a> HiffyContext<'a> {
             bail!("invalid state for consuming results: {:?}", self.state);
         }
 
+        if let Some(results) = self.pending.take() {
+            self.state = State::ResultsConsumed;
+            return Ok(results);
+        }
+
         let mut rstack: Vec<u8> = vec![];
         rstack.resize_with(self.rstack.size, Default::default);
 
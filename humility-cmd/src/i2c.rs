@@ -2,7 +2,10 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::hiffy::{HiffyBatch, HiffyContext, HiffyFunctions};
 use anyhow::{bail, Context, Result};
+use hif::*;
+use humility::core::Core;
 use humility::hubris::*;
 use std::fmt;
 
@@ -49,6 +52,24 @@ impl<'a> I2cArgs<'a> {
         }
     }
 
+    /// Returns the operations that push the controller, port, mux, segment
+    /// and device address -- the leading arguments to each I2C function.
+    pub fn base_ops(&self, address: u8) -> Vec<Op> {
+        let mut ops =
+            vec![Op::Push(self.controller), Op::Push(self.port.index)];
+
+        if let Some((mux, segment)) = self.mux {
+            ops.push(Op::Push(mux));
+            ops.push(Op::Push(segment));
+        } else {
+            ops.push(Op::PushNone);
+            ops.push(Op::PushNone);
+        }
+
+        ops.push(Op::Push(address));
+        ops
+    }
+
     pub fn parse(
         hubris: &'a HubrisArchive,
         bus: &Option<String>,
@@ -203,3 +224,365 @@ impl<'a> I2cArgs<'a> {
         Ok(Self { controller, port, mux, device, address, class })
     }
 }
+
+/// The order in which the bytes of a register address are sent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum I2cEndian {
+    Big,
+    Little,
+}
+
+/// Describes how a device's registers are addressed:  the width of a register
+/// address (in bytes) and the order in which its bytes are sent.  Registers
+/// with 8-bit addresses can be read with a single call; for registers with
+/// wider addresses, we must write the register address and then read.
+#[derive(Copy, Clone, Debug)]
+pub struct I2cRegisterAddressing {
+    pub width: u8,
+    pub endian: I2cEndian,
+}
+
+impl I2cRegisterAddressing {
+    /// Returns the bytes of the specified register address, as sent.
+    pub fn bytes(&self, register: u16) -> Vec<u8> {
+        match (self.width, self.endian) {
+            (1, _) => vec![register as u8],
+            (_, I2cEndian::Big) => register.to_be_bytes().to_vec(),
+            (_, I2cEndian::Little) => register.to_le_bytes().to_vec(),
+        }
+    }
+
+    /// Returns a program that reads `nbytes` (or a block, if `None`) from the
+    /// specified register, given a base that pushes the controller, port,
+    /// mux, segment and device address (see [`I2cArgs::base_ops`]).  The
+    /// program leaves the stack as it found it, and can therefore be run as
+    /// part of a batch.
+    pub fn read_ops(
+        &self,
+        base: &[Op],
+        funcs: &HiffyFunctions,
+        register: u16,
+        nbytes: Option<u8>,
+    ) -> Result<Vec<Op>> {
+        let read = funcs.get("I2cRead", 7)?;
+        let mut ops = base.to_vec();
+
+        if self.width == 1 && register > u8::MAX.into() {
+            bail!("register 0x{:x} exceeds register width", register);
+        }
+
+        if self.width == 1 {
+            ops.push(Op::Push(register as u8));
+        } else {
+            let write = funcs.get("I2cWrite", 8)?;
+            let bytes = self.bytes(register);
+
+            ops.push(Op::PushNone);
+
+            for byte in &bytes {
+                ops.push(Op::Push(*byte));
+            }
+
+            ops.push(Op::Push(bytes.len() as u8));
+            ops.push(Op::Call(write.id));
+            ops.push(Op::DropN(bytes.len() as u8 + 2));
+            ops.push(Op::PushNone);
+        }
+
+        match nbytes {
+            Some(nbytes) => ops.push(Op::Push(nbytes)),
+            None => ops.push(Op::PushNone),
+        }
+
+        ops.push(Op::Call(read.id));
+        ops.push(Op::DropN(base.len() as u8 + 2));
+
+        Ok(ops)
+    }
+
+    //
+    // Returns the result of the read from the results of a program returned
+    // by `read_ops`.  There is one result for each call in the program: if
+    // the register address was written, the write's result precedes the
+    // read's -- and if the write failed, so too did the read.
+    //
+    fn read_result(
+        &self,
+        results: &[Result<Vec<u8>, u32>],
+    ) -> Result<Result<Vec<u8>, u32>> {
+        match (self.width, results) {
+            (1, [read]) => Ok(read.clone()),
+            (2.., [Err(err), _]) => Ok(Err(*err)),
+            (2.., [Ok(_), read]) => Ok(read.clone()),
+            _ => bail!(
+                "expected {} result(s) from a read with {}-byte register \
+                addresses, found {}",
+                if self.width == 1 { 1 } else { 2 },
+                self.width,
+                results.len()
+            ),
+        }
+    }
+
+    /// Runs a batch of programs returned by [`read_ops`], each reading at
+    /// most `nbytes`, and returns the result of each read.  Programs not
+    /// returned by [`read_ops`] (for this addressing) result in an error.
+    ///
+    /// [`read_ops`]: Self::read_ops
+    pub fn run_reads(
+        &self,
+        core: &mut dyn Core,
+        context: &mut HiffyContext,
+        reads: Vec<Vec<Op>>,
+        nbytes: usize,
+    ) -> Result<Vec<Result<Vec<u8>, u32>>> {
+        let mut batch = HiffyBatch::new();
+
+        for ops in reads {
+            batch.push(ops, nbytes);
+        }
+
+        let results = context.run_batch(core, &batch)?;
+
+        results.iter().map(|r| self.read_result(r)).collect()
+    }
+
+    /// Reads `size` bytes from the specified device, starting at register 0
+    /// and reading `pagesize` bytes at a time (e.g., to read the contents of
+    /// an EEPROM).  The reads are run as batches that (roughly) fill the
+    /// return stack; after each batch, `progress` is called with the number
+    /// of bytes read thus far.  A failed or short read results in an error.
+    #[allow(clippy::too_many_arguments)]
+    pub fn read_contents(
+        &self,
+        core: &mut dyn Core,
+        context: &mut HiffyContext,
+        funcs: &HiffyFunctions,
+        hargs: &I2cArgs,
+        pagesize: u8,
+        size: u32,
+        mut progress: impl FnMut(usize),
+    ) -> Result<Vec<u8>> {
+        let address = match hargs.address {
+            Some(address) => address,
+            None => bail!("expected device"),
+        };
+
+        let limit = 1u32 << (self.width as u32 * 8);
+
+        if size > limit {
+            bail!("size exceeds the {} bytes that can be addressed", limit);
+        }
+
+        let base = hargs.base_ops(address);
+        let func = funcs.get("I2cRead", 7)?;
+        let pages = context.rstack_size() / pagesize as usize;
+        let mut contents = Vec::with_capacity(size as usize);
+        let mut offset = 0;
+
+        while offset < size {
+            let mut reads = vec![];
+            let mut lens = vec![];
+
+            while offset < size && reads.len() < pages.max(1) {
+                let len = (size - offset).min(pagesize as u32);
+                let ops = self.read_ops(
+                    &base,
+                    funcs,
+                    offset as u16,
+                    Some(len as u8),
+                )?;
+
+                reads.push(ops);
+                lens.push(len);
+                offset += len;
+            }
+
+            let results =
+                self.run_reads(core, context, reads, pagesize.into())?;
+
+            for (result, len) in results.iter().zip(lens) {
+                match result {
+                    Ok(buf) if buf.len() == len as usize => {
+                        contents.extend_from_slice(buf);
+                    }
+                    Ok(buf) => {
+                        bail!(
+                            "short read ({} bytes) at offset 0x{:x}",
+                            buf.len(),
+                            contents.len()
+                        );
+                    }
+                    Err(err) => {
+                        bail!(
+                            "failed to read at offset 0x{:x}: {}",
+                            contents.len(),
+                            func.strerror(*err)
+                        );
+                    }
+                }
+            }
+
+            progress(contents.len());
+        }
+
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hiffy::HiffyFunction;
+    use std::collections::HashMap;
+
+    const I2C_READ: u8 = 1;
+    const I2C_WRITE: u8 = 2;
+
+    fn functions() -> HiffyFunctions {
+        let function = |id, name: &str, nargs| HiffyFunction {
+            id: TargetFunction(id),
+            name: name.to_string(),
+            args: vec![HubrisGoff { object: 0, goff: 0 }; nargs],
+            errmap: HashMap::new(),
+        };
+
+        let mut functions = HashMap::new();
+        functions
+            .insert("I2cRead".to_string(), function(I2C_READ, "I2cRead", 7));
+        functions
+            .insert("I2cWrite".to_string(), function(I2C_WRITE, "I2cWrite", 8));
+
+        HiffyFunctions(functions)
+    }
+
+    fn base() -> Vec<Op> {
+        vec![
+            Op::Push(3),
+            Op::Push(0),
+            Op::PushNone,
+            Op::PushNone,
+            Op::Push(0x50),
+        ]
+    }
+
+    fn addressing(width: u8, endian: I2cEndian) -> I2cRegisterAddressing {
+        I2cRegisterAddressing { width, endian }
+    }
+
+    //
+    // Returns the depth of the stack at each call, along with its depth at
+    // the end of the program.
+    //
+    fn stack(ops: &[Op]) -> (Vec<(u8, usize)>, usize) {
+        let mut depth = 0;
+        let mut calls = vec![];
+
+        for op in ops {
+            match op {
+                Op::Push(_) | Op::Push16(_) | Op::Push32(_) | Op::PushNone => {
+                    depth += 1;
+                }
+                Op::Drop => depth -= 1,
+                Op::DropN(n) => depth -= *n as usize,
+                Op::Call(TargetFunction(id)) => calls.push((*id, depth)),
+                op => panic!("unexpected operation {:?}", op),
+            }
+        }
+
+        (calls, depth)
+    }
+
+    #[test]
+    fn register_bytes() {
+        use I2cEndian::*;
+
+        assert_eq!(addressing(1, Big).bytes(0x34), [0x34]);
+        assert_eq!(addressing(1, Little).bytes(0x34), [0x34]);
+        assert_eq!(addressing(2, Big).bytes(0x1234), [0x12, 0x34]);
+        assert_eq!(addressing(2, Little).bytes(0x1234), [0x34, 0x12]);
+    }
+
+    #[test]
+    fn read_ops_8bit() {
+        let funcs = functions();
+        let addressing = addressing(1, I2cEndian::Big);
+
+        let ops = addressing.read_ops(&base(), &funcs, 0x12, Some(2)).unwrap();
+        assert!(matches!(ops[5], Op::Push(0x12)));
+        assert!(matches!(ops[6], Op::Push(2)));
+
+        //
+        // The read has its 7 arguments on the stack, and the program leaves
+        // the stack as it found it.
+        //
+        assert_eq!(stack(&ops), (vec![(I2C_READ, 7)], 0));
+
+        let ops = addressing.read_ops(&base(), &funcs, 0x12, None).unwrap();
+        assert!(matches!(ops[6], Op::PushNone));
+        assert_eq!(stack(&ops), (vec![(I2C_READ, 7)], 0));
+
+        //
+        // A register that doesn't fit in the width is an error.
+        //
+        assert!(addressing.read_ops(&base(), &funcs, 0x100, Some(1)).is_err());
+    }
+
+    #[test]
+    fn read_ops_16bit() {
+        let funcs = functions();
+
+        for (endian, hi, lo) in
+            [(I2cEndian::Big, 0x12, 0x34), (I2cEndian::Little, 0x34, 0x12)]
+        {
+            let addressing = addressing(2, endian);
+            let ops =
+                addressing.read_ops(&base(), &funcs, 0x1234, Some(4)).unwrap();
+
+            //
+            // The register address is written (with no register for the
+            // write itself) and then read without a register.
+            //
+            assert!(matches!(ops[5], Op::PushNone));
+            assert!(matches!(ops[6], Op::Push(b) if b == hi));
+            assert!(matches!(ops[7], Op::Push(b) if b == lo));
+            assert!(matches!(ops[8], Op::Push(2)));
+
+            assert_eq!(
+                stack(&ops),
+                (vec![(I2C_WRITE, 9), (I2C_READ, 7)], 0),
+                "{:?}",
+                endian
+            );
+        }
+    }
+
+    #[test]
+    fn read_results() {
+        let byte = addressing(1, I2cEndian::Big);
+        let word = addressing(2, I2cEndian::Big);
+
+        assert_eq!(byte.read_result(&[Ok(vec![1])]).unwrap(), Ok(vec![1]));
+        assert_eq!(byte.read_result(&[Err(3)]).unwrap(), Err(3));
+
+        //
+        // A failed write fails the read; otherwise, the read's result is
+        // the result.
+        //
+        assert_eq!(word.read_result(&[Err(3), Err(4)]).unwrap(), Err(3));
+        assert_eq!(
+            word.read_result(&[Ok(vec![]), Ok(vec![5])]).unwrap(),
+            Ok(vec![5])
+        );
+        assert_eq!(word.read_result(&[Ok(vec![]), Err(4)]).unwrap(), Err(4));
+
+        //
+        // Results from a program that isn't from read_ops are an error, not
+        // a panic.
+        //
+        assert!(byte.read_result(&[]).is_err());
+        assert!(byte.read_result(&[Ok(vec![]), Ok(vec![])]).is_err());
+        assert!(word.read_result(&[Ok(vec![])]).is_err());
+        assert!(word.read_result(&[Ok(vec![]), Ok(vec![]), Err(1)]).is_err());
+    }
+}
@@ -2,6 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::reflect;
 use ::idol::syntax::{Operation, RecvStrategy, Reply};
 use anyhow::{anyhow, bail, Context, Result};
 use humility::hubris::*;
@@ -111,7 +112,7 @@ impl<'a> IdolOperation<'a> {
                 if s.newtype().is_some() {
                     call_arg(hubris, &s.members[0], val, &mut payload)?;
                 } else {
-                    bail!("structure arguments currently unsupported");
+                    call_arg(hubris, member, val, &mut payload)?;
                 }
             } else {
                 bail!("don't know what to do with {:?}", self.args);
@@ -127,6 +128,47 @@ impl<'a> IdolOperation<'a> {
 
         Ok(payload)
     }
+
+    ///
+    /// Decodes the result of a call to this operation.  A reply is decoded
+    /// as the operation's `ok` type; any data beyond it (e.g., the contents
+    /// of a write lease) is ignored.  An error is decoded as the variant of
+    /// the operation's error type that it denotes, or left as a bare code if
+    /// there is no such variant (as when the server has died).
+    ///
+    pub fn reply(
+        &self,
+        result: &Result<Vec<u8>, u32>,
+    ) -> Result<Result<reflect::Value, reflect::Value>> {
+        let hubris = self.hubris;
+
+        match result {
+            Ok(val) => {
+                let ty = hubris.lookup_type(self.ok)?;
+
+                if let HubrisType::Union(_) = ty {
+                    bail!("cannot decode union reply {:?}", ty);
+                }
+
+                Ok(Ok(reflect::load_value(hubris, val, ty, 0)?))
+            }
+            Err(code) => {
+                let code = *code as u64;
+
+                let err = match self.error {
+                    Some(e) if e.lookup_variant(code).is_some() => {
+                        let buf = code.to_le_bytes();
+                        reflect::Value::Enum(reflect::load_enum(
+                            hubris, &buf, e, 0,
+                        )?)
+                    }
+                    _ => reflect::Value::Base(reflect::Base::U32(code as u32)),
+                };
+
+                Ok(Err(err))
+            }
+        }
+    }
 }
 
 //
@@ -197,84 +239,501 @@ fn call_arg(
     value: &IdolArgument,
     buf: &mut [u8],
 ) -> Result<()> {
-    let t = hubris.lookup_type(member.goff)?;
-    let arg = &member.name;
+    let size = hubris.typesize(member.goff)?;
+
+    let dest = buf
+        .get_mut(member.offset..member.offset + size)
+        .ok_or_else(|| anyhow!("illegal argument type {}", member.goff))?;
+
+    let literal = match value {
+        IdolArgument::Scalar(value) => IdolLiteral::Atom(value.to_string()),
+        IdolArgument::String(value) => IdolLiteral::parse(value)
+            .with_context(|| format!("illegal value for {}", member.name))?,
+    };
+
+    encode(hubris, member.goff, &literal, dest)
+        .with_context(|| format!("illegal value for {}", member.name))
+}
+
+///
+/// A value for an argument, as parsed from either a Rust-like literal
+/// (e.g., `Foo { a: 1, b: [2, 3] }`, `(1, 2)` or `Some(3)`) or JSON (e.g.,
+/// `{"a": 1, "b": [2, 3]}`).  How a literal is interpreted depends on the
+/// type to which it is encoded.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum IdolLiteral {
+    /// A number, boolean, or identifier
+    Atom(String),
+    /// A quoted string
+    Str(String),
+    /// An array or tuple, e.g. `[1, 2]` or `(1, 2)`
+    List(Vec<IdolLiteral>),
+    /// A structure, optionally named, e.g. `Foo { a: 1 }` or `{"a": 1}`
+    Struct(Option<String>, Vec<(String, IdolLiteral)>),
+    /// A tuple struct or tuple variant, e.g. `Some(3)`
+    Tuple(String, Vec<IdolLiteral>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Punct(char),
+    Atom(String),
+    Str(String),
+}
 
-    let err = |err| anyhow!("illegal value for {}: {}", arg, err);
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '{' | '}' | '[' | ']' | '(' | ')' | ',' | ':' => {
+                tokens.push(Token::Punct(c));
+            }
+            '"' => {
+                let mut s = String::new();
+
+                loop {
+                    match chars.next() {
+                        None => bail!("unterminated string in \"{}\"", input),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('0') => s.push('\0'),
+                            Some(c @ ('"' | '\\')) => s.push(c),
+                            c => bail!("bad escape {:?} in \"{}\"", c, input),
+                        },
+                        Some(c) => s.push(c),
+                    }
+                }
 
-    if let IdolArgument::Scalar(value) = value {
-        let base = match t {
-            HubrisType::Base(base) => base,
+                tokens.push(Token::Str(s));
+            }
             _ => {
-                bail!("scalar for {} ({:?}) not yet supported", member.name, t);
+                let mut atom = c.to_string();
+
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}[](),\"".contains(c) {
+                        break;
+                    }
+
+                    //
+                    // A colon terminates an atom -- unless it's part of a
+                    // path separator.
+                    //
+                    if c == ':' {
+                        let mut lookahead = chars.clone();
+                        lookahead.next();
+
+                        if lookahead.peek() != Some(&':') {
+                            break;
+                        }
+
+                        chars.next();
+                        atom.push(':');
+                    }
+
+                    atom.push(c);
+                    chars.next();
+                }
+
+                tokens.push(Token::Atom(atom));
             }
-        };
+        }
+    }
 
-        if member.offset + base.size > buf.len() {
-            bail!("illegal argument type {}", member.goff);
+    Ok(tokens)
+}
+
+impl IdolLiteral {
+    pub fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        let mut tokens = tokens.iter().peekable();
+        let rval = Self::parse_value(&mut tokens)?;
+
+        if let Some(token) = tokens.next() {
+            bail!("unexpected {:?} in \"{}\"", token, input);
         }
 
-        let dest = &mut buf[member.offset..member.offset + base.size];
+        Ok(rval)
+    }
 
-        if base.encoding != HubrisEncoding::Unsigned {
-            bail!("signed scalar arguments not yet supported");
+    fn parse_value<'a>(
+        tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a Token>>,
+    ) -> Result<Self> {
+        match tokens.next() {
+            Some(Token::Punct('[')) => {
+                Ok(Self::List(Self::parse_list(tokens, ']')?))
+            }
+            Some(Token::Punct('(')) => {
+                Ok(Self::List(Self::parse_list(tokens, ')')?))
+            }
+            Some(Token::Punct('{')) => {
+                Ok(Self::Struct(None, Self::parse_fields(tokens)?))
+            }
+            Some(Token::Str(s)) => Ok(Self::Str(s.clone())),
+            Some(Token::Atom(atom)) => match tokens.peek() {
+                Some(Token::Punct('{')) => {
+                    tokens.next();
+                    let fields = Self::parse_fields(tokens)?;
+                    Ok(Self::Struct(Some(atom.clone()), fields))
+                }
+                Some(Token::Punct('(')) => {
+                    tokens.next();
+                    let items = Self::parse_list(tokens, ')')?;
+                    Ok(Self::Tuple(atom.clone(), items))
+                }
+                _ => Ok(Self::Atom(atom.clone())),
+            },
+            Some(token) => bail!("unexpected {:?}", token),
+            None => bail!("missing value"),
         }
+    }
 
-        let mut v = *value;
+    fn parse_list<'a>(
+        tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a Token>>,
+        close: char,
+    ) -> Result<Vec<Self>> {
+        let mut items = vec![];
+
+        loop {
+            if tokens.peek() == Some(&&Token::Punct(close)) {
+                tokens.next();
+                return Ok(items);
+            }
 
-        // A little dirty
-        for d in dest.iter_mut() {
-            *d = (v & 0xff) as u8;
-            v >>= 8;
+            items.push(Self::parse_value(tokens)?);
+
+            match tokens.next() {
+                Some(Token::Punct(',')) => {}
+                Some(Token::Punct(c)) if *c == close => return Ok(items),
+                Some(token) => {
+                    bail!("expected ',' or '{}'; found {:?}", close, token)
+                }
+                None => bail!("missing '{}'", close),
+            }
         }
+    }
+
+    fn parse_fields<'a>(
+        tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a Token>>,
+    ) -> Result<Vec<(String, Self)>> {
+        let mut fields = vec![];
 
-        if v != 0 {
-            bail!("value of {} exceeds maximum for {}", value, member.name);
+        loop {
+            let name = match tokens.next() {
+                Some(Token::Punct('}')) => return Ok(fields),
+                Some(Token::Atom(name)) | Some(Token::Str(name)) => {
+                    name.clone()
+                }
+                Some(token) => bail!("expected field name; found {:?}", token),
+                None => bail!("missing '}}'"),
+            };
+
+            match tokens.next() {
+                Some(Token::Punct(':')) => {}
+                _ => bail!("expected ':' after field {}", name),
+            }
+
+            fields.push((name, Self::parse_value(tokens)?));
+
+            match tokens.next() {
+                Some(Token::Punct(',')) => {}
+                Some(Token::Punct('}')) => return Ok(fields),
+                Some(token) => bail!("expected ',' or '}}'; found {:?}", token),
+                None => bail!("missing '}}'"),
+            }
         }
+    }
+}
+
+//
+// Strips any path from a name, e.g. `Foo::Bar` becomes `Bar`.
+//
+fn basename(name: &str) -> &str {
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+///
+/// Encodes a literal as the specified type into the specified buffer, which
+/// must be at least as large as the type.
+///
+pub fn encode(
+    hubris: &HubrisArchive,
+    goff: HubrisGoff,
+    literal: &IdolLiteral,
+    buf: &mut [u8],
+) -> Result<()> {
+    let size = hubris.typesize(goff)?;
 
-        return Ok(());
+    if size > buf.len() {
+        bail!("type {} ({} bytes) exceeds its space", goff, size);
     }
 
-    match t {
-        HubrisType::Base(base) => {
-            if member.offset + base.size > buf.len() {
-                bail!("illegal argument type {}", member.goff);
+    match hubris.lookup_type(goff)? {
+        HubrisType::Base(base) => encode_base(base, literal, &mut buf[..size]),
+        HubrisType::Struct(s) => encode_struct(hubris, s, literal, buf),
+        HubrisType::Enum(e) => encode_enum(hubris, e, literal, buf),
+        HubrisType::Array(a) => encode_array(hubris, a, literal, buf),
+        t => bail!("cannot encode {:?} as {}", literal, t.name(hubris)?),
+    }
+}
+
+fn encode_base(
+    base: &HubrisBasetype,
+    literal: &IdolLiteral,
+    buf: &mut [u8],
+) -> Result<()> {
+    let value = match literal {
+        IdolLiteral::Atom(value) => value.as_str(),
+        _ => bail!("expected a scalar, found {:?}", literal),
+    };
+
+    let bytes = match (base.encoding, base.size) {
+        (HubrisEncoding::Bool, 1) => match value {
+            "true" => vec![1],
+            "false" => vec![0],
+            _ => bail!("expected a boolean, found \"{}\"", value),
+        },
+        (HubrisEncoding::Unsigned, size) if size <= 8 => {
+            let v = parse_int::parse::<u64>(value)
+                .map_err(|e| anyhow!("\"{}\": {}", value, e))?;
+
+            if size < 8 && v >> (size * 8) != 0 {
+                bail!("value of {} exceeds maximum for {} bytes", v, size);
+            }
+
+            v.to_le_bytes()[..size].to_vec()
+        }
+        (HubrisEncoding::Signed, size) if size <= 8 => {
+            let v = parse_int::parse::<i64>(value)
+                .map_err(|e| anyhow!("\"{}\": {}", value, e))?;
+
+            let bits = size * 8;
+
+            if bits < 64 && (v >= 1 << (bits - 1) || v < -(1 << (bits - 1))) {
+                bail!("value of {} out of range for {} bytes", v, size);
             }
 
-            let value = match value {
-                IdolArgument::String(value) => value,
-                _ => {
-                    bail!("unrecognized argument type: {:?}", value);
+            v.to_le_bytes()[..size].to_vec()
+        }
+        (HubrisEncoding::Float, 4) => {
+            value.parse::<f32>()?.to_le_bytes().to_vec()
+        }
+        (HubrisEncoding::Float, 8) => {
+            value.parse::<f64>()?.to_le_bytes().to_vec()
+        }
+        (_, _) => bail!("encoding {:?} not yet supported", base),
+    };
+
+    buf.copy_from_slice(&bytes);
+    Ok(())
+}
+
+fn encode_struct(
+    hubris: &HubrisArchive,
+    s: &HubrisStruct,
+    literal: &IdolLiteral,
+    buf: &mut [u8],
+) -> Result<()> {
+    let check = |name: &str| -> Result<()> {
+        if basename(name) != basename(&s.name) {
+            bail!("expected {}, found {}", s.name, name);
+        }
+        Ok(())
+    };
+
+    let values: Vec<(&HubrisStructMember, &IdolLiteral)> = match literal {
+        IdolLiteral::List(items) | IdolLiteral::Tuple(_, items) => {
+            if let IdolLiteral::Tuple(name, _) = literal {
+                check(name)?;
+            }
+
+            if items.len() != s.members.len() {
+                bail!(
+                    "{} has {} members, but {} were specified",
+                    s.name,
+                    s.members.len(),
+                    items.len()
+                );
+            }
+
+            s.members.iter().zip(items.iter()).collect()
+        }
+        IdolLiteral::Struct(name, fields) => {
+            if let Some(name) = name {
+                check(name)?;
+            }
+
+            let mut fields: IndexMap<_, _> = fields
+                .iter()
+                .map(|(f, v)| {
+                    //
+                    // Allow tuple struct members to be specified by index.
+                    //
+                    match f.parse::<usize>() {
+                        Ok(ndx) => (format!("__{}", ndx), v),
+                        Err(_) => (f.to_string(), v),
+                    }
+                })
+                .collect();
+
+            let mut values = vec![];
+
+            for m in &s.members {
+                let v = fields.remove(&m.name).ok_or_else(|| {
+                    anyhow!("{}: field {} is not specified", s.name, m.name)
+                })?;
+
+                values.push((m, v));
+            }
+
+            if !fields.is_empty() {
+                bail!(
+                    "{}: spurious fields: {}",
+                    s.name,
+                    fields.keys().cloned().collect::<Vec<_>>().join(", ")
+                );
+            }
+
+            values
+        }
+        _ if s.newtype().is_some() => vec![(&s.members[0], literal)],
+        _ => bail!("expected {}, found {:?}", s.name, literal),
+    };
+
+    for (m, v) in values {
+        let dest = buf
+            .get_mut(m.offset..)
+            .ok_or_else(|| anyhow!("{}: bad offset for {}", s.name, m.name))?;
+
+        encode(hubris, m.goff, v, dest)
+            .with_context(|| format!("{}.{}", s.name, m.name))?;
+    }
+
+    Ok(())
+}
+
+fn encode_enum(
+    hubris: &HubrisArchive,
+    e: &HubrisEnum,
+    literal: &IdolLiteral,
+    buf: &mut [u8],
+) -> Result<()> {
+    let (name, payload) = match literal {
+        IdolLiteral::Atom(name) | IdolLiteral::Str(name) => (name, None),
+        IdolLiteral::Tuple(name, items) => {
+            (name, Some(IdolLiteral::List(items.clone())))
+        }
+        IdolLiteral::Struct(Some(name), fields) => {
+            (name, Some(IdolLiteral::Struct(None, fields.clone())))
+        }
+        IdolLiteral::Struct(None, fields) if fields.len() == 1 => {
+            (&fields[0].0, Some(fields[0].1.clone()))
+        }
+        _ => bail!("expected variant of {}, found {:?}", e.name, literal),
+    };
+
+    let variant = e.lookup_variant_byname(basename(name)).map_err(|_| {
+        let all = e.variants.iter().map(|v| v.name.as_str());
+        anyhow!(
+            "{} must be one of: {}",
+            e.name,
+            all.collect::<Vec<_>>().join(", ")
+        )
+    })?;
+
+    match (variant.goff, &payload) {
+        (Some(goff), Some(payload)) => {
+            //
+            // If a variant with a single value has been specified without
+            // parentheses (as JSON might), wrap it.
+            //
+            let payload = match (hubris.lookup_struct(goff), payload) {
+                (Ok(s), IdolLiteral::Atom(_) | IdolLiteral::Str(_))
+                    if s.members.len() == 1 =>
+                {
+                    IdolLiteral::List(vec![payload.clone()])
                 }
+                _ => payload.clone(),
             };
 
-            let dest = &mut buf[member.offset..member.offset + base.size];
+            encode(hubris, goff, &payload, buf)
+                .with_context(|| format!("{}::{}", e.name, variant.name))?;
+        }
+        (Some(goff), None) => {
+            if !hubris.lookup_struct(goff)?.members.is_empty() {
+                bail!("{}::{} requires a value", e.name, variant.name);
+            }
+        }
+        (None, Some(_)) => {
+            bail!("{}::{} does not take a value", e.name, variant.name);
+        }
+        (None, None) => {}
+    }
 
-            match (base.encoding, base.size) {
-                (HubrisEncoding::Unsigned, 4) => {
-                    let v = parse_int::parse::<u32>(value).map_err(err)?;
-                    dest.copy_from_slice(v.to_le_bytes().as_slice());
-                }
-                (HubrisEncoding::Unsigned, 2) => {
-                    let v = parse_int::parse::<u16>(value).map_err(err)?;
-                    dest.copy_from_slice(v.to_le_bytes().as_slice());
-                }
-                (HubrisEncoding::Unsigned, 1) => {
-                    dest[0] = parse_int::parse::<u8>(value).map_err(err)?;
-                }
-                (_, _) => {
-                    bail!(
-                        "encoding of {} ({:?}) not yet supported",
-                        member.name,
-                        base
-                    );
-                }
+    //
+    // Now write our discriminant.  A variant without a tag is the variant
+    // that holds the niche; its value alone denotes it.
+    //
+    if let Some(tag) = variant.tag {
+        match e.discriminant {
+            Some(HubrisDiscriminant::Value(goff, offs)) => {
+                let size = hubris.typesize(goff)?;
+
+                let dest = buf.get_mut(offs..offs + size).ok_or_else(|| {
+                    anyhow!("{}: bad discriminant offset {}", e.name, offs)
+                })?;
+
+                dest.copy_from_slice(&tag.to_le_bytes()[..size]);
+            }
+            Some(HubrisDiscriminant::Expected(_)) => {
+                bail!("{}: discriminant not resolved", e.name);
             }
+            None => {}
         }
-        _ => {
-            bail!("type of {} ({:?}) not yet supported", member.name, t);
+    }
+
+    Ok(())
+}
+
+fn encode_array(
+    hubris: &HubrisArchive,
+    a: &HubrisArray,
+    literal: &IdolLiteral,
+    buf: &mut [u8],
+) -> Result<()> {
+    let size = hubris.typesize(a.goff)?;
+
+    match literal {
+        IdolLiteral::List(items) => {
+            if items.len() != a.count {
+                bail!("expected {} elements, found {}", a.count, items.len());
+            }
+
+            for (i, item) in items.iter().enumerate() {
+                encode(hubris, a.goff, item, &mut buf[i * size..])
+                    .with_context(|| format!("element {}", i))?;
+            }
         }
-    };
+
+        //
+        // As a convenience, a byte array can be specified as a string,
+        // which will be zero-padded as needed.
+        //
+        IdolLiteral::Str(s) if size == 1 => {
+            if s.len() > a.count {
+                bail!("string exceeds {} bytes", a.count);
+            }
+
+            buf[..s.len()].copy_from_slice(s.as_bytes());
+            buf[s.len()..a.count].fill(0);
+        }
+        _ => bail!("expected array, found {:?}", literal),
+    }
 
     Ok(())
 }
@@ -359,7 +818,7 @@ pub fn lookup_reply<'a>(
         .ok_or_else(|| anyhow!("unknown operation \"{}\"", op))?
         .reply;
 
-    match reply {
+    let (ok, err) = match reply {
         Reply::Result { ok, err } => {
             let err = match err {
                 ::idol::syntax::Error::CLike(t) => m
@@ -367,48 +826,448 @@ pub fn lookup_reply<'a>(
                     .context(format!("failed to find error type {:?}", reply)),
             }?;
 
-            if let Ok(goff) = hubris.lookup_basetype_byname(&ok.ty.0) {
-                Ok((*goff, Some(err)))
-            } else if let Ok(e) = m.lookup_enum_byname(hubris, &ok.ty.0) {
-                Ok((e.goff, Some(err)))
-            } else if let Ok(s) = m.lookup_struct_byname(hubris, &ok.ty.0) {
-                Ok((s.goff, Some(err)))
-            } else {
-                //
-                // As a last ditch, we look up the REPLY type. This is a last
-                // effort because it might not be there:  if no task calls
-                // the function, the type will be absent.
-                //
-                let t = format!("{}_{}_REPLY", iface.name, op);
-
-                if let Ok(s) = hubris.lookup_struct_byname(&t) {
-                    Ok((s.goff, Some(err)))
-                } else {
-                    bail!("no type for {}.{}: {:?}", iface.name, op, reply);
-                }
-            }
+            (ok, Some(err))
         }
-        Reply::Simple(ok) => {
-            if let Ok(goff) = hubris.lookup_basetype_byname(&ok.ty.0) {
-                Ok((*goff, None))
-            } else if let Ok(e) = m.lookup_enum_byname(hubris, &ok.ty.0) {
-                Ok((e.goff, None))
-            } else if let Ok(s) = m.lookup_struct_byname(hubris, &ok.ty.0) {
-                Ok((s.goff, None))
-            } else {
-                //
-                // As a last ditch, we look up the REPLY type. This is a last
-                // effort because it might not be there:  if no task calls
-                // the function, the type will be absent.
-                //
-                let t = format!("{}_{}_REPLY", iface.name, op);
-
-                if let Ok(s) = hubris.lookup_struct_byname(&t) {
-                    Ok((s.goff, None))
-                } else {
-                    bail!("no type for {}.{}: {:?}", iface.name, op, reply);
-                }
-            }
+        Reply::Simple(ok) => (ok, None),
+    };
+
+    let ty = &ok.ty.0;
+
+    if let Ok(goff) = hubris.lookup_basetype_byname(ty) {
+        Ok((*goff, err))
+    } else if let Ok(e) = m.lookup_enum_byname(hubris, ty) {
+        Ok((e.goff, err))
+    } else if let Ok(s) = m.lookup_struct_byname(hubris, ty) {
+        Ok((s.goff, err))
+    } else if let Ok(goff) = hubris.lookup_array_byname(ty) {
+        Ok((goff, err))
+    } else {
+        //
+        // As a last ditch, we look up the REPLY type. This is a last
+        // effort because it might not be there:  if no task calls
+        // the function, the type will be absent.
+        //
+        let t = format!("{}_{}_REPLY", iface.name, op);
+
+        if let Ok(s) = hubris.lookup_struct_byname(&t) {
+            Ok((s.goff, err))
+        } else {
+            bail!("no type for {}.{}: {:?}", iface.name, op, reply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(s: &str) -> IdolLiteral {
+        IdolLiteral::Atom(s.to_string())
+    }
+
+    fn base(encoding: HubrisEncoding, size: usize) -> HubrisBasetype {
+        HubrisBasetype { encoding, size }
+    }
+
+    fn encoded(
+        encoding: HubrisEncoding,
+        size: usize,
+        value: &str,
+    ) -> Result<Vec<u8>> {
+        let mut buf = vec![0; size];
+        encode_base(&base(encoding, size), &atom(value), &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn parse_scalars() {
+        assert_eq!(IdolLiteral::parse("0x1f").unwrap(), atom("0x1f"));
+        assert_eq!(IdolLiteral::parse("  -3 ").unwrap(), atom("-3"));
+        assert_eq!(IdolLiteral::parse("Foo::Bar").unwrap(), atom("Foo::Bar"));
+        assert_eq!(
+            IdolLiteral::parse(r#""a \"b\"\n""#).unwrap(),
+            IdolLiteral::Str("a \"b\"\n".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_compound() {
+        assert_eq!(
+            IdolLiteral::parse("Foo { a: 1, b: [2, 3] }").unwrap(),
+            IdolLiteral::Struct(
+                Some("Foo".to_string()),
+                vec![
+                    ("a".to_string(), atom("1")),
+                    (
+                        "b".to_string(),
+                        IdolLiteral::List(vec![atom("2"), atom("3")])
+                    ),
+                ]
+            )
+        );
+
+        assert_eq!(
+            IdolLiteral::parse(r#"{"a": 1, "b": true,}"#).unwrap(),
+            IdolLiteral::Struct(
+                None,
+                vec![
+                    ("a".to_string(), atom("1")),
+                    ("b".to_string(), atom("true")),
+                ]
+            )
+        );
+
+        assert_eq!(
+            IdolLiteral::parse("(1, Some(2))").unwrap(),
+            IdolLiteral::List(vec![
+                atom("1"),
+                IdolLiteral::Tuple("Some".to_string(), vec![atom("2")])
+            ])
+        );
+
+        assert_eq!(
+            IdolLiteral::parse("[]").unwrap(),
+            IdolLiteral::List(vec![])
+        );
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for input in [
+            "",
+            "[1, 2",
+            "[1 2]",
+            "(1, 2]",
+            "Foo { a 1 }",
+            "Foo { a: 1",
+            "{ [1]: 2 }",
+            "\"unterminated",
+            "\"bad \\q escape\"",
+            "1 2",
+            "]",
+            "a:b",
+        ] {
+            assert!(IdolLiteral::parse(input).is_err(), "{:?}", input);
         }
     }
+
+    #[test]
+    fn encode_unsigned() {
+        use HubrisEncoding::Unsigned;
+
+        assert_eq!(encoded(Unsigned, 1, "0xff").unwrap(), vec![0xff]);
+        assert_eq!(encoded(Unsigned, 2, "0x1234").unwrap(), vec![0x34, 0x12]);
+        assert_eq!(
+            encoded(Unsigned, 8, "0xffffffffffffffff").unwrap(),
+            vec![0xff; 8]
+        );
+
+        assert!(encoded(Unsigned, 1, "0x100").is_err());
+        assert!(encoded(Unsigned, 2, "65536").is_err());
+        assert!(encoded(Unsigned, 4, "0x100000000").is_err());
+        assert!(encoded(Unsigned, 4, "-1").is_err());
+        assert!(encoded(Unsigned, 4, "zero").is_err());
+    }
+
+    #[test]
+    fn encode_signed() {
+        use HubrisEncoding::Signed;
+
+        assert_eq!(encoded(Signed, 1, "-128").unwrap(), vec![0x80]);
+        assert_eq!(encoded(Signed, 1, "127").unwrap(), vec![0x7f]);
+        assert_eq!(encoded(Signed, 2, "-1").unwrap(), vec![0xff, 0xff]);
+        assert_eq!(
+            encoded(Signed, 8, "-9223372036854775808").unwrap(),
+            i64::MIN.to_le_bytes().to_vec()
+        );
+
+        assert!(encoded(Signed, 1, "128").is_err());
+        assert!(encoded(Signed, 1, "-129").is_err());
+        assert!(encoded(Signed, 4, "2147483648").is_err());
+        assert!(encoded(Signed, 8, "9223372036854775808").is_err());
+    }
+
+    #[test]
+    fn encode_other() {
+        use HubrisEncoding::{Bool, Float, Unknown};
+
+        assert_eq!(encoded(Bool, 1, "true").unwrap(), vec![1]);
+        assert_eq!(encoded(Bool, 1, "false").unwrap(), vec![0]);
+        assert!(encoded(Bool, 1, "1").is_err());
+
+        assert_eq!(
+            encoded(Float, 4, "1.5").unwrap(),
+            1.5f32.to_le_bytes().to_vec()
+        );
+        assert!(encoded(Float, 4, "one").is_err());
+        assert!(encoded(Float, 2, "1.5").is_err());
+        assert!(encoded(Unknown, 4, "1").is_err());
+
+        let mut buf = vec![0; 4];
+        let list = IdolLiteral::List(vec![atom("1")]);
+        let ty = base(HubrisEncoding::Unsigned, 4);
+        assert!(encode_base(&ty, &list, &mut buf).is_err());
+    }
+
+    const U8: HubrisGoff = HubrisGoff { object: 0, goff: 1 };
+    const U16: HubrisGoff = HubrisGoff { object: 0, goff: 2 };
+    const U32: HubrisGoff = HubrisGoff { object: 0, goff: 3 };
+    const POINT: HubrisGoff = HubrisGoff { object: 0, goff: 4 };
+    const PAIR: HubrisGoff = HubrisGoff { object: 0, goff: 5 };
+    const WRAPPER: HubrisGoff = HubrisGoff { object: 0, goff: 6 };
+    const POINTS: HubrisGoff = HubrisGoff { object: 0, goff: 7 };
+    const NAME: HubrisGoff = HubrisGoff { object: 0, goff: 8 };
+    const COMMAND: HubrisGoff = HubrisGoff { object: 0, goff: 9 };
+    const SPEED: HubrisGoff = HubrisGoff { object: 0, goff: 10 };
+    const GOTO: HubrisGoff = HubrisGoff { object: 0, goff: 11 };
+    const NONZERO: HubrisGoff = HubrisGoff { object: 0, goff: 12 };
+    const SOME: HubrisGoff = HubrisGoff { object: 0, goff: 13 };
+
+    fn member(
+        name: &str,
+        offset: usize,
+        goff: HubrisGoff,
+    ) -> HubrisStructMember {
+        HubrisStructMember { name: name.to_string(), offset, goff }
+    }
+
+    fn variant(
+        name: &str,
+        tag: Option<u64>,
+        goff: Option<HubrisGoff>,
+    ) -> HubrisEnumVariant {
+        HubrisEnumVariant { name: name.to_string(), offset: 0, goff, tag }
+    }
+
+    fn structure(
+        name: &str,
+        goff: HubrisGoff,
+        size: usize,
+        members: Vec<HubrisStructMember>,
+    ) -> HubrisStruct {
+        HubrisStruct { name: name.to_string(), goff, size, members }
+    }
+
+    //
+    // Types for the equivalent of:
+    //
+    //     struct Point { x: u16, y: u16 }
+    //     struct Pair(u8, u32);
+    //     struct Wrapper(u32);
+    //
+    //     enum Command { Stop, Speed(u32), Goto { x: u16, y: u16 } }
+    //
+    // along with [Point; 2], [u8; 8] and an Option<NonZeroU32> (in which
+    // the Some variant has no tag, being denoted by the niche).
+    //
+    fn archive() -> HubrisArchive {
+        let mut hubris = HubrisArchive::new().unwrap();
+
+        hubris.add_basetype(U8, base(HubrisEncoding::Unsigned, 1));
+        hubris.add_basetype(U16, base(HubrisEncoding::Unsigned, 2));
+        hubris.add_basetype(U32, base(HubrisEncoding::Unsigned, 4));
+
+        hubris.add_struct(structure(
+            "Point",
+            POINT,
+            4,
+            vec![member("x", 0, U16), member("y", 2, U16)],
+        ));
+
+        hubris.add_struct(structure(
+            "Pair",
+            PAIR,
+            8,
+            vec![member("__0", 0, U8), member("__1", 4, U32)],
+        ));
+
+        hubris.add_struct(structure(
+            "Wrapper",
+            WRAPPER,
+            4,
+            vec![member("__0", 0, U32)],
+        ));
+
+        hubris.add_array(POINTS, HubrisArray { goff: POINT, count: 2 });
+        hubris.add_array(NAME, HubrisArray { goff: U8, count: 8 });
+
+        hubris.add_struct(structure(
+            "Speed",
+            SPEED,
+            8,
+            vec![member("__0", 4, U32)],
+        ));
+
+        hubris.add_struct(structure(
+            "Goto",
+            GOTO,
+            8,
+            vec![member("x", 2, U16), member("y", 4, U16)],
+        ));
+
+        hubris.add_enum(HubrisEnum {
+            name: "Command".to_string(),
+            goff: COMMAND,
+            size: 8,
+            discriminant: Some(HubrisDiscriminant::Value(U8, 0)),
+            tag: None,
+            variants: vec![
+                variant("Stop", Some(0), None),
+                variant("Speed", Some(1), Some(SPEED)),
+                variant("Goto", Some(2), Some(GOTO)),
+            ],
+        });
+
+        hubris.add_struct(structure(
+            "Some",
+            SOME,
+            4,
+            vec![member("__0", 0, U32)],
+        ));
+
+        hubris.add_enum(HubrisEnum {
+            name: "Option<NonZeroU32>".to_string(),
+            goff: NONZERO,
+            size: 4,
+            discriminant: Some(HubrisDiscriminant::Value(U32, 0)),
+            tag: None,
+            variants: vec![
+                variant("None", Some(0), None),
+                variant("Some", None, Some(SOME)),
+            ],
+        });
+
+        hubris
+    }
+
+    //
+    // Encodes the specified input into a buffer that starts out filled with
+    // the specified byte, allowing us to see which bytes were written.
+    //
+    fn encoded_as(
+        hubris: &HubrisArchive,
+        goff: HubrisGoff,
+        input: &str,
+        fill: u8,
+    ) -> Result<Vec<u8>> {
+        let mut buf = vec![fill; hubris.typesize(goff)?];
+        encode(hubris, goff, &IdolLiteral::parse(input)?, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn encode_structs() {
+        let hubris = archive();
+        let point = |input| encoded_as(&hubris, POINT, input, 0);
+
+        assert_eq!(point("Point { x: 1, y: 0x203 }").unwrap(), [1, 0, 3, 2]);
+        assert_eq!(point(r#"{"y": 2, "x": 1}"#).unwrap(), [1, 0, 2, 0]);
+        assert_eq!(point("(1, 2)").unwrap(), [1, 0, 2, 0]);
+        assert_eq!(point("Point(1, 2)").unwrap(), [1, 0, 2, 0]);
+
+        assert!(point("Other { x: 1, y: 2 }").is_err());
+        assert!(point("(1, 2, 3)").is_err());
+        assert!(point("(1)").is_err());
+        assert!(point("{ x: 1 }").is_err());
+        assert!(point("{ x: 1, y: 2, z: 3 }").is_err());
+        assert!(point("{ x: 1, y: 0x10000 }").is_err());
+        assert!(point("1").is_err());
+    }
+
+    #[test]
+    fn encode_tuple_structs() {
+        let hubris = archive();
+        let pair = |input| encoded_as(&hubris, PAIR, input, 0);
+        let expected = [7, 0, 0, 0, 0x78, 0x56, 0x34, 0x12];
+
+        assert_eq!(pair("Pair(7, 0x12345678)").unwrap(), expected);
+        assert_eq!(pair("{ 1: 0x12345678, 0: 7 }").unwrap(), expected);
+        assert_eq!(pair(r#"{"0": 7, "1": 0x12345678}"#).unwrap(), expected);
+
+        assert!(pair("{ 0: 7 }").is_err());
+        assert!(pair("{ 0: 7, 1: 8, 2: 9 }").is_err());
+        assert!(pair("7").is_err());
+
+        let wrapper = |input| encoded_as(&hubris, WRAPPER, input, 0);
+        assert_eq!(wrapper("5").unwrap(), [5, 0, 0, 0]);
+        assert_eq!(wrapper("Wrapper(5)").unwrap(), [5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn encode_arrays() {
+        let hubris = archive();
+        let points = |input| encoded_as(&hubris, POINTS, input, 0);
+
+        assert_eq!(
+            points("[{ x: 1, y: 2 }, (3, 4)]").unwrap(),
+            [1, 0, 2, 0, 3, 0, 4, 0]
+        );
+
+        assert!(points("[(1, 2)]").is_err());
+        assert!(points("[(1, 2), (3, 4), (5, 6)]").is_err());
+        assert!(points(r#""abcd""#).is_err());
+
+        let name = |input| encoded_as(&hubris, NAME, input, 0xff);
+
+        assert_eq!(name(r#""abc""#).unwrap(), *b"abc\0\0\0\0\0");
+        assert_eq!(name(r#""abcdefgh""#).unwrap(), *b"abcdefgh");
+        assert_eq!(name(r#""""#).unwrap(), [0; 8]);
+        assert_eq!(
+            name("[1, 2, 3, 4, 5, 6, 7, 8]").unwrap(),
+            [1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        assert!(name(r#""abcdefghi""#).is_err());
+    }
+
+    #[test]
+    fn encode_enums() {
+        let hubris = archive();
+        let command = |input| encoded_as(&hubris, COMMAND, input, 0);
+
+        assert_eq!(command("Stop").unwrap(), [0; 8]);
+        assert_eq!(command(r#""Stop""#).unwrap(), [0; 8]);
+        assert_eq!(command("Speed(5)").unwrap(), [1, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(
+            command("Command::Speed(5)").unwrap(),
+            [1, 0, 0, 0, 5, 0, 0, 0]
+        );
+
+        //
+        // A single-value variant can be given a bare value, as JSON would.
+        //
+        assert_eq!(
+            command(r#"{"Speed": 5}"#).unwrap(),
+            [1, 0, 0, 0, 5, 0, 0, 0]
+        );
+
+        assert_eq!(
+            command("Goto { x: 1, y: 2 }").unwrap(),
+            [2, 0, 1, 0, 2, 0, 0, 0]
+        );
+        assert_eq!(
+            command(r#"{"Goto": {"x": 1, "y": 2}}"#).unwrap(),
+            [2, 0, 1, 0, 2, 0, 0, 0]
+        );
+
+        assert!(command("Fly").is_err());
+        assert!(command("Stop(1)").is_err());
+        assert!(command("Speed").is_err());
+        assert!(command("Speed(1, 2)").is_err());
+        assert!(command("Goto { x: 1 }").is_err());
+        assert!(command(r#"{"Speed": 5, "Stop": 0}"#).is_err());
+    }
+
+    #[test]
+    fn encode_niche() {
+        let hubris = archive();
+        let option = |input| encoded_as(&hubris, NONZERO, input, 0xff);
+
+        //
+        // The untagged variant is denoted by its value alone, which must
+        // not be overwritten by a discriminant.
+        //
+        assert_eq!(option("Some(5)").unwrap(), [5, 0, 0, 0]);
+        assert_eq!(option(r#"{"Some": 5}"#).unwrap(), [5, 0, 0, 0]);
+        assert_eq!(option("None").unwrap(), [0, 0, 0, 0]);
+    }
 }
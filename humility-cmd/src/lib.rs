@@ -7,10 +7,13 @@ pub mod hiffy;
 pub mod i2c;
 pub mod idol;
 pub mod jefe;
+pub mod mock;
 pub mod reflect;
+pub mod server;
 pub mod test;
+pub mod varpath;
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use clap::{AppSettings, Parser};
 use humility::core::Core;
 use humility::hubris::*;
@@ -48,6 +51,10 @@ pub struct Args {
     #[clap(long, short, env = "HUMILITY_DUMP")]
     pub dump: Option<String>,
 
+    /// script of HIF responses for a mock target backed by a dump
+    #[clap(long, env = "HUMILITY_MOCK", conflicts_with = "probe")]
+    pub mock: Option<String>,
+
     #[clap(subcommand)]
     pub cmd: Option<Subcommand>,
 }
@@ -116,6 +123,20 @@ pub fn attach_live(args: &Args) -> Result<Box<dyn Core>> {
     }
 }
 
+///
+/// Attaches to a live system via `humility server` if one is running (and
+/// a probe hasn't been explicitly specified), or directly otherwise.
+///
+fn attach_live_or_server(args: &Args) -> Result<Box<dyn Core>> {
+    if args.dump.is_none() && args.probe.is_none() {
+        if let Some(core) = server::connect()? {
+            return Ok(Box::new(core));
+        }
+    }
+
+    attach_live(args)
+}
+
 pub fn attach_dump(
     args: &Args,
     hubris: &HubrisArchive,
@@ -127,6 +148,23 @@ pub fn attach_dump(
     }
 }
 
+pub fn attach_mock(
+    args: &Args,
+    hubris: &HubrisArchive,
+) -> Result<Box<dyn Core>> {
+    let script = args
+        .mock
+        .as_ref()
+        .ok_or_else(|| anyhow!("must be run against a mock target"))?;
+
+    if let Some(dump) = &args.dump {
+        let dump = humility::core::attach_dump(dump, hubris)?;
+        Ok(Box::new(mock::MockCore::new(dump, hubris, script)?))
+    } else {
+        bail!("a mock target requires a dump");
+    }
+}
+
 pub fn attach(
     hubris: &HubrisArchive,
     args: &Args,
@@ -135,13 +173,16 @@ pub fn attach(
     mut run: impl FnMut(&HubrisArchive, &mut dyn Core) -> Result<()>,
 ) -> Result<()> {
     let mut c = match attach {
-        Attach::LiveOnly => attach_live(args),
+        Attach::LiveOnly | Attach::Any if args.mock.is_some() => {
+            attach_mock(args, hubris)
+        }
+        Attach::LiveOnly => attach_live_or_server(args),
         Attach::DumpOnly => attach_dump(args, hubris),
         Attach::Any => {
             if args.dump.is_some() {
                 attach_dump(args, hubris)
             } else {
-                attach_live(args)
+                attach_live_or_server(args)
             }
         }
     }?;
@@ -0,0 +1,1065 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A mock target, backed by a dump.
+//!
+//! [`MockCore`] presents a dump as if it were a live, running target:  memory
+//! and registers are served from the dump, writes are retained in an overlay,
+//! and the HIF agent is emulated by watching for `HIFFY_KICK` to be written,
+//! decoding the HIF program in `HIFFY_TEXT`, and writing a scripted response
+//! for each call into `HIFFY_RSTACK`.  This allows commands that require a
+//! live system to be run (and tested) without any hardware.  The mock does
+//! not, however, emulate the debug unit:  it cannot be stepped, and halting
+//! and running it are no-ops (save for running the HIF agent).  It therefore
+//! still reports itself as a dump, so that commands that would otherwise
+//! set breakpoints or step (e.g., `humility gdbserver`) treat it as one.
+//!
+//! The script is a TOML file consisting of `call` entries, each of which
+//! names a HIF function, optionally constrains the arguments (as found on
+//! the HIF stack) and specifies either a successful payload or an error:
+//!
+//! ```toml
+//! [[call]]
+//! function = "I2cRead"
+//! args = ["3", "0", "-", "-", "0x48", "*", "*"]
+//! ok = [0x0c, 0x80]
+//!
+//! [[call]]
+//! function = "I2cRead"
+//! err = "NoDevice"
+//! ```
+//!
+//! Arguments are matched positionally:  `*` matches any value, `-` matches
+//! an absent value (i.e., `Op::PushNone`), and anything else is parsed as an
+//! integer.  The first matching entry wins; a call that matches no entry
+//! causes the HIF program to fail.  (As a convenience, `Sleep` succeeds
+//! without needing to be scripted.)
+
+use crate::hiffy::HiffyFunctions;
+use anyhow::{anyhow, bail, Context, Result};
+use hif::*;
+use humility::arch::ARMRegister;
+use humility::core::Core;
+use humility::hubris::*;
+use postcard::{take_from_bytes, to_slice};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+//
+// An upper bound on the number of HIF operations we will execute for a
+// single kick, to keep a mistaken loop from hanging us forever.
+//
+const MOCK_MAX_OPS: usize = 1_000_000;
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MockError {
+    Code(u32),
+    Name(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MockCall {
+    function: String,
+    args: Option<Vec<String>>,
+    ok: Option<Vec<u8>>,
+    err: Option<MockError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MockScript {
+    #[serde(default)]
+    call: Vec<MockCall>,
+}
+
+#[derive(Debug)]
+enum MockArg {
+    Any,
+    None,
+    Value(u32),
+}
+
+#[derive(Clone, Debug)]
+enum MockResponse {
+    Success(Vec<u8>),
+    Failure(u32),
+}
+
+#[derive(Debug)]
+struct MockEntry {
+    args: Option<Vec<MockArg>>,
+    response: MockResponse,
+}
+
+#[derive(Debug)]
+struct MockFunction {
+    name: String,
+    nargs: usize,
+    errmap: HashMap<u32, String>,
+    entries: Vec<MockEntry>,
+}
+
+#[derive(Debug)]
+struct MockHiffy {
+    kick: u32,
+    text: (u32, usize),
+    rstack: (u32, usize),
+    requests: u32,
+    functions: HashMap<u8, MockFunction>,
+}
+
+pub struct MockCore {
+    dump: Box<dyn Core>,
+    memory: BTreeMap<u32, u8>,
+    registers: HashMap<ARMRegister, u32>,
+    hiffy: Option<MockHiffy>,
+    kicked: bool,
+}
+
+impl MockArg {
+    fn parse(arg: &str) -> Result<Self> {
+        match arg {
+            "*" => Ok(MockArg::Any),
+            "-" | "none" => Ok(MockArg::None),
+            _ => Ok(MockArg::Value(
+                parse_int::parse::<u32>(arg)
+                    .with_context(|| format!("invalid argument \"{}\"", arg))?,
+            )),
+        }
+    }
+
+    fn matches(&self, val: &Option<u32>) -> bool {
+        match (self, val) {
+            (MockArg::Any, _) => true,
+            (MockArg::None, None) => true,
+            (MockArg::Value(v), Some(val)) => v == val,
+            _ => false,
+        }
+    }
+}
+
+fn mock_args(args: &[Option<u32>]) -> String {
+    args.iter()
+        .map(|arg| match arg {
+            Some(val) => format!("0x{:x}", val),
+            None => "-".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+//
+// Adds the entries of the specified script to the functions (keyed by ID)
+// that they call.
+//
+fn mock_script(
+    functions: &mut HashMap<u8, MockFunction>,
+    script: MockScript,
+) -> Result<()> {
+    let mut byname = HashMap::new();
+
+    for (id, f) in functions.iter() {
+        byname.insert(f.name.clone(), *id);
+    }
+
+    for (ndx, call) in script.call.into_iter().enumerate() {
+        let id = byname.get(&call.function).ok_or_else(|| {
+            anyhow!("call {}: unknown function {}", ndx, call.function)
+        })?;
+
+        let f = functions.get_mut(id).unwrap();
+
+        let args = match call.args {
+            Some(args) => {
+                if args.len() != f.nargs {
+                    bail!(
+                        "call {}: {} takes {} arguments, found {}",
+                        ndx,
+                        f.name,
+                        f.nargs,
+                        args.len()
+                    );
+                }
+
+                let args = args
+                    .iter()
+                    .map(|arg| MockArg::parse(arg))
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("call {}", ndx))?;
+
+                Some(args)
+            }
+            None => None,
+        };
+
+        let response = match (call.ok, call.err) {
+            (Some(payload), None) => MockResponse::Success(payload),
+            (None, Some(MockError::Code(code))) => MockResponse::Failure(code),
+            (None, Some(MockError::Name(name))) => {
+                let code = f
+                    .errmap
+                    .iter()
+                    .find(|(_, n)| **n == name)
+                    .map(|(code, _)| *code)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "call {}: {} has no error {}",
+                            ndx,
+                            f.name,
+                            name
+                        )
+                    })?;
+
+                MockResponse::Failure(code)
+            }
+            (None, None) => MockResponse::Success(vec![]),
+            (Some(_), Some(_)) => {
+                bail!("call {}: cannot specify both ok and err", ndx);
+            }
+        };
+
+        f.entries.push(MockEntry { args, response });
+    }
+
+    Ok(())
+}
+
+impl MockHiffy {
+    fn new(hubris: &HubrisArchive, script: MockScript) -> Result<Self> {
+        let variable = |name| -> Result<(u32, usize)> {
+            let v = hubris
+                .lookup_variable(name)
+                .context("expected hiffy interface not found")?;
+            Ok((v.addr, v.size))
+        };
+
+        let mut functions = HashMap::new();
+
+        for (name, f) in HiffyFunctions::from_archive(hubris)?.0 {
+            functions.insert(
+                f.id.0,
+                MockFunction {
+                    name,
+                    nargs: f.args.len(),
+                    errmap: f.errmap,
+                    entries: vec![],
+                },
+            );
+        }
+
+        mock_script(&mut functions, script)?;
+
+        Ok(Self {
+            kick: variable("HIFFY_KICK")?.0,
+            text: variable("HIFFY_TEXT")?,
+            rstack: variable("HIFFY_RSTACK")?,
+            requests: variable("HIFFY_REQUESTS")?.0,
+            functions,
+        })
+    }
+
+    fn call(&self, id: u8, stack: &[Option<u32>]) -> Result<MockResponse> {
+        let f = self
+            .functions
+            .get(&id)
+            .ok_or_else(|| anyhow!("call to unknown function {}", id))?;
+
+        if stack.len() < f.nargs {
+            bail!(
+                "{} takes {} arguments, but stack has only {}",
+                f.name,
+                f.nargs,
+                stack.len()
+            );
+        }
+
+        let args = &stack[stack.len() - f.nargs..];
+
+        for entry in &f.entries {
+            let matched = match &entry.args {
+                Some(expected) => {
+                    expected.iter().zip(args.iter()).all(|(e, a)| e.matches(a))
+                }
+                None => true,
+            };
+
+            if matched {
+                return Ok(entry.response.clone());
+            }
+        }
+
+        if f.name == "Sleep" {
+            return Ok(MockResponse::Success(vec![]));
+        }
+
+        bail!("no scripted response for {}({})", f.name, mock_args(args));
+    }
+
+    fn execute(&self, text: &[u8], rstack: &mut [u8]) -> Result<()> {
+        let mut ops = vec![];
+        let mut remaining = text;
+
+        loop {
+            let (op, next) = take_from_bytes::<Op>(remaining)
+                .context("failed to decode HIF program")?;
+
+            let done = matches!(op, Op::Done);
+
+            ops.push(op);
+            remaining = next;
+
+            if done {
+                break;
+            }
+        }
+
+        let labels = ops
+            .iter()
+            .enumerate()
+            .filter_map(|(ndx, op)| match op {
+                Op::Label(Target(t)) => Some((*t, ndx)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut stack: Vec<Option<u32>> = vec![];
+        let mut offs = 0;
+        let mut pc = 0;
+        let mut executed = 0;
+
+        let pop = |stack: &mut Vec<Option<u32>>| {
+            stack.pop().ok_or_else(|| anyhow!("HIF stack underflow"))
+        };
+
+        let operands = |stack: &Vec<Option<u32>>| -> Result<(u32, u32)> {
+            match stack.as_slice() {
+                [.., Some(rhs), Some(lhs)] => Ok((*lhs, *rhs)),
+                [.., _, _] => bail!("missing operand"),
+                _ => bail!("HIF stack underflow"),
+            }
+        };
+
+        let mut push_result = |rval: &FunctionResult| -> Result<()> {
+            let serialized = to_slice(rval, &mut rstack[offs..])
+                .map_err(|_| anyhow!("HIF return stack overflow"))?;
+            offs += serialized.len();
+            Ok(())
+        };
+
+        while pc < ops.len() {
+            executed += 1;
+
+            if executed > MOCK_MAX_OPS {
+                bail!("HIF program exceeded {} operations", MOCK_MAX_OPS);
+            }
+
+            let mut next = pc + 1;
+
+            let mut branch = |target: &Target, taken: bool| -> Result<()> {
+                if taken {
+                    next = *labels
+                        .get(&target.0)
+                        .ok_or_else(|| anyhow!("bad target {}", target.0))?;
+                }
+                Ok(())
+            };
+
+            match &ops[pc] {
+                Op::Push(val) => stack.push(Some(*val as u32)),
+                Op::Push16(val) => stack.push(Some(*val as u32)),
+                Op::Push32(val) => stack.push(Some(*val)),
+                Op::PushNone => stack.push(None),
+                Op::Drop => {
+                    pop(&mut stack)?;
+                }
+                Op::DropN(n) => {
+                    for _ in 0..*n {
+                        pop(&mut stack)?;
+                    }
+                }
+                Op::Swap => {
+                    let a = pop(&mut stack)?;
+                    let b = pop(&mut stack)?;
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Op::Add => {
+                    let (lhs, rhs) = operands(&stack)?;
+                    stack.truncate(stack.len() - 2);
+                    stack.push(Some(lhs.wrapping_add(rhs)));
+                }
+                Op::Label(_) => {}
+                Op::BranchGreaterThan(target) => {
+                    let (lhs, rhs) = operands(&stack)?;
+                    branch(target, lhs > rhs)?;
+                }
+                Op::BranchGreaterThanOrEqualTo(target) => {
+                    let (lhs, rhs) = operands(&stack)?;
+                    branch(target, lhs >= rhs)?;
+                }
+                Op::BranchLessThan(target) => {
+                    let (lhs, rhs) = operands(&stack)?;
+                    branch(target, lhs < rhs)?;
+                }
+                Op::Call(TargetFunction(id)) => {
+                    match self.call(*id, &stack)? {
+                        MockResponse::Success(payload) => {
+                            push_result(&FunctionResult::Success(&payload))?;
+                        }
+                        MockResponse::Failure(code) => {
+                            push_result(&FunctionResult::Failure(code))?;
+                        }
+                    }
+                }
+                Op::Done => {
+                    break;
+                }
+                op => {
+                    bail!("unsupported HIF operation {:?}", op);
+                }
+            }
+
+            pc = next;
+        }
+
+        push_result(&FunctionResult::Done)
+    }
+}
+
+impl MockCore {
+    pub fn new(
+        dump: Box<dyn Core>,
+        hubris: &HubrisArchive,
+        script: &str,
+    ) -> Result<Self> {
+        let contents = fs::read_to_string(script).with_context(|| {
+            format!("failed to read mock script {}", script)
+        })?;
+
+        let script: MockScript =
+            toml::from_str(&contents).with_context(|| {
+                format!("failed to parse mock script {}", script)
+            })?;
+
+        //
+        // An archive need not contain the HIF agent; if it doesn't, we'll
+        // behave as a dump that can be written to, but anything that
+        // attempts to use HIF will fail in the usual way.
+        //
+        let hiffy = if hubris.lookup_variable("HIFFY_KICK").is_ok() {
+            Some(MockHiffy::new(hubris, script)?)
+        } else {
+            if !script.call.is_empty() {
+                bail!("mock script has calls, but archive lacks hiffy");
+            }
+            None
+        };
+
+        let mut core = Self {
+            dump,
+            memory: BTreeMap::new(),
+            registers: HashMap::new(),
+            hiffy,
+            kicked: false,
+        };
+
+        //
+        // Whatever state the HIF agent was in when the dump was taken, we
+        // want it to look ready to accept a program.
+        //
+        if let Ok(ready) = hubris.lookup_variable("HIFFY_READY") {
+            core.write_word_32(ready.addr, 1)?;
+        }
+
+        Ok(core)
+    }
+
+    fn execute(&mut self) -> Result<()> {
+        let hiffy = match &self.hiffy {
+            Some(hiffy) => hiffy,
+            None => return Ok(()),
+        };
+
+        let (kick, requests) = (hiffy.kick, hiffy.requests);
+        let (taddr, tsize) = hiffy.text;
+        let (raddr, rsize) = hiffy.rstack;
+
+        let mut text = vec![0u8; tsize];
+        let mut rstack = vec![0u8; rsize];
+
+        self.read_8(taddr, &mut text)?;
+
+        //
+        // We can't borrow self.hiffy across our own reads and writes, so
+        // we temporarily take it.
+        //
+        let hiffy = self.hiffy.take().unwrap();
+        let rval = hiffy.execute(&text, &mut rstack);
+        self.hiffy = Some(hiffy);
+
+        rval.context("mock HIF execution failed")?;
+
+        self.write_8(raddr, &rstack)?;
+
+        let nrequests = self.read_word_32(requests)?;
+        self.write_word_32(requests, nrequests.wrapping_add(1))?;
+        self.write_word_32(kick, 0)?;
+
+        Ok(())
+    }
+}
+
+impl Core for MockCore {
+    fn info(&self) -> (String, Option<String>) {
+        ("mock (core dump)".to_string(), None)
+    }
+
+    fn read_word_32(&mut self, addr: u32) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.read_8(addr, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        self.dump.read_8(addr, data)?;
+
+        let end = addr as u64 + data.len() as u64;
+
+        for (&a, &val) in self.memory.range(addr..) {
+            if a as u64 >= end {
+                break;
+            }
+
+            data[(a - addr) as usize] = val;
+        }
+
+        Ok(())
+    }
+
+    fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
+        match self.registers.get(&reg) {
+            Some(val) => Ok(*val),
+            None => self.dump.read_reg(reg),
+        }
+    }
+
+    fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()> {
+        self.registers.insert(reg, value);
+        Ok(())
+    }
+
+    fn init_swv(&mut self) -> Result<()> {
+        bail!("cannot enable SWV on a mock target");
+    }
+
+    fn read_swv(&mut self) -> Result<Vec<u8>> {
+        bail!("cannot read SWV on a mock target");
+    }
+
+    fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()> {
+        self.write_8(addr, &data.to_le_bytes())?;
+
+        if let Some(hiffy) = &self.hiffy {
+            if addr == hiffy.kick && data != 0 {
+                self.kicked = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        //
+        // We only allow writes to memory that is present in the dump.
+        //
+        let mut buf = vec![0u8; data.len()];
+        self.dump.read_8(addr, &mut buf).with_context(|| {
+            format!("write of {} bytes to 0x{:x}", data.len(), addr)
+        })?;
+
+        for (i, val) in data.iter().enumerate() {
+            self.memory.insert(addr + i as u32, *val);
+        }
+
+        Ok(())
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        //
+        // If we have been kicked, this is when the HIF agent would run.
+        //
+        if self.kicked {
+            self.kicked = false;
+            self.execute()?;
+        }
+
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<()> {
+        bail!("cannot step a mock target");
+    }
+
+    fn is_dump(&self) -> bool {
+        true
+    }
+}
+
+//
+// A dump consisting of a single region of zeroed memory, over which tests
+// (here and elsewhere) can construct a mock.
+//
+#[cfg(test)]
+struct ZeroDump {
+    base: u32,
+    size: u32,
+}
+
+#[cfg(test)]
+impl Core for ZeroDump {
+    fn info(&self) -> (String, Option<String>) {
+        ("zero dump".to_string(), None)
+    }
+
+    fn read_word_32(&mut self, addr: u32) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.read_8(addr, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        let end = addr as u64 + data.len() as u64;
+
+        if addr < self.base || end > self.base as u64 + self.size as u64 {
+            bail!("address 0x{:x} not in dump", addr);
+        }
+
+        data.iter_mut().for_each(|b| *b = 0);
+        Ok(())
+    }
+
+    fn read_reg(&mut self, _reg: ARMRegister) -> Result<u32> {
+        Ok(0)
+    }
+
+    fn write_reg(&mut self, _reg: ARMRegister, _value: u32) -> Result<()> {
+        bail!("cannot write register on a dump");
+    }
+
+    fn init_swv(&mut self) -> Result<()> {
+        bail!("cannot enable SWV on a dump");
+    }
+
+    fn read_swv(&mut self) -> Result<Vec<u8>> {
+        bail!("cannot read SWV on a dump");
+    }
+
+    fn write_word_32(&mut self, _addr: u32, _data: u32) -> Result<()> {
+        bail!("cannot write a word on a dump");
+    }
+
+    fn write_8(&mut self, _addr: u32, _data: &[u8]) -> Result<()> {
+        bail!("cannot write a byte on a dump");
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<()> {
+        bail!("can't step a dump");
+    }
+
+    fn is_dump(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+impl MockCore {
+    pub(crate) fn zeroed(base: u32, size: u32) -> Self {
+        Self {
+            dump: Box::new(ZeroDump { base, size }),
+            memory: BTreeMap::new(),
+            registers: HashMap::new(),
+            hiffy: None,
+            kicked: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const I2C_READ: u8 = 1;
+    const SLEEP: u8 = 2;
+
+    fn functions() -> HashMap<u8, MockFunction> {
+        let mut functions = HashMap::new();
+        let mut errmap = HashMap::new();
+        errmap.insert(3, "NoDevice".to_string());
+
+        functions.insert(
+            I2C_READ,
+            MockFunction {
+                name: "I2cRead".to_string(),
+                nargs: 3,
+                errmap,
+                entries: vec![],
+            },
+        );
+
+        functions.insert(
+            SLEEP,
+            MockFunction {
+                name: "Sleep".to_string(),
+                nargs: 1,
+                errmap: HashMap::new(),
+                entries: vec![],
+            },
+        );
+
+        functions
+    }
+
+    fn hiffy(script: &str) -> Result<MockHiffy> {
+        let mut functions = functions();
+        mock_script(&mut functions, toml::from_str(script)?)?;
+
+        Ok(MockHiffy {
+            kick: 0,
+            text: (0, 0),
+            rstack: (0, 0),
+            requests: 0,
+            functions,
+        })
+    }
+
+    fn success(response: MockResponse) -> Vec<u8> {
+        match response {
+            MockResponse::Success(payload) => payload,
+            MockResponse::Failure(code) => {
+                panic!("unexpected failure {}", code)
+            }
+        }
+    }
+
+    fn failure(response: MockResponse) -> u32 {
+        match response {
+            MockResponse::Success(p) => panic!("unexpected success {:?}", p),
+            MockResponse::Failure(code) => code,
+        }
+    }
+
+    //
+    // Executes the specified HIF program, returning its results as a HIF
+    // context would.
+    //
+    fn execute(
+        hiffy: &MockHiffy,
+        ops: &[Op],
+    ) -> Result<Vec<Result<Vec<u8>, u32>>> {
+        let mut text = vec![0u8; 1024];
+        let mut len = 0;
+
+        for op in ops {
+            len += to_slice(op, &mut text[len..])?.len();
+        }
+
+        let mut rstack = vec![0u8; 1024];
+        hiffy.execute(&text[..len], &mut rstack)?;
+
+        let mut results = vec![];
+        let mut remaining = rstack.as_slice();
+
+        loop {
+            let (rval, next) = take_from_bytes::<FunctionResult>(remaining)?;
+
+            match rval {
+                FunctionResult::Done => break,
+                FunctionResult::Success(payload) => {
+                    results.push(Ok(payload.to_vec()))
+                }
+                FunctionResult::Failure(code) => results.push(Err(code)),
+            }
+
+            remaining = next;
+        }
+
+        Ok(results)
+    }
+
+    const SCRIPT: &str = r#"
+        [[call]]
+        function = "I2cRead"
+        args = ["3", "-", "0x48"]
+        ok = [0x0c, 0x80]
+
+        [[call]]
+        function = "I2cRead"
+        args = ["*", "*", "0x49"]
+        err = "NoDevice"
+
+        [[call]]
+        function = "I2cRead"
+        args = ["*", "*", "*"]
+        err = 7
+    "#;
+
+    #[test]
+    fn script_matching() {
+        let hiffy = hiffy(SCRIPT).unwrap();
+
+        let call = |args: &[Option<u32>]| hiffy.call(I2C_READ, args).unwrap();
+
+        assert_eq!(success(call(&[Some(3), None, Some(0x48)])), [0x0c, 0x80]);
+
+        //
+        // An absent argument matches only `-`, and the first matching
+        // entry wins.
+        //
+        assert_eq!(failure(call(&[Some(3), Some(0), Some(0x48)])), 7);
+        assert_eq!(failure(call(&[Some(3), None, Some(0x49)])), 3);
+        assert_eq!(failure(call(&[None, None, Some(0x50)])), 7);
+
+        //
+        // Arguments are taken from the top of the stack.
+        //
+        let stack = [Some(1), Some(2), Some(3), None, Some(0x48)];
+        assert_eq!(success(call(&stack)), [0x0c, 0x80]);
+
+        assert!(hiffy.call(I2C_READ, &[Some(3), None]).is_err());
+        assert!(hiffy.call(9, &[]).is_err());
+    }
+
+    #[test]
+    fn script_unmatched() {
+        let hiffy = hiffy(
+            r#"
+            [[call]]
+            function = "I2cRead"
+            args = ["3", "-", "0x48"]
+            "#,
+        )
+        .unwrap();
+
+        let ok = hiffy.call(I2C_READ, &[Some(3), None, Some(0x48)]).unwrap();
+        assert_eq!(success(ok), Vec::<u8>::new());
+        assert!(hiffy.call(I2C_READ, &[Some(3), None, Some(0x49)]).is_err());
+
+        //
+        // Sleep needn't be scripted.
+        //
+        assert_eq!(
+            success(hiffy.call(SLEEP, &[Some(5)]).unwrap()),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn script_malformed() {
+        for script in [
+            "[[call]]\nfunction = \"I2cWrite\"",
+            "[[call]]\nfunction = \"I2cRead\"\nargs = [\"*\"]",
+            "[[call]]\nfunction = \"I2cRead\"\nargs = [\"*\", \"*\", \"x\"]",
+            "[[call]]\nfunction = \"I2cRead\"\nerr = \"NoSuchError\"",
+            "[[call]]\nfunction = \"I2cRead\"\nok = []\nerr = 3",
+            "[[call]]\nfunction = \"I2cRead\"\nbogus = 1",
+            "[[call]]\nargs = []",
+        ] {
+            assert!(hiffy(script).is_err(), "{:?}", script);
+        }
+    }
+
+    #[test]
+    fn execute_calls() {
+        let hiffy = hiffy(SCRIPT).unwrap();
+
+        let results = execute(
+            &hiffy,
+            &[
+                Op::Push(3),
+                Op::PushNone,
+                Op::Push(0x48),
+                Op::Call(TargetFunction(I2C_READ)),
+                Op::Drop,
+                Op::Push(0x49),
+                Op::Call(TargetFunction(I2C_READ)),
+                Op::DropN(3),
+                Op::Done,
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(results, vec![Ok(vec![0x0c, 0x80]), Err(3)]);
+    }
+
+    #[test]
+    fn execute_loop() {
+        let hiffy = hiffy(
+            r#"
+            [[call]]
+            function = "I2cRead"
+            args = ["3", "-", "*"]
+            ok = [1]
+            "#,
+        )
+        .unwrap();
+
+        //
+        // Call I2cRead for addresses 0 through 2, as an address scan would.
+        //
+        let results = execute(
+            &hiffy,
+            &[
+                Op::Push(3),
+                Op::PushNone,
+                Op::Push(0),
+                Op::PushNone,
+                Op::Label(Target(0)),
+                Op::Drop,
+                Op::Call(TargetFunction(I2C_READ)),
+                Op::Push(1),
+                Op::Add,
+                Op::Push(2),
+                Op::BranchGreaterThanOrEqualTo(Target(0)),
+                Op::Done,
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(results, vec![Ok(vec![1]); 3]);
+    }
+
+    const BASE: u32 = 0x2000_0000;
+    const KICK: u32 = BASE;
+    const REQUESTS: u32 = BASE + 0x4;
+    const TEXT: u32 = BASE + 0x100;
+    const RSTACK: u32 = BASE + 0x200;
+
+    fn mock(script: &str) -> MockCore {
+        let mut hiffy = hiffy(script).unwrap();
+        hiffy.kick = KICK;
+        hiffy.requests = REQUESTS;
+        hiffy.text = (TEXT, 0x100);
+        hiffy.rstack = (RSTACK, 0x100);
+
+        let mut core = MockCore::zeroed(BASE, 0x1000);
+        core.hiffy = Some(hiffy);
+        core
+    }
+
+    #[test]
+    fn core_is_dump() {
+        let mut core = mock(SCRIPT);
+
+        //
+        // Commands that would set breakpoints or step a live target must see
+        // the mock as the dump that it is.
+        //
+        assert!(core.is_dump());
+        assert!(core.step().is_err());
+
+        //
+        // Writes are retained, but only to memory that's in the dump.
+        //
+        core.write_word_32(BASE + 0x10, 0x1de).unwrap();
+        assert_eq!(core.read_word_32(BASE + 0x10).unwrap(), 0x1de);
+        assert_eq!(core.read_word_32(BASE + 0x14).unwrap(), 0);
+        assert!(core.write_word_32(BASE + 0x1000, 0).is_err());
+
+        core.write_reg(ARMRegister::R0, 7).unwrap();
+        assert_eq!(core.read_reg(ARMRegister::R0).unwrap(), 7);
+    }
+
+    #[test]
+    fn core_kick() {
+        let mut core = mock(SCRIPT);
+
+        let mut text = vec![0u8; 0x100];
+        let mut len = 0;
+
+        for op in [
+            Op::Push(3),
+            Op::PushNone,
+            Op::Push(0x48),
+            Op::Call(TargetFunction(I2C_READ)),
+            Op::DropN(3),
+            Op::Done,
+        ] {
+            len += to_slice(&op, &mut text[len..]).unwrap().len();
+        }
+
+        core.write_8(TEXT, &text[..len]).unwrap();
+
+        //
+        // Nothing runs until we are both kicked and run.
+        //
+        core.run().unwrap();
+        assert_eq!(core.read_word_32(REQUESTS).unwrap(), 0);
+
+        core.write_word_32(KICK, 1).unwrap();
+        core.run().unwrap();
+        assert_eq!(core.read_word_32(KICK).unwrap(), 0);
+        assert_eq!(core.read_word_32(REQUESTS).unwrap(), 1);
+
+        let mut rstack = vec![0u8; 0x100];
+        core.read_8(RSTACK, &mut rstack).unwrap();
+
+        let (rval, remaining) =
+            take_from_bytes::<FunctionResult>(&rstack).unwrap();
+        assert!(matches!(rval, FunctionResult::Success(&[0x0c, 0x80])));
+
+        let (rval, _) = take_from_bytes::<FunctionResult>(remaining).unwrap();
+        assert!(matches!(rval, FunctionResult::Done));
+    }
+
+    #[test]
+    fn execute_malformed() {
+        let hiffy = hiffy(SCRIPT).unwrap();
+
+        assert!(execute(&hiffy, &[Op::Drop, Op::Done]).is_err());
+        assert!(execute(&hiffy, &[Op::Push(1), Op::Add, Op::Done]).is_err());
+        assert!(execute(
+            &hiffy,
+            &[Op::Push(1), Op::Push(2), Op::BranchGreaterThan(Target(9))]
+        )
+        .is_err());
+
+        //
+        // A call without enough arguments on the stack fails, as does one
+        // that matches no entry.
+        //
+        let call = || Op::Call(TargetFunction(I2C_READ));
+        assert!(execute(&hiffy, &[Op::Push(1), call(), Op::Done]).is_err());
+
+        let hiffy = self::hiffy("").unwrap();
+        let ops = [Op::Push(1), Op::Push(2), Op::Push(3), call(), Op::Done];
+        assert!(execute(&hiffy, &ops).is_err());
+
+        //
+        // And a runaway loop is cut off.
+        //
+        let ops = [
+            Op::Label(Target(0)),
+            Op::Push(0),
+            Op::Push(1),
+            Op::BranchGreaterThan(Target(0)),
+            Op::Done,
+        ];
+        assert!(execute(&hiffy, &ops).is_err());
+    }
+}
@@ -71,15 +71,21 @@
 //! changes. If you implement a `Load` impl by hand, you should try to emulate
 //! this.
 //!
-//! By writing the impl by hand, you can go a step farther. Say a type was
+//! The derived impls can be adjusted with `#[load(...)]` attributes: a field
+//! or variant can be matched under a different name with `rename`, and a field
+//! that is absent from older programs can be marked `default`. Say a type was
 //! represented in one way in older versions, but has changed representation in
-//! newer ones. You can write a `Load` impl that matches on `Value` and handles
-//! either. There's an example of this in `doppel`.
+//! newer ones. An enum marked `#[load(untagged)]` will load whichever of its
+//! variants matches the `Value`; there's an example of this in `doppel`. For
+//! anything more involved, you can write a `Load` impl by hand that matches on
+//! `Value` directly.
 
 use indexmap::IndexMap;
 use std::convert::TryInto;
 
 use anyhow::{anyhow, bail, Result};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Serialize, Serializer};
 
 use humility::core::Core;
 use humility::hubris::{
@@ -205,6 +211,22 @@ impl Format for Value {
     }
 }
 
+/// Values serialize into the shape that `serde` would give the corresponding
+/// Rust types:  structs as maps, tuples and arrays as sequences, and enums as
+/// either the name of the variant or a map from the variant to its contents.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Struct(v) => v.serialize(s),
+            Self::Enum(v) => v.serialize(s),
+            Self::Base(v) => v.serialize(s),
+            Self::Tuple(v) => v.serialize(s),
+            Self::Array(v) => v.serialize(s),
+            Self::Ptr(v) => v.serialize(s),
+        }
+    }
+}
+
 /// A value of an enumeration.
 #[derive(Clone, Debug, Default)]
 pub struct Enum(String, Option<Box<Value>>);
@@ -259,6 +281,19 @@ impl Format for Enum {
     }
 }
 
+impl Serialize for Enum {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let contents = match self.contents() {
+            None => return s.serialize_str(self.disc()),
+            Some(c) => c.as_1tuple().unwrap_or(c),
+        };
+
+        let mut map = s.serialize_map(Some(1))?;
+        map.serialize_entry(self.disc(), contents)?;
+        map.end()
+    }
+}
+
 /// A value of a basetype, often called a "primitive."
 ///
 /// There is one variant of this enum for every fundamental type in Rust, unless
@@ -420,6 +455,30 @@ impl Format for Base {
     }
 }
 
+impl Serialize for Base {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Self::U0 => s.serialize_unit(),
+            Self::U8(x) => s.serialize_u8(x),
+            Self::U16(x) => s.serialize_u16(x),
+            Self::U32(x) => s.serialize_u32(x),
+            Self::U64(x) => s.serialize_u64(x),
+            Self::U128(x) => s.serialize_u128(x),
+
+            Self::I8(x) => s.serialize_i8(x),
+            Self::I16(x) => s.serialize_i16(x),
+            Self::I32(x) => s.serialize_i32(x),
+            Self::I64(x) => s.serialize_i64(x),
+            Self::I128(x) => s.serialize_i128(x),
+
+            Self::F32(x) => s.serialize_f32(x),
+            Self::F64(x) => s.serialize_f64(x),
+
+            Self::Bool(x) => s.serialize_bool(x),
+        }
+    }
+}
+
 /// A struct with named fields.
 #[derive(Clone, Debug, Default)]
 pub struct Struct {
@@ -444,6 +503,11 @@ impl Struct {
         self.members.iter().map(|(s, v)| (s.as_str(), &**v))
     }
 
+    /// Returns the named member, if the struct has it.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.members.get(name).map(|m| &**m)
+    }
+
     /// Verifies that the struct contains _at least_ members with the given
     /// `names`. If not, returns an error.
     pub fn check_members(&self, names: &[&str]) -> Result<()> {
@@ -509,6 +573,18 @@ impl Format for Struct {
     }
 }
 
+impl Serialize for Struct {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut map = s.serialize_map(Some(self.len()))?;
+
+        for (name, value) in self.iter() {
+            map.serialize_entry(name, value)?;
+        }
+
+        map.end()
+    }
+}
+
 /// A tuple or tuple struct.
 #[derive(Clone, Debug, Default)]
 pub struct Tuple(String, Vec<Value>);
@@ -568,6 +644,18 @@ impl Format for Tuple {
     }
 }
 
+impl Serialize for Tuple {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut seq = s.serialize_seq(Some(self.len()))?;
+
+        for e in self.iter() {
+            seq.serialize_element(e)?;
+        }
+
+        seq.end()
+    }
+}
+
 /// An array, e.g. `[T; N]`.
 #[derive(Clone, Debug, Default)]
 pub struct Array(Vec<Value>);
@@ -619,6 +707,18 @@ impl Format for Array {
     }
 }
 
+impl Serialize for Array {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut seq = s.serialize_seq(Some(self.len()))?;
+
+        for e in self.iter() {
+            seq.serialize_element(e)?;
+        }
+
+        seq.end()
+    }
+}
+
 /// A pointer with an embedded type.
 ///
 /// The type is of the _pointer_, not the pointed-to item, so that we can
@@ -668,6 +768,72 @@ impl Format for Ptr {
     }
 }
 
+/// Pointers serialize as the address being pointed to.
+impl Serialize for Ptr {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u32(self.addr())
+    }
+}
+
+/// Flattens a value into named fields, appending them to `fields`.  The
+/// value itself is named `name`; members of structures and tuples are named
+/// by their path within it (e.g., `name.a.0`), and elements of arrays by
+/// their index (e.g., `name[3]`).  An enum contributes a field bearing its
+/// variant, with the fields of its contents named by the variant (and thus
+/// distinct from those of other variants).  If `name` is empty, a top-level
+/// enum or base value is named `variant` or `payload`, respectively.
+pub fn flatten(
+    hubris: &HubrisArchive,
+    fmt: HubrisPrintFormat,
+    name: &str,
+    value: &Value,
+    fields: &mut IndexMap<String, String>,
+) -> Result<()> {
+    let path = |field: &str| {
+        if name.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", name, field)
+        }
+    };
+
+    match value {
+        Value::Enum(e) => {
+            let field = if name.is_empty() { "variant" } else { name };
+            fields.insert(field.to_string(), e.disc().to_string());
+
+            if let Some(c) = e.contents() {
+                let c = c.as_1tuple().unwrap_or(c);
+                flatten(hubris, fmt, &path(e.disc()), c, fields)?;
+            }
+        }
+        Value::Struct(s) => {
+            for (member, v) in s.iter() {
+                flatten(hubris, fmt, &path(member), v, fields)?;
+            }
+        }
+        Value::Tuple(t) => {
+            for (i, v) in t.iter().enumerate() {
+                flatten(hubris, fmt, &path(&i.to_string()), v, fields)?;
+            }
+        }
+        Value::Array(a) => {
+            for (i, v) in a.iter().enumerate() {
+                let field = format!("{}[{}]", name, i);
+                flatten(hubris, fmt, &field, v, fields)?;
+            }
+        }
+        Value::Base(_) | Value::Ptr(_) => {
+            let field = if name.is_empty() { "payload" } else { name };
+            let mut dumped = vec![];
+            value.format(hubris, fmt, &mut dumped)?;
+            fields.insert(field.to_string(), String::from_utf8(dumped)?);
+        }
+    }
+
+    Ok(())
+}
+
 /// Loads data from memory image `buf` at offset `addr` and maps it onto a Rust
 /// `T`.
 pub fn load<'a, T: Load>(
@@ -969,3 +1135,338 @@ impl<T: Load> Load for Vec<T> {
         Ok(out)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doppel::{GenOrRestartCount, Generation};
+
+    fn u8v(x: u8) -> Value {
+        Value::Base(Base::U8(x))
+    }
+
+    fn u32v(x: u32) -> Value {
+        Value::Base(Base::U32(x))
+    }
+
+    fn structv(name: &str, members: &[(&str, Value)]) -> Value {
+        Value::Struct(Struct {
+            name: name.to_string(),
+            members: members
+                .iter()
+                .map(|(n, v)| (n.to_string(), Box::new(v.clone())))
+                .collect(),
+        })
+    }
+
+    fn tuplev(name: &str, fields: &[Value]) -> Value {
+        Value::Tuple(Tuple(name.to_string(), fields.to_vec()))
+    }
+
+    fn enumv(disc: &str, contents: Option<Value>) -> Value {
+        Value::Enum(Enum(disc.to_string(), contents.map(Box::new)))
+    }
+
+    #[derive(Debug, PartialEq, Load)]
+    struct Named {
+        a: u32,
+        #[load(rename = "bee")]
+        b: u8,
+        #[load(default)]
+        c: u32,
+    }
+
+    #[derive(Debug, PartialEq, Load)]
+    struct Short(u8, #[load(default)] u32, #[load(default)] u32);
+
+    #[derive(Debug, PartialEq, Load)]
+    enum Tagged {
+        Unit,
+        #[load(rename = "Renamed")]
+        Other,
+        Tuple(u8, #[load(default)] u32),
+        Named {
+            x: u32,
+        },
+    }
+
+    #[derive(Debug, PartialEq, Load)]
+    #[load(untagged)]
+    enum Either {
+        First(u32),
+        Second(u32),
+    }
+
+    #[derive(Debug, PartialEq, Load)]
+    #[load(untagged)]
+    enum Shape {
+        Pair(u8, u8),
+        Single(u32),
+        Fields { x: u8 },
+    }
+
+    #[derive(Debug, PartialEq, Load)]
+    struct Wrapper<T> {
+        inner: T,
+    }
+
+    #[test]
+    fn named_members() {
+        let v = structv(
+            "Named",
+            &[("a", u32v(1)), ("bee", u8v(2)), ("c", u32v(3))],
+        );
+        assert_eq!(Named::from_value(&v).unwrap(), Named { a: 1, b: 2, c: 3 });
+    }
+
+    #[test]
+    fn named_default_absent() {
+        let v = structv("Named", &[("a", u32v(1)), ("bee", u8v(2))]);
+        assert_eq!(Named::from_value(&v).unwrap(), Named { a: 1, b: 2, c: 0 });
+    }
+
+    #[test]
+    fn named_required_absent() {
+        let v = structv("Named", &[("bee", u8v(2)), ("c", u32v(3))]);
+        assert!(Named::from_value(&v).is_err());
+
+        //
+        // A renamed member must be present under its new name.
+        //
+        let v = structv("Named", &[("a", u32v(1)), ("b", u8v(2))]);
+        assert!(Named::from_value(&v).is_err());
+    }
+
+    #[test]
+    fn named_wrong_shape() {
+        assert!(Named::from_value(&tuplev("Named", &[u32v(1)])).is_err());
+        assert!(Named::from_value(&u32v(1)).is_err());
+
+        let v = structv("Named", &[("a", u8v(1)), ("bee", u8v(2))]);
+        assert!(Named::from_value(&v).is_err());
+    }
+
+    #[test]
+    fn tuple_short() {
+        let v = tuplev("Short", &[u8v(1)]);
+        assert_eq!(Short::from_value(&v).unwrap(), Short(1, 0, 0));
+
+        let v = tuplev("Short", &[u8v(1), u32v(2)]);
+        assert_eq!(Short::from_value(&v).unwrap(), Short(1, 2, 0));
+
+        let v = tuplev("Short", &[u8v(1), u32v(2), u32v(3)]);
+        assert_eq!(Short::from_value(&v).unwrap(), Short(1, 2, 3));
+    }
+
+    #[test]
+    fn tuple_wrong_length() {
+        assert!(Short::from_value(&tuplev("Short", &[])).is_err());
+
+        let v = tuplev("Short", &[u8v(1), u32v(2), u32v(3), u32v(4)]);
+        assert!(Short::from_value(&v).is_err());
+    }
+
+    #[test]
+    fn tuple_exact_length() {
+        assert_eq!(
+            Generation::from_value(&tuplev("Generation", &[u8v(3)])).unwrap(),
+            Generation(3)
+        );
+
+        assert!(Generation::from_value(&tuplev("Generation", &[])).is_err());
+
+        let v = tuplev("Generation", &[u8v(3), u8v(4)]);
+        assert!(Generation::from_value(&v).is_err());
+    }
+
+    #[test]
+    fn tagged_enum() {
+        assert_eq!(
+            Tagged::from_value(&enumv("Unit", None)).unwrap(),
+            Tagged::Unit
+        );
+
+        assert_eq!(
+            Tagged::from_value(&enumv("Renamed", None)).unwrap(),
+            Tagged::Other
+        );
+
+        assert!(Tagged::from_value(&enumv("Other", None)).is_err());
+        assert!(Tagged::from_value(&enumv("Missing", None)).is_err());
+
+        let v = enumv("Tuple", Some(tuplev("", &[u8v(1)])));
+        assert_eq!(Tagged::from_value(&v).unwrap(), Tagged::Tuple(1, 0));
+
+        let v = enumv("Tuple", Some(tuplev("", &[u8v(1), u32v(2)])));
+        assert_eq!(Tagged::from_value(&v).unwrap(), Tagged::Tuple(1, 2));
+
+        let v = enumv("Named", Some(structv("", &[("x", u32v(5))])));
+        assert_eq!(Tagged::from_value(&v).unwrap(), Tagged::Named { x: 5 });
+
+        assert!(Tagged::from_value(&enumv("Named", None)).is_err());
+    }
+
+    #[test]
+    fn untagged_order() {
+        //
+        // Both variants can be loaded from a u32; the first wins.
+        //
+        assert_eq!(Either::from_value(&u32v(5)).unwrap(), Either::First(5));
+    }
+
+    #[test]
+    fn untagged_fallthrough() {
+        let v = tuplev("", &[u8v(1), u8v(2)]);
+        assert_eq!(Shape::from_value(&v).unwrap(), Shape::Pair(1, 2));
+
+        assert_eq!(Shape::from_value(&u32v(3)).unwrap(), Shape::Single(3));
+
+        let v = structv("", &[("x", u8v(4))]);
+        assert_eq!(Shape::from_value(&v).unwrap(), Shape::Fields { x: 4 });
+
+        assert!(Shape::from_value(&u8v(5)).is_err());
+        assert!(Shape::from_value(&tuplev("", &[u8v(1)])).is_err());
+    }
+
+    #[test]
+    fn gen_or_restart_count() {
+        let v = tuplev("Generation", &[u8v(3)]);
+        assert_eq!(
+            GenOrRestartCount::from_value(&v).unwrap(),
+            GenOrRestartCount::Gen(Generation(3))
+        );
+
+        assert_eq!(
+            GenOrRestartCount::from_value(&u32v(0x47)).unwrap(),
+            GenOrRestartCount::RestartCount(0x47)
+        );
+
+        assert!(GenOrRestartCount::from_value(&u8v(3)).is_err());
+    }
+
+    #[test]
+    fn generic_struct() {
+        let v = structv("Wrapper", &[("inner", u32v(7))]);
+        assert_eq!(
+            Wrapper::<u32>::from_value(&v).unwrap(),
+            Wrapper { inner: 7 }
+        );
+
+        let v =
+            structv("Wrapper", &[("inner", tuplev("Generation", &[u8v(2)]))]);
+        assert_eq!(
+            Wrapper::<Generation>::from_value(&v).unwrap(),
+            Wrapper { inner: Generation(2) }
+        );
+
+        assert!(Wrapper::<Generation>::from_value(&structv(
+            "Wrapper",
+            &[("inner", u32v(2))]
+        ))
+        .is_err());
+    }
+
+    fn arrayv(elements: &[Value]) -> Value {
+        Value::Array(Array(elements.to_vec()))
+    }
+
+    fn flattened(name: &str, value: &Value) -> Vec<(String, String)> {
+        let hubris = HubrisArchive::new().unwrap();
+        let mut fields = IndexMap::new();
+
+        flatten(
+            &hubris,
+            HubrisPrintFormat::default(),
+            name,
+            value,
+            &mut fields,
+        )
+        .unwrap();
+
+        fields.into_iter().collect()
+    }
+
+    fn fields(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn flatten_top_level() {
+        assert_eq!(flattened("", &u8v(7)), fields(&[("payload", "7")]));
+
+        assert_eq!(
+            flattened("", &enumv("Idle", None)),
+            fields(&[("variant", "Idle")])
+        );
+
+        assert_eq!(
+            flattened("", &structv("Point", &[("x", u8v(1)), ("y", u8v(2))])),
+            fields(&[("x", "1"), ("y", "2")])
+        );
+
+        assert_eq!(
+            flattened("", &arrayv(&[u8v(1), u8v(2)])),
+            fields(&[("[0]", "1"), ("[1]", "2")])
+        );
+    }
+
+    #[test]
+    fn flatten_variants() {
+        //
+        // A variant that holds a single value is named by the variant
+        // alone, rather than as a 1-tuple.
+        //
+        let v = enumv("FanRead", Some(tuplev("FanRead", &[u8v(1)])));
+        assert_eq!(
+            flattened("", &v),
+            fields(&[("variant", "FanRead"), ("FanRead", "1")])
+        );
+
+        let v = enumv("Pair", Some(tuplev("Pair", &[u8v(1), u8v(2)])));
+        assert_eq!(
+            flattened("", &v),
+            fields(&[("variant", "Pair"), ("Pair.0", "1"), ("Pair.1", "2")])
+        );
+
+        let v = enumv(
+            "Move",
+            Some(structv(
+                "Move",
+                &[("x", u8v(1)), ("y", arrayv(&[u8v(2), u8v(3)]))],
+            )),
+        );
+
+        assert_eq!(
+            flattened("", &v),
+            fields(&[
+                ("variant", "Move"),
+                ("Move.x", "1"),
+                ("Move.y[0]", "2"),
+                ("Move.y[1]", "3"),
+            ])
+        );
+    }
+
+    #[test]
+    fn flatten_named() {
+        let v = structv(
+            "State",
+            &[
+                ("mode", enumv("On", Some(tuplev("On", &[u32v(3)])))),
+                ("ports", arrayv(&[tuplev("Port", &[u8v(4), u8v(5)])])),
+                ("idle", enumv("Off", None)),
+            ],
+        );
+
+        assert_eq!(
+            flattened("state", &v),
+            fields(&[
+                ("state.mode", "On"),
+                ("state.mode.On", "3"),
+                ("state.ports[0].0", "4"),
+                ("state.ports[0].1", "5"),
+                ("state.idle", "Off"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,354 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client support for `humility server`.
+//!
+//! A server holds a single attached target (and a single [`HiffyContext`])
+//! open, and accepts requests over a local Unix domain socket.  Requests and
+//! responses are [`Request`] and [`Response`], serialized as JSON, one per
+//! line.  When a server is running (and neither a probe nor a dump has been
+//! specified), commands are attached to it via [`ServerCore`], which
+//! forwards each target operation to the server; HIF programs are forwarded
+//! in their entirety, and executed by the server's own context.
+//!
+//! [`HiffyContext`]: crate::hiffy::HiffyContext
+
+use crate::hiffy::{HiffyFunction, HiffyFunctions};
+use anyhow::{anyhow, bail, Context, Result};
+use hif::Op;
+use humility::arch::ARMRegister;
+use humility::core::Core;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    Info,
+    ReadWord32(u32),
+    Read8(u32, usize),
+    ReadReg(u16),
+    WriteReg(u16, u32),
+    InitSwv,
+    ReadSwv,
+    WriteWord32(u32, u32),
+    Write8(u32, Vec<u8>),
+    Halt,
+    Run,
+    Step,
+    Hiffy {
+        ops: Vec<Op>,
+        data: Option<Vec<u8>>,
+        timeout: u32,
+    },
+    Call {
+        call: String,
+        #[serde(default)]
+        task: Option<String>,
+        #[serde(default)]
+        args: Vec<(String, String)>,
+    },
+    Functions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    Ok,
+    Info(String, Option<String>),
+    Word(u32),
+    Bytes(Vec<u8>),
+    Results(Vec<Result<Vec<u8>, u32>>),
+    Reply(Result<serde_json::Value, serde_json::Value>),
+    Functions(Vec<HiffyFunction>),
+    Error(String),
+}
+
+impl Response {
+    fn unexpected(self) -> anyhow::Error {
+        match self {
+            Response::Error(err) => anyhow!("server: {}", err),
+            r => anyhow!("unexpected response from server: {:?}", r),
+        }
+    }
+}
+
+///
+/// Checks that the specified path is owned by us and is inaccessible to
+/// others, failing if it isn't.  Anyone that can connect to the server's
+/// socket can drive the target (and anyone that can replace it can
+/// masquerade as the server), so we are strict about both.
+///
+pub fn check_private(path: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let uid = unsafe { libc::geteuid() };
+
+    if metadata.uid() != uid {
+        bail!(
+            "{} is owned by uid {}, not by us (uid {})",
+            path.display(),
+            metadata.uid(),
+            uid
+        );
+    }
+
+    if metadata.mode() & 0o077 != 0 {
+        bail!(
+            "{} is accessible by others (mode {:o})",
+            path.display(),
+            metadata.mode() & 0o777
+        );
+    }
+
+    Ok(())
+}
+
+///
+/// Returns the directory in which the server's socket is created by default:
+/// `XDG_RUNTIME_DIR` if set, or a private directory of our own in the
+/// temporary directory otherwise (which is created if need be).
+///
+fn socket_dir() -> Result<PathBuf> {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let uid = unsafe { libc::geteuid() };
+            let dir = std::env::temp_dir().join(format!("humility-{}", uid));
+
+            match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => bail!("failed to create {}: {}", dir.display(), e),
+            }
+
+            dir
+        }
+    };
+
+    if !std::fs::symlink_metadata(&dir)?.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+
+    check_private(&dir)?;
+
+    Ok(dir)
+}
+
+///
+/// Returns the path of the server's socket:  `HUMILITY_SERVER` if set,
+/// `humility.sock` in our private socket directory otherwise.
+///
+pub fn socket_path() -> Result<PathBuf> {
+    match std::env::var_os("HUMILITY_SERVER") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Ok(socket_dir()?.join("humility.sock")),
+    }
+}
+
+struct Connection {
+    reader: BufReader<UnixStream>,
+    stream: UnixStream,
+}
+
+impl Connection {
+    fn request(&mut self, request: &Request) -> Result<Response> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        self.stream.write_all(line.as_bytes())?;
+
+        line.clear();
+
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("server closed connection");
+        }
+
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+//
+// We have (at most) one connection to the server, which is shared by the
+// core and by any HIF context.
+//
+thread_local! {
+    static CONNECTION: RefCell<Option<Connection>> = RefCell::new(None);
+}
+
+fn request(request: &Request) -> Result<Response> {
+    CONNECTION.with(|c| match c.borrow_mut().as_mut() {
+        Some(connection) => connection.request(request),
+        None => bail!("not connected to a server"),
+    })
+}
+
+/// Returns true if we are connected to a server
+pub fn connected() -> bool {
+    CONNECTION.with(|c| c.borrow().is_some())
+}
+
+///
+/// Connects to the server, if one is running.  A socket that refuses our
+/// connection is assumed to have been left behind by a server that is no
+/// longer running, and is ignored.  A socket that isn't private to us is an
+/// error:  it may not be our server behind it.
+///
+pub fn connect() -> Result<Option<ServerCore>> {
+    let path = socket_path()?;
+
+    match std::fs::symlink_metadata(&path) {
+        Ok(_) => check_private(&path).with_context(|| {
+            format!("refusing to use server at {}", path.display())
+        })?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => bail!("failed to stat {}: {}", path.display(), e),
+    }
+
+    let stream = match UnixStream::connect(&path) {
+        Ok(stream) => stream,
+        Err(e)
+            if e.kind() == ErrorKind::NotFound
+                || e.kind() == ErrorKind::ConnectionRefused =>
+        {
+            return Ok(None);
+        }
+        Err(e) => {
+            bail!("failed to connect to server at {}: {}", path.display(), e)
+        }
+    };
+
+    let reader = BufReader::new(stream.try_clone()?);
+
+    CONNECTION.with(|c| {
+        *c.borrow_mut() = Some(Connection { reader, stream });
+    });
+
+    let info = match request(&Request::Info)? {
+        Response::Info(probe, serial) => (probe, serial),
+        r => return Err(r.unexpected()),
+    };
+
+    humility::msg!("attached via server at {}", path.display());
+
+    Ok(Some(ServerCore { info }))
+}
+
+///
+/// Executes a HIF program on the server, returning its results.
+///
+pub fn hiffy(
+    ops: &[Op],
+    data: Option<&[u8]>,
+    timeout: u32,
+) -> Result<Vec<Result<Vec<u8>, u32>>> {
+    let hiffy = Request::Hiffy {
+        ops: ops.to_vec(),
+        data: data.map(|d| d.to_vec()),
+        timeout,
+    };
+
+    match request(&hiffy)? {
+        Response::Results(results) => Ok(results),
+        r => Err(r.unexpected()),
+    }
+}
+
+///
+/// Returns the HIF functions, as discovered by the server.  (The server and
+/// its clients are validated against the same image, so the types that the
+/// functions refer to are the same in each.)
+///
+pub fn functions() -> Result<HiffyFunctions> {
+    match request(&Request::Functions)? {
+        Response::Functions(functions) => Ok(HiffyFunctions(
+            functions.into_iter().map(|f| (f.name.clone(), f)).collect(),
+        )),
+        r => Err(r.unexpected()),
+    }
+}
+
+pub struct ServerCore {
+    info: (String, Option<String>),
+}
+
+impl ServerCore {
+    fn ok(&mut self, req: Request) -> Result<()> {
+        match request(&req)? {
+            Response::Ok => Ok(()),
+            r => Err(r.unexpected()),
+        }
+    }
+
+    fn bytes(&mut self, req: Request) -> Result<Vec<u8>> {
+        match request(&req)? {
+            Response::Bytes(bytes) => Ok(bytes),
+            r => Err(r.unexpected()),
+        }
+    }
+
+    fn word(&mut self, req: Request) -> Result<u32> {
+        match request(&req)? {
+            Response::Word(word) => Ok(word),
+            r => Err(r.unexpected()),
+        }
+    }
+}
+
+impl Core for ServerCore {
+    fn info(&self) -> (String, Option<String>) {
+        self.info.clone()
+    }
+
+    fn read_word_32(&mut self, addr: u32) -> Result<u32> {
+        self.word(Request::ReadWord32(addr))
+    }
+
+    fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        let bytes = self.bytes(Request::Read8(addr, data.len()))?;
+
+        if bytes.len() != data.len() {
+            bail!("expected {} bytes, found {}", data.len(), bytes.len());
+        }
+
+        data.copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
+        self.word(Request::ReadReg(reg as u16))
+    }
+
+    fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()> {
+        self.ok(Request::WriteReg(reg as u16, value))
+    }
+
+    fn init_swv(&mut self) -> Result<()> {
+        self.ok(Request::InitSwv)
+    }
+
+    fn read_swv(&mut self) -> Result<Vec<u8>> {
+        self.bytes(Request::ReadSwv)
+    }
+
+    fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()> {
+        self.ok(Request::WriteWord32(addr, data))
+    }
+
+    fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        self.ok(Request::Write8(addr, data.to_vec()))
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        self.ok(Request::Halt)
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.ok(Request::Run)
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.ok(Request::Step)
+    }
+}
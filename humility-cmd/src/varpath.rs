@@ -0,0 +1,608 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Path expressions for variables.
+//!
+//! A [`VariablePath`] names a global variable, optionally followed by a path
+//! to a part of it, e.g. `TASK_STATE.ports[3].status.link_up`:
+//!
+//! - `.member` denotes a member of a structure, or (as `.0`, `.1`, etc.) a
+//!   member of a tuple or tuple struct;
+//! - `[n]` denotes an element of an array;
+//! - `.Variant` denotes the contents of an enum, which must currently be the
+//!   named variant;
+//! - `*` (as a prefix) dereferences a pointer that the path denotes.
+//!
+//! Pointers encountered in the middle of a path are dereferenced
+//! implicitly, so `CURRENT_TASK_PTR.Some.0.pointer.priority` denotes the
+//! priority of the current task.  Variable names may be qualified by the
+//! path of their task, e.g. `task_thermal::THERMAL_RINGBUF`.
+//!
+//! Paths are resolved against the DWARF type information to an address and
+//! a type; resolution only reads the target (via [`Core`]) when it must
+//! determine the variant of an enum or the value of a pointer.
+
+use crate::reflect::{self, Ptr};
+use anyhow::{anyhow, bail, Context, Result};
+use humility::core::Core;
+use humility::hubris::*;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Element<'a> {
+    Member(&'a str),
+    Index(usize),
+}
+
+impl fmt::Display for Element<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Element::Member(name) => write!(f, ".{}", name),
+            Element::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VariablePath<'a> {
+    /// name of the variable, as specified
+    pub name: &'a str,
+    /// members and elements within the variable
+    pub elements: Vec<Element<'a>>,
+    /// number of times the result is to be dereferenced
+    pub derefs: usize,
+}
+
+impl<'a> VariablePath<'a> {
+    pub fn parse(expr: &'a str) -> Result<Self> {
+        let trimmed = expr.trim_start_matches(|c: char| c == '*' || c == ' ');
+        let derefs = expr[..expr.len() - trimmed.len()]
+            .chars()
+            .filter(|&c| c == '*')
+            .count();
+
+        let delim = |c: char| c == '.' || c == '[' || c == ']';
+        let end = trimmed.find(delim).unwrap_or(trimmed.len());
+        let (name, mut rest) = trimmed.split_at(end);
+        let mut elements = vec![];
+
+        if name.is_empty() {
+            bail!("expected variable name in \"{}\"", expr);
+        }
+
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix('.') {
+                let end = r.find(delim).unwrap_or(r.len());
+
+                if end == 0 {
+                    bail!("expected member name in \"{}\"", expr);
+                }
+
+                elements.push(Element::Member(&r[..end]));
+                rest = &r[end..];
+            } else if let Some(r) = rest.strip_prefix('[') {
+                let end = r.find(']').ok_or_else(|| {
+                    anyhow!("unterminated index in \"{}\"", expr)
+                })?;
+
+                let index = r[..end].trim();
+                let index = parse_int::parse::<usize>(index)
+                    .with_context(|| format!("illegal index \"{}\"", index))?;
+
+                elements.push(Element::Index(index));
+                rest = &r[end + 1..];
+            } else {
+                bail!("unexpected \"{}\" in \"{}\"", rest, expr);
+            }
+        }
+
+        Ok(Self { name, elements, derefs })
+    }
+
+    /// Returns true if this path denotes an entire variable
+    pub fn is_variable(&self) -> bool {
+        self.elements.is_empty() && self.derefs == 0
+    }
+
+    ///
+    /// Looks up the variables that this path could denote:  the variable of
+    /// the specified name in each task that has one, or -- if the name is
+    /// qualified by the path of its task -- that task's alone.
+    ///
+    pub fn variables<'b>(
+        &self,
+        hubris: &'b HubrisArchive,
+    ) -> Result<Vec<&'b HubrisVariable>> {
+        let qualified = hubris
+            .qualified_variables()
+            .filter(|(n, _)| *n == self.name)
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>();
+
+        if !qualified.is_empty() {
+            return Ok(qualified);
+        }
+
+        Ok(hubris.lookup_variables(self.name)?.iter().collect())
+    }
+
+    ///
+    /// Looks up the variable that this path denotes, failing if it is
+    /// ambiguous.
+    ///
+    pub fn variable<'b>(
+        &self,
+        hubris: &'b HubrisArchive,
+    ) -> Result<&'b HubrisVariable> {
+        match self.variables(hubris)?.as_slice() {
+            [variable] => Ok(*variable),
+            _ => bail!(
+                "variable {} is present in more than one task; \
+                qualify it (use \"humility readvar -l\" to list)",
+                self.name
+            ),
+        }
+    }
+
+    ///
+    /// Resolves this path within the specified variable, returning the
+    /// address and type that it denotes.  The target should be halted if
+    /// it is running, as the path may entail reading enums or pointers.
+    ///
+    pub fn resolve(
+        &self,
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+        variable: &HubrisVariable,
+    ) -> Result<(u32, HubrisGoff)> {
+        let mut addr = variable.addr;
+        let mut goff = variable.goff;
+
+        for (ndx, element) in self.elements.iter().enumerate() {
+            //
+            // Pointers (and pointers to pointers) are dereferenced until we
+            // find something that the element can apply to.
+            //
+            if let Some(mut ptr) = Self::pointer(hubris, core, addr, goff)? {
+                while let HubrisType::Ptr(_) =
+                    hubris.lookup_type(ptr.dest_goff(hubris)?)?
+                {
+                    ptr = ptr.load_from(hubris, core)?;
+                }
+
+                addr = ptr.addr();
+                goff = ptr.dest_goff(hubris)?;
+            }
+
+            let (offset, g) = Self::element(hubris, core, addr, goff, element)?;
+            addr = addr.checked_add(offset).ok_or_else(|| {
+                let path = self.elements[..=ndx]
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<String>();
+
+                anyhow!(
+                    "{}{}: offset 0x{:x} from 0x{:x} overflows address",
+                    self.name,
+                    path,
+                    offset,
+                    addr
+                )
+            })?;
+            goff = g;
+        }
+
+        for _ in 0..self.derefs {
+            match Self::pointer(hubris, core, addr, goff)? {
+                Some(ptr) => {
+                    addr = ptr.addr();
+                    goff = ptr.dest_goff(hubris)?;
+                }
+                None => {
+                    let ty = hubris.lookup_type(goff)?;
+                    bail!("cannot dereference {}", ty.name(hubris)?);
+                }
+            }
+        }
+
+        Ok((addr, goff))
+    }
+
+    //
+    // Loads the pointer at the specified address -- if the type there is a
+    // pointer.
+    //
+    fn pointer(
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+        addr: u32,
+        goff: HubrisGoff,
+    ) -> Result<Option<Ptr>> {
+        let ty = hubris.lookup_type(goff)?;
+
+        if let HubrisType::Ptr(_) = ty {
+            let mut buf = vec![0u8; ty.size(hubris)?];
+            core.read_8(addr, &mut buf)?;
+            Ok(Some(reflect::load(hubris, &buf, ty, 0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn element(
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+        addr: u32,
+        goff: HubrisGoff,
+        element: &Element,
+    ) -> Result<(u32, HubrisGoff)> {
+        let ty = hubris.lookup_type(goff)?;
+
+        match (element, ty) {
+            (Element::Member(name), HubrisType::Struct(s)) => {
+                //
+                // Tuples have members named __0, __1, etc.
+                //
+                let member = match name.parse::<usize>() {
+                    Ok(n) => s.lookup_member(&format!("__{}", n))?,
+                    Err(_) => s.lookup_member(name)?,
+                };
+
+                Ok((member.offset as u32, member.goff))
+            }
+            (Element::Member(name), HubrisType::Enum(e)) => {
+                let mut buf = vec![0u8; hubris.typesize(goff)?];
+                core.read_8(addr, &mut buf)?;
+
+                let variant = e.determine_variant(hubris, &buf)?;
+
+                if variant.name != *name {
+                    bail!("{} is {}, not {}", e.name, variant.name, name);
+                }
+
+                //
+                // The contents of a variant are relative to the enum itself.
+                //
+                match variant.goff {
+                    Some(goff) => Ok((0, goff)),
+                    None => bail!("{}::{} has no contents", e.name, name),
+                }
+            }
+            (Element::Index(index), HubrisType::Array(a)) => {
+                if *index >= a.count {
+                    bail!(
+                        "index {} exceeds bounds of {}",
+                        index,
+                        ty.name(hubris)?
+                    );
+                }
+
+                Ok(((index * hubris.typesize(a.goff)?) as u32, a.goff))
+            }
+            (Element::Member(name), _) => {
+                bail!(
+                    "can't find member {}: {} is not a structure or enum",
+                    name,
+                    ty.name(hubris)?
+                );
+            }
+            (Element::Index(index), _) => {
+                bail!(
+                    "can't find element {}: {} is not an array",
+                    index,
+                    ty.name(hubris)?
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockCore;
+
+    fn parse(expr: &str) -> (&str, Vec<Element>, usize) {
+        let path = VariablePath::parse(expr).unwrap();
+        (path.name, path.elements, path.derefs)
+    }
+
+    #[test]
+    fn parse_variable() {
+        assert_eq!(parse("TICKS"), ("TICKS", vec![], 0));
+        assert!(VariablePath::parse("TICKS").unwrap().is_variable());
+
+        assert_eq!(
+            parse("task_thermal::THERMAL_RINGBUF"),
+            ("task_thermal::THERMAL_RINGBUF", vec![], 0)
+        );
+    }
+
+    #[test]
+    fn parse_elements() {
+        use Element::*;
+
+        assert_eq!(
+            parse("TASK_STATE.ports[3].status.link_up"),
+            (
+                "TASK_STATE",
+                vec![
+                    Member("ports"),
+                    Index(3),
+                    Member("status"),
+                    Member("link_up")
+                ],
+                0
+            )
+        );
+
+        assert_eq!(
+            parse("CURRENT_TASK_PTR.Some.0.pointer.priority"),
+            (
+                "CURRENT_TASK_PTR",
+                vec![
+                    Member("Some"),
+                    Member("0"),
+                    Member("pointer"),
+                    Member("priority")
+                ],
+                0
+            )
+        );
+
+        assert_eq!(
+            parse("TABLE[0x10][ 2 ]"),
+            ("TABLE", vec![Index(0x10), Index(2)], 0)
+        );
+    }
+
+    #[test]
+    fn parse_derefs() {
+        assert_eq!(parse("*PTR"), ("PTR", vec![], 1));
+        assert_eq!(parse("* *PTR.0"), ("PTR", vec![Element::Member("0")], 2));
+        assert!(!VariablePath::parse("*PTR").unwrap().is_variable());
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for expr in [
+            "",
+            "*",
+            ".member",
+            "[0]",
+            "FOO.",
+            "FOO..bar",
+            "FOO.[0]",
+            "FOO[",
+            "FOO[0",
+            "FOO[]",
+            "FOO[x]",
+            "FOO[-1]",
+            "FOO[0]bar",
+            "FOO]",
+            "FOO.bar]",
+            "FOO[0]]",
+        ] {
+            assert!(VariablePath::parse(expr).is_err(), "{:?}", expr);
+        }
+    }
+
+    #[test]
+    fn parse_out_of_range() {
+        assert!(VariablePath::parse("FOO[99999999999999999999999]").is_err());
+    }
+
+    #[test]
+    fn display_elements() {
+        let path = VariablePath::parse("TASK_STATE.ports[3].0").unwrap();
+        let elements =
+            path.elements.iter().map(|e| e.to_string()).collect::<String>();
+
+        assert_eq!(elements, ".ports[3].0");
+    }
+
+    const BASE: u32 = 0x2000_0000;
+
+    const U8: HubrisGoff = HubrisGoff { object: 0, goff: 1 };
+    const U32: HubrisGoff = HubrisGoff { object: 0, goff: 2 };
+    const PORT: HubrisGoff = HubrisGoff { object: 0, goff: 3 };
+    const PORTS: HubrisGoff = HubrisGoff { object: 0, goff: 4 };
+    const STATE: HubrisGoff = HubrisGoff { object: 0, goff: 5 };
+    const PTR: HubrisGoff = HubrisGoff { object: 0, goff: 6 };
+    const PTRPTR: HubrisGoff = HubrisGoff { object: 0, goff: 7 };
+    const OPTION: HubrisGoff = HubrisGoff { object: 0, goff: 8 };
+    const SOME: HubrisGoff = HubrisGoff { object: 0, goff: 9 };
+
+    fn member(
+        name: &str,
+        offset: usize,
+        goff: HubrisGoff,
+    ) -> HubrisStructMember {
+        HubrisStructMember { name: name.to_string(), offset, goff }
+    }
+
+    fn variant(
+        name: &str,
+        tag: u64,
+        goff: Option<HubrisGoff>,
+    ) -> HubrisEnumVariant {
+        HubrisEnumVariant {
+            name: name.to_string(),
+            offset: 0,
+            goff,
+            tag: Some(tag),
+        }
+    }
+
+    //
+    // Types for the equivalent of:
+    //
+    //     struct Port { status: u8, speed: u32 }
+    //
+    //     struct State {
+    //         ports: [Port; 4],
+    //         current: *const Port,
+    //         indirect: *const *const Port,
+    //     }
+    //
+    // along with an Option<u32>.
+    //
+    fn archive() -> HubrisArchive {
+        let mut hubris = HubrisArchive::new().unwrap();
+
+        let base =
+            |size| HubrisBasetype { encoding: HubrisEncoding::Unsigned, size };
+        hubris.add_basetype(U8, base(1));
+        hubris.add_basetype(U32, base(4));
+
+        hubris.add_struct(HubrisStruct {
+            name: "Port".to_string(),
+            goff: PORT,
+            size: 8,
+            members: vec![member("status", 0, U8), member("speed", 4, U32)],
+        });
+
+        hubris.add_array(PORTS, HubrisArray { goff: PORT, count: 4 });
+        hubris.add_ptrtype(PTR, "*const Port", PORT);
+        hubris.add_ptrtype(PTRPTR, "*const *const Port", PTR);
+
+        hubris.add_struct(HubrisStruct {
+            name: "State".to_string(),
+            goff: STATE,
+            size: 40,
+            members: vec![
+                member("ports", 0, PORTS),
+                member("current", 32, PTR),
+                member("indirect", 36, PTRPTR),
+            ],
+        });
+
+        hubris.add_struct(HubrisStruct {
+            name: "Some".to_string(),
+            goff: SOME,
+            size: 8,
+            members: vec![member("__0", 4, U32)],
+        });
+
+        hubris.add_enum(HubrisEnum {
+            name: "Option<u32>".to_string(),
+            goff: OPTION,
+            size: 8,
+            discriminant: Some(HubrisDiscriminant::Value(U32, 0)),
+            tag: None,
+            variants: vec![
+                variant("None", 0, None),
+                variant("Some", 1, Some(SOME)),
+            ],
+        });
+
+        hubris
+    }
+
+    fn resolve(
+        hubris: &HubrisArchive,
+        core: &mut dyn Core,
+        variable: &HubrisVariable,
+        expr: &str,
+    ) -> Result<(u32, HubrisGoff)> {
+        VariablePath::parse(expr)?.resolve(hubris, core, variable)
+    }
+
+    #[test]
+    fn resolve_elements() {
+        let hubris = archive();
+        let mut core = MockCore::zeroed(BASE, 0x1000);
+        let state = HubrisVariable { goff: STATE, addr: BASE, size: 40 };
+
+        let mut resolve = |expr| resolve(&hubris, &mut core, &state, expr);
+
+        assert_eq!(resolve("STATE").unwrap(), (BASE, STATE));
+        assert_eq!(resolve("STATE.ports[2]").unwrap(), (BASE + 16, PORT));
+        assert_eq!(resolve("STATE.ports[3].speed").unwrap(), (BASE + 28, U32));
+
+        //
+        // Elements must exist, and must apply to the type they follow.
+        //
+        assert!(resolve("STATE.ports[4]").is_err());
+        assert!(resolve("STATE.ports[0].bogus").is_err());
+        assert!(resolve("STATE.ports.status").is_err());
+        assert!(resolve("STATE[0]").is_err());
+        assert!(resolve("STATE.ports[0].speed[0]").is_err());
+    }
+
+    #[test]
+    fn resolve_pointers() {
+        let hubris = archive();
+        let mut core = MockCore::zeroed(BASE, 0x1000);
+        let state = HubrisVariable { goff: STATE, addr: BASE, size: 40 };
+
+        //
+        // The current port is at BASE + 0x40, and the indirect pointer points
+        // to a pointer (at BASE + 0x80) to it.
+        //
+        core.write_word_32(BASE + 32, BASE + 0x40).unwrap();
+        core.write_word_32(BASE + 36, BASE + 0x80).unwrap();
+        core.write_word_32(BASE + 0x80, BASE + 0x40).unwrap();
+
+        let mut resolve = |expr| resolve(&hubris, &mut core, &state, expr);
+
+        assert_eq!(resolve("STATE.current").unwrap(), (BASE + 32, PTR));
+        assert_eq!(resolve("*STATE.current").unwrap(), (BASE + 0x40, PORT));
+        assert_eq!(resolve("STATE.current.speed").unwrap(), (BASE + 0x44, U32));
+
+        //
+        // Pointers to pointers are chased implicitly, but dereferenced
+        // explicitly only as many times as specified.
+        //
+        assert_eq!(
+            resolve("STATE.indirect.status").unwrap(),
+            (BASE + 0x40, U8)
+        );
+        assert_eq!(resolve("*STATE.indirect").unwrap(), (BASE + 0x80, PTR));
+        assert_eq!(resolve("**STATE.indirect").unwrap(), (BASE + 0x40, PORT));
+
+        assert!(resolve("***STATE.indirect").is_err());
+        assert!(resolve("*STATE.ports").is_err());
+    }
+
+    #[test]
+    fn resolve_variants() {
+        let hubris = archive();
+        let mut core = MockCore::zeroed(BASE, 0x1000);
+        let option = HubrisVariable { goff: OPTION, addr: BASE, size: 8 };
+
+        //
+        // The enum is None, which has no contents -- and isn't Some.
+        //
+        assert!(resolve(&hubris, &mut core, &option, "OPT.None").is_err());
+        assert!(resolve(&hubris, &mut core, &option, "OPT.Some").is_err());
+        assert!(resolve(&hubris, &mut core, &option, "OPT.Bogus").is_err());
+
+        core.write_word_32(BASE, 1).unwrap();
+
+        assert_eq!(
+            resolve(&hubris, &mut core, &option, "OPT.Some").unwrap(),
+            (BASE, SOME)
+        );
+        assert_eq!(
+            resolve(&hubris, &mut core, &option, "OPT.Some.0").unwrap(),
+            (BASE + 4, U32)
+        );
+        assert!(resolve(&hubris, &mut core, &option, "OPT.None").is_err());
+    }
+
+    #[test]
+    fn resolve_overflow() {
+        let hubris = archive();
+        let mut core = MockCore::zeroed(BASE, 0x1000);
+        let state = HubrisVariable { goff: STATE, addr: 0xffff_fff0, size: 40 };
+
+        let mut resolve = |expr| resolve(&hubris, &mut core, &state, expr);
+
+        assert_eq!(resolve("STATE.ports[1]").unwrap(), (0xffff_fff8, PORT));
+
+        let err = resolve("STATE.current").unwrap_err();
+        assert!(err.to_string().contains("overflows"), "{}", err);
+    }
+}
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use std::collections::{BTreeMap, HashMap};
 
 #[allow(non_camel_case_types)]
@@ -187,6 +187,38 @@ pub fn exception_stack_realign(regs: &HashMap<ARMRegister, u32>) -> u32 {
     0
 }
 
+//
+// On exception entry, the CPU loads LR with an EXC_RETURN value rather than
+// a return address.  EXC_RETURN values have all of their upper bits set;
+// bit 2 indicates that the exception was taken from the process stack (that
+// is, from a task) rather than the main stack, and bit 4 is clear if the
+// exception frame includes floating point state.
+//
+pub fn is_exc_return(lr: u32) -> bool {
+    lr & 0xff00_0000 == 0xff00_0000
+}
+
+pub fn exc_return_process_stack(exc_return: u32) -> bool {
+    exc_return & (1 << 2) != 0
+}
+
+//
+// Returns the size (in bytes) of the exception frame that the CPU pushed for
+// the specified EXC_RETURN value:  R0-R3, R12, LR, PC and the PSR -- and, if
+// the frame is an extended one, S0-S15, the FPSCR and a reserved word.  Any
+// realignment (see `exception_stack_realign`) is not included.
+//
+pub fn exception_frame_size(exc_return: u32) -> u32 {
+    const NREGS_CORE: u32 = 8;
+    const NREGS_FP: u32 = 18;
+
+    if exc_return & (1 << 4) == 0 {
+        (NREGS_CORE + NREGS_FP) * 4
+    } else {
+        NREGS_CORE * 4
+    }
+}
+
 pub fn unhalted_read_regions() -> BTreeMap<u32, u32> {
     let mut map = BTreeMap::new();
 
@@ -201,6 +233,87 @@ pub fn unhalted_read_regions() -> BTreeMap<u32, u32> {
     map
 }
 
+//
+// Device memory isn't included in a dump by default, but small windows of it
+// can be captured on request:  the parts of the PPB that describe exception,
+// interrupt and trace state (SCB, NVIC and DWT, respectively) and -- on chips
+// that we know about -- the clock and GPIO configuration.  All of these are
+// word-aligned and can be read without side-effects.  (In particular, we
+// don't capture SysTick:  reading its control and status register clears
+// its COUNTFLAG, changing the state of the target.)  The chip is the one
+// that the archive was built for (see `HubrisArchive::chip`) rather than the
+// one specified to attach, which defaults to an STM32F4 whatever the target;
+// if the archive doesn't name its chip, we offer only the PPB windows.  The
+// windows are named (and returned in name order) so they can be selected by
+// name.
+//
+pub fn dump_mmio_windows(
+    chip: Option<&str>,
+) -> BTreeMap<&'static str, Vec<(u32, u32)>> {
+    let mut map = BTreeMap::new();
+
+    //
+    // The SCB proper starts at 0xe000_ed00, but we also want ICTR (which
+    // indicates the number of interrupt lines and precedes the NVIC).
+    //
+    map.insert("scb", vec![(0xe000_e004, 0x4), (0xe000_ed00, 0x90)]);
+    map.insert("nvic", vec![(0xe000_e100, 0x400)]);
+    map.insert("dwt", vec![(0xe000_1000, 0x60)]);
+
+    let chip = chip.unwrap_or_default().to_uppercase();
+
+    if chip.starts_with("STM32H7") {
+        map.insert("rcc", vec![(0x5802_4400, 0x400)]);
+        map.insert("gpio", vec![(0x5802_0000, 0x2c00)]);
+    } else if chip.starts_with("STM32F4") {
+        map.insert("rcc", vec![(0x4002_3800, 0x400)]);
+        map.insert("gpio", vec![(0x4002_0000, 0x2c00)]);
+    }
+
+    map
+}
+
+///
+/// Returns the device memory windows to capture in a dump, given a list of
+/// windows that are either named (see [`dump_mmio_windows`]) or specified as
+/// an address and size (i.e., `address:size`).
+///
+pub fn dump_mmio(
+    chip: Option<&str>,
+    mmio: &[String],
+) -> Result<Vec<(u32, u32)>> {
+    let named = dump_mmio_windows(chip);
+    let mut rval = vec![];
+
+    for window in mmio {
+        if let Some(windows) = named.get(window.as_str()) {
+            rval.extend_from_slice(windows);
+            continue;
+        }
+
+        let (addr, size) = match window.split_once(':') {
+            Some((addr, size)) => (addr, size),
+            None => {
+                bail!(
+                    "unknown MMIO window \"{}\"; expected one of {} \
+                    or address:size",
+                    window,
+                    named.keys().copied().collect::<Vec<_>>().join(", ")
+                );
+            }
+        };
+
+        let parse = |val: &str| {
+            parse_int::parse::<u32>(val)
+                .map_err(|_| anyhow!("invalid MMIO window \"{}\"", window))
+        };
+
+        rval.push((parse(addr)?, parse(size)?));
+    }
+
+    Ok(rval)
+}
+
 impl std::fmt::Display for ARMRegister {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         formatter.pad(&format!("{:?}", self))
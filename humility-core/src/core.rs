@@ -853,6 +853,22 @@ impl DumpCore {
 
         Ok(Self { contents, regions, registers: hubris.dump_registers() })
     }
+
+    //
+    // Device memory is only in a dump if it was explicitly captured (via
+    // `humility dump --mmio`); if a read of device memory misses, we want
+    // to say as much rather than simply calling the address invalid.
+    //
+    fn check_device(&self, addr: u32) -> Result<()> {
+        if addr >= 0x4000_0000 && !(0x6000_0000..0xa000_0000).contains(&addr) {
+            bail!(
+                "0x{:x} is device memory that was not captured in the dump",
+                addr
+            );
+        }
+
+        Ok(())
+    }
 }
 
 #[rustfmt::skip::macros(bail)]
@@ -882,6 +898,8 @@ impl Core for DumpCore {
                 ));
             }
         }
+
+        self.check_device(addr)?;
         bail!("read from invalid address: 0x{:x}", addr);
     }
 
@@ -908,6 +926,7 @@ impl Core for DumpCore {
             }
         }
 
+        self.check_device(addr)?;
         bail!("read of {} bytes from invalid address: 0x{:x}", rsize, addr);
     }
 
@@ -5,7 +5,7 @@
 use crate::arch::{presyscall_pushes, ARMRegister};
 use capstone::prelude::*;
 use indexmap::IndexMap;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::io::prelude::*;
 
 use std::borrow::Cow;
@@ -42,6 +42,7 @@ pub struct HubrisManifest {
     features: Vec<String>,
     board: Option<String>,
     target: Option<String>,
+    chip: Option<String>,
     task_features: HashMap<String, Vec<String>>,
     pub task_irqs: HashMap<String, Vec<(u32, u32)>>,
     peripherals: BTreeMap<String, u32>,
@@ -87,10 +88,17 @@ struct HubrisConfigPeripheral {
     interrupts: Option<IndexMap<String, u32>>,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+struct HubrisConfigI2cMux {
+    driver: String,
+    address: u8,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 struct HubrisConfigI2cPort {
     name: Option<String>,
     description: Option<String>,
+    muxes: Option<Vec<HubrisConfigI2cMux>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -155,6 +163,13 @@ pub struct HubrisI2cPort {
     pub index: u8,
 }
 
+#[derive(Clone, Debug)]
+pub struct HubrisI2cMux {
+    pub id: u8,
+    pub driver: String,
+    pub address: u8,
+}
+
 #[derive(Clone, Debug)]
 pub struct HubrisI2cBus {
     pub controller: u8,
@@ -162,6 +177,7 @@ pub struct HubrisI2cBus {
     pub name: Option<String>,
     pub description: Option<String>,
     pub target: bool,
+    pub muxes: Vec<HubrisI2cMux>,
 }
 
 #[derive(Clone, Debug)]
@@ -1981,6 +1997,21 @@ impl HubrisArchive {
                         name: port.name.as_ref().cloned(),
                         description: port.description.as_ref().cloned(),
                         target: controller.target.unwrap_or(false),
+                        //
+                        // Muxes are identified by their (1-based) position
+                        // in the port's list of muxes.
+                        //
+                        muxes: port
+                            .muxes
+                            .iter()
+                            .flatten()
+                            .enumerate()
+                            .map(|(ndx, mux)| HubrisI2cMux {
+                                id: ndx as u8 + 1,
+                                driver: mux.driver.clone(),
+                                address: mux.address,
+                            })
+                            .collect(),
                     });
                 }
             }
@@ -2122,6 +2153,17 @@ impl HubrisArchive {
     ) -> Result<()> {
         self.manifest.board = Some(config.board.clone());
         self.manifest.target = Some(config.target.clone());
+
+        //
+        // As with finding the chip TOML, we want only the chip's basename
+        // (e.g., "stm32h7").
+        //
+        self.manifest.chip = config
+            .chip
+            .as_ref()
+            .and_then(|chip| chip.rsplit('/').next())
+            .map(String::from);
+
         self.manifest.features = config.kernel.features.clone();
 
         let mut named_interrupts = HashMap::new();
@@ -2471,6 +2513,26 @@ impl HubrisArchive {
         }
     }
 
+    ///
+    /// Looks up an array type by its name, e.g. `[u8; 16]`.  Array types
+    /// are anonymous, so this must generate the name of each in turn; it
+    /// should not be used in a hot path.
+    ///
+    pub fn lookup_array_byname(&self, name: &str) -> Result<HubrisGoff> {
+        let strip = |s: &str| s.split_whitespace().collect::<String>();
+        let name = strip(name);
+
+        for (goff, array) in &self.arrays {
+            if let Ok(n) = HubrisType::Array(array).name(self) {
+                if strip(&n) == name {
+                    return Ok(*goff);
+                }
+            }
+        }
+
+        Err(anyhow!("expected array {} not found", name))
+    }
+
     pub fn lookup_type(&self, goff: HubrisGoff) -> Result<HubrisType> {
         let r = self
             .lookup_struct(goff)
@@ -2531,6 +2593,35 @@ impl HubrisArchive {
         }
     }
 
+    ///
+    /// Adds type information directly rather than from DWARF, allowing code
+    /// that is driven by type information to be tested without an archive.
+    ///
+    pub fn add_basetype(&mut self, goff: HubrisGoff, ty: HubrisBasetype) {
+        self.basetypes.insert(goff, ty);
+    }
+
+    pub fn add_ptrtype(
+        &mut self,
+        goff: HubrisGoff,
+        name: &str,
+        dest: HubrisGoff,
+    ) {
+        self.ptrtypes.insert(goff, (name.to_string(), dest));
+    }
+
+    pub fn add_struct(&mut self, ty: HubrisStruct) {
+        self.structs.insert(ty.goff, ty);
+    }
+
+    pub fn add_enum(&mut self, ty: HubrisEnum) {
+        self.enums.insert(ty.goff, ty);
+    }
+
+    pub fn add_array(&mut self, goff: HubrisGoff, ty: HubrisArray) {
+        self.arrays.insert(goff, ty);
+    }
+
     ///
     /// Looks up the specified symbol.  This is more of a convenience routine
     /// that turns an Option into a Result.
@@ -3068,6 +3159,179 @@ impl HubrisArchive {
         Ok(rval)
     }
 
+    ///
+    /// Returns the task that was running when the target was halted (or when
+    /// the dump was taken), if any.  If the target was in handler mode (that
+    /// is, in the kernel or in an ISR) this is the kernel, not the task that
+    /// was last scheduled:  the task's registers are saved rather than live.
+    ///
+    pub fn current_task(
+        &self,
+        core: &mut dyn crate::core::Core,
+    ) -> Result<Option<HubrisTask>> {
+        const IPSR_MASK: u32 = 0x1ff;
+
+        if core.read_reg(ARMRegister::PSR)? & IPSR_MASK != 0 {
+            return Ok(Some(HubrisTask::Kernel));
+        }
+
+        let base =
+            core.read_word_32(self.lookup_symword("TASK_TABLE_BASE")?)?;
+        let cur =
+            core.read_word_32(self.lookup_symword("CURRENT_TASK_PTR")?)?;
+        let task = self.lookup_struct_byname("Task")?;
+
+        if cur < base || (cur - base) % task.size as u32 != 0 {
+            return Ok(None);
+        }
+
+        let ndx = (cur - base) / task.size as u32;
+
+        if ndx as usize >= self.ntasks() {
+            return Ok(None);
+        }
+
+        Ok(Some(HubrisTask::Task(ndx)))
+    }
+
+    ///
+    /// Returns the specified task as a thread:  its register state and the
+    /// information needed to unwind its stack.  This works on both live
+    /// systems and dumps.
+    ///
+    pub fn thread(
+        &self,
+        core: &mut dyn crate::core::Core,
+        task: HubrisTask,
+    ) -> Result<HubrisThread> {
+        let ndx = match task {
+            HubrisTask::Task(ndx) => ndx,
+            _ => {
+                bail!("must provide a user task")
+            }
+        };
+
+        let base =
+            core.read_word_32(self.lookup_symword("TASK_TABLE_BASE")?)?;
+
+        let task_t = self.lookup_struct_byname("Task")?;
+        let desc_t = self.lookup_struct_byname("TaskDesc")?;
+
+        let descriptor = task_t.lookup_member("descriptor")?.offset as u32;
+        let initial_stack =
+            desc_t.lookup_member("initial_stack")?.offset as u32;
+
+        let daddr =
+            core.read_word_32(base + ndx * task_t.size as u32 + descriptor)?;
+
+        Ok(HubrisThread {
+            task,
+            name: &self.lookup_module(task)?.name,
+            initial_stack: core.read_word_32(daddr + initial_stack)?,
+            registers: self.registers(core, task)?,
+        })
+    }
+
+    ///
+    /// Returns every task as a thread; see [`HubrisArchive::thread`].
+    ///
+    pub fn threads(
+        &self,
+        core: &mut dyn crate::core::Core,
+    ) -> Result<Vec<HubrisThread>> {
+        (0..self.ntasks())
+            .map(|ndx| self.thread(core, HubrisTask::Task(ndx as u32)))
+            .collect()
+    }
+
+    ///
+    /// Returns the kernel as a thread:  the registers of the processor as of
+    /// the halt (or the dump), with the stack pointer being the main stack
+    /// pointer and the initial stack being the top of the main stack.  This
+    /// fails if the processor was not executing in the kernel.
+    ///
+    pub fn kernel_thread(
+        &self,
+        core: &mut dyn crate::core::Core,
+    ) -> Result<HubrisThread> {
+        let mut regs = HashMap::new();
+
+        for i in 0..=31 {
+            if let Some(reg) = ARMRegister::from_u16(i) {
+                regs.insert(reg, core.read_reg(reg)?);
+            }
+        }
+
+        let pc = regs[&ARMRegister::PC];
+
+        let kernel = match self.modules.range(..=pc).next_back() {
+            Some((base, module)) => {
+                pc < *base + module.textsize
+                    && module.task == HubrisTask::Kernel
+            }
+            None => false,
+        };
+
+        if !kernel {
+            bail!(
+                "PC 0x{:x} is in {}, not the kernel",
+                pc,
+                self.instr_mod(pc).unwrap_or("<unknown>")
+            );
+        }
+
+        regs.insert(ARMRegister::SP, regs[&ARMRegister::MSP]);
+
+        //
+        // The top of the main stack is the initial stack pointer, which is
+        // the word in the vector table that precedes the reset vector.
+        //
+        let reset = match self.esyms_byname.get("__RESET_VECTOR") {
+            Some(sym) => sym.0,
+            None => bail!("couldn't find vector table"),
+        };
+
+        Ok(HubrisThread {
+            task: HubrisTask::Kernel,
+            name: &self.lookup_module(HubrisTask::Kernel)?.name,
+            initial_stack: core.read_word_32(reset - 4)?,
+            registers: regs,
+        })
+    }
+
+    ///
+    /// Returns the message with which the kernel panicked, if it has
+    /// panicked (and if it records such a message).
+    ///
+    pub fn kernel_epitaph(
+        &self,
+        core: &mut dyn crate::core::Core,
+    ) -> Result<Option<String>> {
+        let failed = match self.lookup_variable("KERNEL_HAS_FAILED") {
+            Ok(failed) => failed,
+            Err(_) => return Ok(None),
+        };
+
+        let mut buf = [0u8; 1];
+        core.read_8(failed.addr, &mut buf)?;
+
+        if buf[0] == 0 {
+            return Ok(None);
+        }
+
+        let epitaph = match self.lookup_variable("KERNEL_EPITAPH") {
+            Ok(epitaph) => epitaph,
+            Err(_) => return Ok(Some(String::new())),
+        };
+
+        let mut buf = vec![0u8; epitaph.size];
+        core.read_8(epitaph.addr, &mut buf)?;
+
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+
+        Ok(Some(String::from_utf8_lossy(&buf[..len]).to_string()))
+    }
+
     pub fn stack(
         &self,
         core: &mut dyn crate::core::Core,
@@ -3216,12 +3480,56 @@ impl HubrisArchive {
                 registers: frameregs.clone(),
             });
 
+            let lr = *frameregs.get(&ARMRegister::LR).unwrap();
+
             //
-            // Get our LR, and make sure that the low (Thumb) bit is clear
+            // If our LR is an EXC_RETURN value, this frame was entered via an
+            // exception.  If the exception was taken from a task, there is
+            // nothing further to unwind on this stack; if it was taken on the
+            // main stack (that is, from the kernel), the CPU pushed an
+            // exception frame at our CFA from which we can recover the
+            // registers as of the exception -- and continue unwinding.
             //
-            let lr = *frameregs.get(&ARMRegister::LR).unwrap() & !1;
+            if crate::arch::is_exc_return(lr) {
+                if crate::arch::exc_return_process_stack(lr) {
+                    break;
+                }
+
+                let pushed = [
+                    ARMRegister::R0,
+                    ARMRegister::R1,
+                    ARMRegister::R2,
+                    ARMRegister::R3,
+                    ARMRegister::R12,
+                    ARMRegister::LR,
+                    ARMRegister::PC,
+                    ARMRegister::PSR,
+                ];
+
+                for (i, reg) in pushed.into_iter().enumerate() {
+                    frameregs.insert(reg, readval(cfa + (i * 4) as u32)?);
+                }
+
+                let pc = *frameregs.get(&ARMRegister::PC).unwrap() & !1;
+                let sp = cfa
+                    + crate::arch::exception_frame_size(lr)
+                    + crate::arch::exception_stack_realign(&frameregs);
+
+                frameregs.insert(ARMRegister::PC, pc);
+                frameregs.insert(ARMRegister::SP, sp);
+
+                if sp >= limit {
+                    break;
+                }
+
+                prev = Some(cfa);
+                continue;
+            }
 
-            frameregs.insert(ARMRegister::PC, lr);
+            //
+            // Make sure that the low (Thumb) bit of our LR is clear
+            //
+            frameregs.insert(ARMRegister::PC, lr & !1);
 
             if cfa >= limit {
                 break;
@@ -3537,19 +3845,64 @@ impl HubrisArchive {
         )
     }
 
+    ///
+    /// Writes a dump of the (halted) target as an ELF core file.  In addition
+    /// to the non-device memory regions, any specified windows of device
+    /// memory (as base and size) are read a word at a time and included in
+    /// the dump; windows that can't be read are skipped.
+    ///
     pub fn dump(
         &self,
         core: &mut dyn crate::core::Core,
         dumpfile: Option<&str>,
+        mmio: &[(u32, u32)],
     ) -> Result<()> {
         use indicatif::{HumanBytes, HumanDuration};
         use indicatif::{ProgressBar, ProgressStyle};
         use std::io::Write;
 
         let regions = self.regions(core)?;
+        let mut windows: Vec<(u32, Vec<u8>)> = vec![];
+
+        for &(base, size) in mmio {
+            if base & 0b11 != 0 || size & 0b11 != 0 || size == 0 {
+                bail!("MMIO window 0x{:x} ({} bytes) is misaligned", base, size);
+            }
+
+            let end = base as u64 + size as u64;
+
+            let overlaps = |b: u32, s: u32| {
+                (base as u64) < b as u64 + s as u64 && (b as u64) < end
+            };
+
+            if regions
+                .values()
+                .any(|r| !r.attr.device && overlaps(r.base, r.size))
+                || windows.iter().any(|(b, c)| overlaps(*b, c.len() as u32))
+            {
+                bail!("MMIO window 0x{:x} ({} bytes) overlaps", base, size);
+            }
+
+            let mut contents = Vec::with_capacity(size as usize);
+
+            let rval = (base..end as u32).step_by(4).try_for_each(|addr| {
+                let val = core.read_word_32(addr)?;
+                contents.extend_from_slice(&val.to_le_bytes());
+                Ok::<(), anyhow::Error>(())
+            });
+
+            match rval {
+                Ok(_) => windows.push((base, contents)),
+                Err(e) => {
+                    crate::msg!("skipping MMIO window at 0x{:x}: {}", base, e);
+                }
+            }
+        }
+
         let nsegs = regions
             .values()
-            .fold(0, |ttl, r| ttl + if !r.attr.device { 1 } else { 0 });
+            .fold(0, |ttl, r| ttl + if !r.attr.device { 1 } else { 0 })
+            + windows.len();
 
         macro_rules! pad {
             ($size:expr) => {
@@ -3681,6 +4034,30 @@ impl HubrisArchive {
             offset += region.size + pad!(region.size);
             total += region.size;
         }
+
+        //
+        // Our MMIO windows follow our memory regions; they are always a
+        // multiple of four bytes, so there is no padding to account for.
+        //
+        for (base, contents) in &windows {
+            let size = contents.len() as u32;
+
+            let seg_phdr = goblin::elf32::program_header::ProgramHeader {
+                p_type: goblin::elf::program_header::PT_LOAD,
+                p_flags: goblin::elf::program_header::PF_R,
+                p_offset: offset,
+                p_vaddr: *base,
+                p_filesz: size,
+                p_memsz: size,
+                ..Default::default()
+            };
+
+            bytes.pwrite_with(seg_phdr, 0, ctx.le)?;
+            file.write_all(&bytes)?;
+
+            offset += size;
+        }
+
         for note in &notes {
             //
             // Now write our note section, starting with our note header...
@@ -3760,6 +4137,11 @@ impl HubrisArchive {
             file.write_all(&pad[0..npad])?;
         }
 
+        for (_, contents) in &windows {
+            file.write_all(contents)?;
+            written += contents.len();
+        }
+
         bar.finish_and_clear();
 
         crate::msg!(
@@ -3992,6 +4374,14 @@ impl HubrisArchive {
         Ok(rval)
     }
 
+    ///
+    /// Returns the chip that the archive was built for (e.g., "stm32h7"),
+    /// if the archive indicates it.
+    ///
+    pub fn chip(&self) -> Option<&str> {
+        self.manifest.chip.as_deref()
+    }
+
     pub fn lookup_peripheral(&self, name: &str) -> Result<u32> {
         ensure!(
             !self.modules.is_empty(),
@@ -4172,7 +4562,18 @@ impl From<HubrisGoff> for HubrisTask {
 ///
 /// An identifier that corresponds to a global offset within a particular DWARF
 /// object.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Copy,
+    Clone,
+    Serialize,
+    Deserialize,
+)]
 pub struct HubrisGoff {
     pub object: u32,
     pub goff: usize,
@@ -4560,6 +4961,30 @@ pub enum HubrisTarget {
     Return,
 }
 
+///
+/// A task viewed as a thread of execution.  The registers are those of the
+/// task as of its last entry into the kernel -- or, if the task was running
+/// at user-level when the target was halted, its live registers.
+///
+#[derive(Clone, Debug)]
+pub struct HubrisThread<'a> {
+    pub task: HubrisTask,
+    pub name: &'a str,
+    pub initial_stack: u32,
+    pub registers: HashMap<ARMRegister, u32>,
+}
+
+impl<'a> HubrisThread<'a> {
+    /// Unwinds the thread's stack, returning its frames
+    pub fn stack(
+        &self,
+        hubris: &'a HubrisArchive,
+        core: &mut dyn crate::core::Core,
+    ) -> Result<Vec<HubrisStackFrame<'a>>> {
+        hubris.stack(core, self.task, self.initial_stack, &self.registers)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct HubrisStackFrame<'a> {
     pub cfa: u32,
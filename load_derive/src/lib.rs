@@ -2,192 +2,393 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use quote::quote_spanned;
+//! Derive macro for `humility_cmd::reflect::Load`.
+//!
+//! The derived impl can be adjusted with `#[load(...)]` attributes:
+//!
+//! - `#[load(rename = "name")]` on a field or an enum variant matches the
+//!   member or variant of that name in the program, rather than the name of
+//!   the field or variant itself;
+//! - `#[load(default)]` on a field uses `Default::default()` if the member is
+//!   absent from the program (e.g., because it was added in a later version);
+//!   in a tuple struct or tuple variant, only trailing fields can be default;
+//! - `#[load(untagged)]` on an enum matches the value itself against the
+//!   contents of each variant in turn, rather than matching the program's
+//!   variant by name.  This allows for a type whose representation has
+//!   changed over time to be expressed as an enum of its representations.
+//!
+//! Type parameters are required to implement `Load`.
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
 use syn::spanned::Spanned;
 
-#[proc_macro_derive(Load)]
+#[proc_macro_derive(Load, attributes(load))]
 pub fn load_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
-    let ts = match &input.data {
-        syn::Data::Struct(data) => match &data.fields {
-            syn::Fields::Named(fields) => {
-                gen_named_struct(&input.ident, fields)
+    let ts = match gen_load(&input) {
+        Ok(ts) => ts,
+        Err(err) => err.to_compile_error(),
+    };
+
+    proc_macro::TokenStream::from(ts)
+}
+
+#[derive(Default)]
+struct LoadAttrs {
+    rename: Option<String>,
+    default: bool,
+    untagged: bool,
+}
+
+//
+// Parses any `#[load(...)]` attributes, failing if any of them are not among
+// those allowed in this position.
+//
+fn parse_attrs(
+    attrs: &[syn::Attribute],
+    allowed: &[&str],
+) -> syn::Result<LoadAttrs> {
+    let mut rval = LoadAttrs::default();
+
+    for attr in attrs.iter().filter(|a| a.path.is_ident("load")) {
+        let list = match attr.parse_meta()? {
+            syn::Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    "expected #[load(...)]",
+                ));
             }
-            syn::Fields::Unnamed(fields) => {
-                gen_unnamed_struct(&input.ident, fields)
+        };
+
+        for nested in &list.nested {
+            let meta = match nested {
+                syn::NestedMeta::Meta(meta) => meta,
+                syn::NestedMeta::Lit(lit) => {
+                    return Err(syn::Error::new(
+                        lit.span(),
+                        "unexpected literal in #[load(...)]",
+                    ));
+                }
+            };
+
+            let name = meta
+                .path()
+                .get_ident()
+                .map(|i| i.to_string())
+                .unwrap_or_default();
+
+            if !allowed.contains(&name.as_str()) {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    format!(
+                        "load attribute not allowed here; expected one of: {}",
+                        allowed.join(", ")
+                    ),
+                ));
             }
-            syn::Fields::Unit => {
-                unimplemented!(
-                    "unit struct not implemented as DWARF rep \
+
+            match (name.as_str(), meta) {
+                ("rename", syn::Meta::NameValue(nv)) => match &nv.lit {
+                    syn::Lit::Str(s) => rval.rename = Some(s.value()),
+                    lit => {
+                        return Err(syn::Error::new(
+                            lit.span(),
+                            "expected string for rename",
+                        ));
+                    }
+                },
+                ("default", syn::Meta::Path(_)) => rval.default = true,
+                ("untagged", syn::Meta::Path(_)) => rval.untagged = true,
+                _ => {
+                    return Err(syn::Error::new(
+                        meta.span(),
+                        format!("malformed load attribute {}", name),
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(rval)
+}
+
+fn gen_load(input: &syn::DeriveInput) -> syn::Result<TokenStream> {
+    let ident = &input.ident;
+
+    let mut attrs = quote!();
+
+    let body = match &input.data {
+        syn::Data::Struct(data) => {
+            parse_attrs(&input.attrs, &[])?;
+
+            match &data.fields {
+                syn::Fields::Named(fields) => {
+                    gen_named(ident, fields, quote!(Self))?
+                }
+                syn::Fields::Unnamed(fields) => {
+                    gen_unnamed(ident, fields, quote!(Self))?
+                }
+                syn::Fields::Unit => {
+                    unimplemented!(
+                        "unit struct not implemented as DWARF rep \
                                     not clear at time of writing"
-                );
+                    );
+                }
+            }
+        }
+        syn::Data::Enum(data) => {
+            let load = parse_attrs(&input.attrs, &["untagged"])?;
+
+            if load.untagged {
+                //
+                // Each variant is attempted via a closure that is called
+                // but once.
+                //
+                attrs = quote!(#[allow(clippy::redundant_closure_call)]);
+                gen_untagged_enum(ident, data)?
+            } else {
+                gen_enum(ident, data)?
             }
-        },
-        syn::Data::Enum(data) => gen_enum(&input.ident, data),
+        }
         _ => unimplemented!("unsupported type for derive"),
     };
 
-    proc_macro::TokenStream::from(ts)
+    //
+    // Every type parameter must itself be loadable.
+    //
+    let mut generics = input.generics.clone();
+
+    for param in generics.type_params_mut() {
+        param.bounds.push(syn::parse_quote!(crate::reflect::Load));
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote_spanned!(ident.span()=>
+        impl #impl_generics crate::reflect::Load for #ident #ty_generics
+            #where_clause
+        {
+            #attrs
+            fn from_value(v: &crate::reflect::Value) -> anyhow::Result<Self> {
+                #body
+            }
+        }
+    ))
 }
 
 fn gen_enum(
     ident: &syn::Ident,
     data: &syn::DataEnum,
-) -> proc_macro2::TokenStream {
+) -> syn::Result<TokenStream> {
     let mut arms = vec![];
+
     for variant in &data.variants {
         let var_ident = &variant.ident;
+        let attrs = parse_attrs(&variant.attrs, &["rename"])?;
+        let name = attrs.rename.unwrap_or_else(|| var_ident.to_string());
+
         match &variant.fields {
             syn::Fields::Unit => {
                 arms.push(quote_spanned!(variant.ident.span()=>
-                    (stringify!(#var_ident), _) => Ok(Self::#var_ident),
+                    (#name, _) => Ok(Self::#var_ident),
                 ));
             }
             syn::Fields::Unnamed(fields) => {
-                let len = fields.unnamed.len();
-                let field_lets = fields.unnamed.iter().enumerate().map(|(i, fld)| {
-                    let name = syn::Ident::new(&format!("field_{}", i), fld.span());
-                    let fty = &fld.ty;
-                    quote_spanned!(fld.span()=>
-                        let #name: #fty = crate::reflect::Load::from_value(&contents[#i])?;
-                    )
-                });
-                let field_lets =
-                    field_lets.collect::<proc_macro2::TokenStream>();
-
-                let field_uses =
-                    fields.unnamed.iter().enumerate().map(|(i, fld)| {
-                        let name = syn::Ident::new(
-                            &format!("field_{}", i),
-                            fld.span(),
-                        );
-                        quote_spanned!(fld.span()=> #name,)
-                    });
-                let field_uses =
-                    field_uses.collect::<proc_macro2::TokenStream>();
+                let body =
+                    gen_unnamed(ident, fields, quote!(Self::#var_ident))?;
 
                 arms.push(quote_spanned!(variant.ident.span()=>
-                    (stringify!(#var_ident), Some(contents)) => {
-                        let contents = contents.as_tuple()?;
-                        if contents.len() != #len {
-                            anyhow::bail!("wrong tuple size for {}: {:?}",
-                                stringify!(#ident), contents);
-                        }
-                        #field_lets
-                        Ok(Self::#var_ident(#field_uses))
-                    }
+                    (#name, Some(v)) => { #body }
                 ));
             }
             syn::Fields::Named(fields) => {
-                let field_name_strs = fields.named.iter().map(|fld| {
-                    let name = fld.ident.as_ref().unwrap();
-                    quote_spanned!(fld.span()=> stringify!(#name), )
-                });
-                let field_name_strs =
-                    field_name_strs.collect::<proc_macro2::TokenStream>();
-
-                let field_defs = fields.named.iter().map(|fld| {
-                    let name = &fld.ident;
-                    quote_spanned!(fld.span()=>
-                        #name: crate::reflect::Load::from_value(&contents[stringify!(#name)])?,
-                    )
-                });
-                let field_defs =
-                    field_defs.collect::<proc_macro2::TokenStream>();
+                let body = gen_named(ident, fields, quote!(Self::#var_ident))?;
 
                 arms.push(quote_spanned!(variant.ident.span()=>
-                    (stringify!(#var_ident), Some(contents)) => {
-                        let contents = contents.as_struct()?;
-                        contents.check_members(&[#field_name_strs])?;
-
-                        Ok(Self::#var_ident {
-                            #field_defs
-                        })
-                    }
+                    (#name, Some(v)) => { #body }
                 ));
             }
         }
     }
 
-    let arms = arms.into_iter().collect::<proc_macro2::TokenStream>();
+    let arms = arms.into_iter().collect::<TokenStream>();
 
-    quote_spanned!(ident.span() =>
-        impl crate::reflect::Load for #ident {
-            fn from_value(v: &crate::reflect::Value) -> anyhow::Result<Self> {
-                let v = v.as_enum()?;
-                match (v.disc(), v.contents()) {
-                    #arms
-                    _ => anyhow::bail!("unexpected shape for {}: {:?}",
-                        stringify!(#ident), v),
-                }
-            }
+    Ok(quote_spanned!(ident.span()=>
+        let e = v.as_enum()?;
+        match (e.disc(), e.contents()) {
+            #arms
+            _ => anyhow::bail!("unexpected shape for {}: {:?}",
+                stringify!(#ident), e),
         }
-    )
+    ))
 }
 
-fn gen_named_struct(
+//
+// For an untagged enum, we try each variant in the order declared, taking
+// the first whose contents can be loaded from the value.  (As with serde, a
+// variant with a single unnamed field is loaded from the value directly, not
+// from a 1-tuple.)
+//
+fn gen_untagged_enum(
+    ident: &syn::Ident,
+    data: &syn::DataEnum,
+) -> syn::Result<TokenStream> {
+    let mut attempts = vec![];
+
+    for variant in &data.variants {
+        let var_ident = &variant.ident;
+        parse_attrs(&variant.attrs, &[])?;
+
+        let body = match &variant.fields {
+            syn::Fields::Unit => {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "unit variants cannot be loaded in an untagged enum",
+                ));
+            }
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let fld = &fields.unnamed[0];
+                parse_attrs(&fld.attrs, &[])?;
+
+                quote_spanned!(fld.span()=>
+                    Ok(Self::#var_ident(crate::reflect::Load::from_value(v)?))
+                )
+            }
+            syn::Fields::Unnamed(fields) => {
+                gen_unnamed(ident, fields, quote!(Self::#var_ident))?
+            }
+            syn::Fields::Named(fields) => {
+                gen_named(ident, fields, quote!(Self::#var_ident))?
+            }
+        };
+
+        attempts.push(quote_spanned!(variant.ident.span()=>
+            let attempt = || -> anyhow::Result<Self> { #body };
+
+            if let Ok(rval) = attempt() {
+                return Ok(rval);
+            }
+        ));
+    }
+
+    let attempts = attempts.into_iter().collect::<TokenStream>();
+
+    Ok(quote_spanned!(ident.span()=>
+        #attempts
+        anyhow::bail!("unexpected shape for {}: {:?}", stringify!(#ident), v)
+    ))
+}
+
+//
+// Generates the body that loads fields with names from `v`, and constructs
+// them with `ctor`.
+//
+fn gen_named(
     ident: &syn::Ident,
     fields: &syn::FieldsNamed,
-) -> proc_macro2::TokenStream {
-    let field_name_strs = fields.named.iter().map(|fld| {
+    ctor: TokenStream,
+) -> syn::Result<TokenStream> {
+    let mut required = vec![];
+    let mut field_defs = vec![];
+
+    for fld in &fields.named {
         let name = fld.ident.as_ref().unwrap();
-        quote_spanned!(fld.span()=> stringify!(#name), )
-    });
-    let field_name_strs = field_name_strs.collect::<proc_macro2::TokenStream>();
-
-    let field_defs = fields.named.iter().map(|fld| {
-        let name = &fld.ident;
-        quote_spanned!(fld.span()=>
-            #name: crate::reflect::Load::from_value(&v[stringify!(#name)])?,
-        )
-    });
-    let field_defs = field_defs.collect::<proc_macro2::TokenStream>();
-
-    quote_spanned!(ident.span()=>
-        impl crate::reflect::Load for #ident {
-            fn from_value(v: &crate::reflect::Value) -> anyhow::Result<Self> {
-                let v = v.as_struct()?;
-                v.check_members(&[#field_name_strs])?;
-                Ok(Self {
-                    #field_defs
-                })
-            }
+        let attrs = parse_attrs(&fld.attrs, &["rename", "default"])?;
+        let member = attrs.rename.unwrap_or_else(|| name.to_string());
+
+        if attrs.default {
+            field_defs.push(quote_spanned!(fld.span()=>
+                #name: match v.get(#member) {
+                    Some(m) => crate::reflect::Load::from_value(m)?,
+                    None => Default::default(),
+                },
+            ));
+        } else {
+            required.push(quote_spanned!(fld.span()=> #member, ));
+            field_defs.push(quote_spanned!(fld.span()=>
+                #name: crate::reflect::Load::from_value(&v[#member])?,
+            ));
         }
-    )
+    }
+
+    let required = required.into_iter().collect::<TokenStream>();
+    let field_defs = field_defs.into_iter().collect::<TokenStream>();
+
+    Ok(quote_spanned!(ident.span()=>
+        let v = v.as_struct()?;
+        v.check_members(&[#required])?;
+        Ok(#ctor {
+            #field_defs
+        })
+    ))
 }
 
-fn gen_unnamed_struct(
+//
+// Generates the body that loads unnamed fields from the tuple `v`, and
+// constructs them with `ctor`.  Any default fields must be trailing, and
+// may be absent from the tuple.
+//
+fn gen_unnamed(
     ident: &syn::Ident,
     fields: &syn::FieldsUnnamed,
-) -> proc_macro2::TokenStream {
+    ctor: TokenStream,
+) -> syn::Result<TokenStream> {
     let len = fields.unnamed.len();
-    let field_lets = fields.unnamed.iter().enumerate().map(|(i, fld)| {
-        let name = syn::Ident::new(&format!("field_{}", i), fld.span());
-        let fty = &fld.ty;
-        quote_spanned!(fld.span()=>
-            let #name: #fty = crate::reflect::Load::from_value(&v[#i])?;
-        )
-    });
-    let field_lets = field_lets.collect::<proc_macro2::TokenStream>();
+    let mut required = 0;
+    let mut field_lets = vec![];
+    let mut field_uses = vec![];
 
-    let field_uses = fields.unnamed.iter().enumerate().map(|(i, fld)| {
+    for (i, fld) in fields.unnamed.iter().enumerate() {
         let name = syn::Ident::new(&format!("field_{}", i), fld.span());
-        quote_spanned!(fld.span()=> #name,)
-    });
-    let field_uses = field_uses.collect::<proc_macro2::TokenStream>();
+        let fty = &fld.ty;
+        let attrs = parse_attrs(&fld.attrs, &["default"])?;
 
-    quote_spanned!(ident.span()=>
-        impl crate::reflect::Load for #ident {
-            fn from_value(v: &crate::reflect::Value) -> anyhow::Result<Self> {
-                let v = v.as_tuple()?;
-                if v.len() != #len {
-                    anyhow::bail!("wrong tuple size for {}: {:?}",
-                        stringify!(#ident), v);
-                }
-                #field_lets
-                Ok(Self(#field_uses))
+        if attrs.default {
+            field_lets.push(quote_spanned!(fld.span()=>
+                let #name: #fty = match v.get(#i) {
+                    Some(f) => crate::reflect::Load::from_value(f)?,
+                    None => Default::default(),
+                };
+            ));
+        } else {
+            if required != i {
+                return Err(syn::Error::new(
+                    fld.span(),
+                    "fields following a default field must also be default",
+                ));
             }
+
+            required += 1;
+
+            field_lets.push(quote_spanned!(fld.span()=>
+                let #name: #fty = crate::reflect::Load::from_value(&v[#i])?;
+            ));
+        }
+
+        field_uses.push(quote_spanned!(fld.span()=> #name,));
+    }
+
+    let field_lets = field_lets.into_iter().collect::<TokenStream>();
+    let field_uses = field_uses.into_iter().collect::<TokenStream>();
+
+    let wrong_size = if required == len {
+        quote!(v.len() != #len)
+    } else if required == 0 {
+        quote!(v.len() > #len)
+    } else {
+        quote!(v.len() < #required || v.len() > #len)
+    };
+
+    Ok(quote_spanned!(ident.span()=>
+        let v = v.as_tuple()?;
+        if #wrong_size {
+            anyhow::bail!("wrong tuple size for {}: {:?}",
+                stringify!(#ident), v);
         }
-    )
+        #field_lets
+        Ok(#ctor(#field_uses))
+    ))
 }
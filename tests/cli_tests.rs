@@ -6,3 +6,25 @@
 fn cli_tests() {
     trycmd::TestCases::new().case("tests/cmd/*.trycmd");
 }
+
+//
+// These cases run commands that require a live target against a dump, with
+// the HIF agent emulated by a scripted mock (see humility-cmd/src/mock.rs).
+// The dump is generated with `humility dump` from a booted system whose
+// image includes the jefe, hiffy and sensor tasks, and whose manifest has
+// an unnamed tmp117 (with one temperature sensor) at 0x48 on I2C3.  The
+// cases are only as good as the dump, so a missing dump fails the test
+// rather than passing it vacuously.
+//
+#[test]
+fn mock_tests() {
+    const MOCK_DUMP: &str = "tests/cmd/mock/hubris.core.0";
+
+    assert!(
+        std::path::Path::new(MOCK_DUMP).exists(),
+        "{} is missing; generate it with `humility dump`",
+        MOCK_DUMP
+    );
+
+    trycmd::TestCases::new().case("tests/cmd/mock/*.trycmd");
+}